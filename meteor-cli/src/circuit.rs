@@ -0,0 +1,132 @@
+//! `meteor circuit`, simulating networks of chips described in a file
+
+use std::path::{Path, PathBuf};
+
+use meteor::{
+    parser::src::SourceMap,
+    runtime::{
+        circuit::{Circuit, CircuitError, Description},
+        trace::Trace,
+        Chip,
+    },
+};
+
+use crate::{load, report_module_errors};
+
+/// Files to write the values the outputs took to, the circuit only records if there is one
+pub struct Traces<'a> {
+    pub vcd: Option<&'a Path>,
+    pub json: Option<&'a Path>,
+}
+
+/// Runs a circuit for a number of steps and prints the outputs of its chips, returns whether it
+/// ran without errors
+pub fn run(file: &Path, steps: u64, include: &[PathBuf], traces: &Traces) -> bool {
+    let name = file.to_string_lossy();
+    let Ok(contents) = std::fs::read_to_string(file) else {
+        eprintln!("Failed to open file {name}");
+        return false;
+    };
+
+    let description = match Description::parse(&contents) {
+        Ok(description) => description,
+        Err(err) => {
+            eprintln!("Invalid circuit {name}: {err}");
+            return false;
+        }
+    };
+
+    // chip sources are relative to the description
+    let directory = file.parent().unwrap_or(Path::new(""));
+    let graphs = description
+        .chips
+        .iter()
+        .map(|chip| load(&directory.join(&chip.source), include))
+        .collect::<Vec<_>>();
+    let sources: SourceMap = graphs.iter().flat_map(|graph| graph.sources()).collect();
+
+    let mut failed = false;
+    for graph in &graphs {
+        failed |= report_module_errors(graph, &sources);
+    }
+    if failed {
+        return false;
+    }
+
+    let mut circuit = match description.fuel {
+        Some(budget) => Circuit::new().with_fuel(budget),
+        None => Circuit::new(),
+    };
+    if traces.vcd.is_some() || traces.json.is_some() {
+        circuit = circuit.with_recording();
+    }
+
+    let built = description
+        .chips
+        .iter()
+        .zip(&graphs)
+        .try_for_each(|(chip, graph)| circuit.add(chip.name.as_str(), Chip::new(graph.link())))
+        .and_then(|()| {
+            description
+                .wires
+                .iter()
+                .try_for_each(|wire| circuit.connect(wire.clone()))
+        });
+    if let Err(err) = built {
+        eprintln!("{err}");
+        return false;
+    }
+
+    for chain in circuit.loops() {
+        eprintln!("Wires form a combinational loop {chain}");
+    }
+
+    let result = circuit.run(steps);
+
+    for (name, chip) in circuit.chips() {
+        for output in chip.ports().outputs() {
+            println!("{name}.{} = {}", output.name(), output.value());
+        }
+    }
+
+    // the steps before a chip failed are still worth looking at
+    let written = circuit
+        .trace()
+        .map_or(true, |trace| write_traces(trace, traces));
+
+    match result {
+        Ok(()) => written,
+        Err(CircuitError::Chip { chip, error }) => {
+            eprintln!("Chip {chip} failed at step {}", circuit.steps() + 1);
+            error.write(&sources, std::io::stderr());
+            false
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            false
+        }
+    }
+}
+
+/// Returns whether every file was written
+fn write_traces(trace: &Trace, traces: &Traces) -> bool {
+    let write = |path: &Path, contents: Vec<u8>| {
+        let written = std::fs::write(path, contents).is_ok();
+        if !written {
+            eprintln!("Failed to write file {}", path.display());
+        }
+        written
+    };
+
+    let mut written = true;
+    if let Some(path) = traces.vcd {
+        let mut vcd = Vec::new();
+        trace.write_vcd(&mut vcd).unwrap();
+        written &= write(path, vcd);
+    }
+    if let Some(path) = traces.json {
+        written &= write(path, serde_json::to_vec_pretty(trace).unwrap());
+    }
+
+    written
+}
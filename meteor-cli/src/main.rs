@@ -1,81 +1,442 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use ariadne::{Label, Report, Source};
-use clap::{self, Parser};
-use meteor::{parser::src::SourceId, runtime::Chip, semantic};
+use ariadne::{Label, Report};
+use clap::{self, Parser, Subcommand, ValueEnum};
+use meteor::{
+    config::Config,
+    database::Database,
+    formatter::{self, FormatConfig},
+    module::{ModuleGraph, ModuleLoader},
+    parser::src::{self, SourceId, SourceMap},
+    runtime::{self, fuel::Fuel, Chip},
+    semantic::{self, fixes, Diagnostic},
+};
+
+mod circuit;
+mod sarif;
 
 #[derive(Parser)]
-#[command(version, about, author)]
+#[command(
+    version,
+    about,
+    author,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    #[arg(long, short)]
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(long, short, required = true)]
+    file: Option<PathBuf>,
 
     /// Prints out the AST contents instead of evaluating
     #[arg(long, short)]
     dump_ast: bool,
+
+    /// Prints out the parsed program as canonical source instead of evaluating
+    #[arg(long, short, conflicts_with = "dump_ast")]
+    print: bool,
+
+    /// Directories searched for imported modules
+    #[arg(long, short = 'I')]
+    include: Vec<PathBuf>,
+
+    /// Evaluates constant expressions before running the program
+    #[arg(long)]
+    fold: bool,
+
+    /// Stops the program once it did this much work, it runs until it finishes otherwise
+    #[arg(long)]
+    fuel: Option<u64>,
+
+    /// Calls the program can nest before it stops with a stack overflow, at most 4096
+    #[arg(long, default_value_t = runtime::MAX_DEPTH, value_parser = max_depth)]
+    max_depth: usize,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Subcommand)]
+enum Command {
+    /// Formats source files in place
+    Fmt {
+        files: Vec<PathBuf>,
 
-    let Ok(contents) = std::fs::read_to_string(&args.file) else {
-        eprintln!("Failed to open file {}", args.file.to_string_lossy());
-        return;
+        /// Only checks if the files are formatted, exiting with an error if any are not
+        #[arg(long)]
+        check: bool,
+
+        /// Maximum line width
+        #[arg(long, default_value_t = FormatConfig::default().max_width)]
+        max_width: usize,
+    },
+
+    /// Reports the diagnostics of a file and the modules it imports without running it
+    Check {
+        file: PathBuf,
+
+        /// Applies the suggested fixes, reporting only the diagnostics they do not resolve
+        #[arg(long)]
+        fix: bool,
+
+        /// Directories searched for imported modules
+        #[arg(long, short = 'I')]
+        include: Vec<PathBuf>,
+
+        /// How diagnostics are printed
+        #[arg(long, value_enum, default_value_t = Format::Human)]
+        format: Format,
+    },
+
+    /// Simulates circuits of chips wired together, described in a JSON file
+    Circuit {
+        #[command(subcommand)]
+        command: CircuitCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CircuitCommand {
+    /// Runs a circuit for a number of steps and prints the outputs of its chips
+    Run {
+        file: PathBuf,
+
+        /// Number of steps to run, every chip ticks once in each of them
+        #[arg(long, default_value_t = 1)]
+        steps: u64,
+
+        /// Directories searched for imported modules
+        #[arg(long, short = 'I')]
+        include: Vec<PathBuf>,
+
+        /// Writes the values the outputs took to a Value Change Dump, for waveform viewers
+        #[arg(long)]
+        vcd: Option<PathBuf>,
+
+        /// Writes the values the outputs took to a JSON trace
+        #[arg(long)]
+        trace: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Rendered reports pointing into the source
+    Human,
+
+    /// JSON array of all diagnostics, spans are byte ranges
+    Json,
+
+    /// SARIF 2.1.0 log
+    Sarif,
+}
+
+fn load(file: &Path, include: &[PathBuf]) -> ModuleGraph {
+    include
+        .iter()
+        .fold(ModuleLoader::new(), |loader, path| {
+            loader.with_search_path(path)
+        })
+        .load(file)
+}
+
+/// Prints the errors of loading the modules, returns whether there were any
+fn report_module_errors(modules: &ModuleGraph, sources: &SourceMap) -> bool {
+    for err in modules.errors() {
+        let Some(span) = err.span() else {
+            eprintln!("{err}");
+            continue;
+        };
+
+        Report::build(ariadne::ReportKind::Error, span.clone())
+            .with_config(src::report_config())
+            .with_message(err.reason())
+            .with_label(Label::new(span.clone()).with_message(err.reason()))
+            .finish()
+            .print(sources)
+            .unwrap();
+    }
+
+    !modules.errors().is_empty()
+}
+
+fn report(diagnostic: &Diagnostic, sources: &SourceMap) {
+    let kind = match diagnostic.severity {
+        semantic::Severity::Hint => ariadne::ReportKind::Advice,
+        semantic::Severity::Warning => ariadne::ReportKind::Warning,
+        semantic::Severity::Error => ariadne::ReportKind::Error,
     };
 
-    let prog_ctx = SourceId::new(args.file.to_string_lossy());
+    let mut report = Report::build(kind, diagnostic.span.clone())
+        .with_config(src::report_config())
+        .with_code(diagnostic.kind.code())
+        .with_message(diagnostic.reason())
+        .with_label(Label::new(diagnostic.span.clone()).with_message(diagnostic.reason()))
+        .with_labels(
+            diagnostic
+                .related
+                .iter()
+                .map(|label| Label::new(label.span.clone()).with_message(&label.message)),
+        );
 
-    match meteor::parse(prog_ctx.clone(), &contents) {
-        Ok(ast) => {
-            if args.dump_ast {
-                println!("{}", serde_json::to_string_pretty(&ast).unwrap());
-                return;
-            }
+    // `with_notes` takes the report by reference, unlike the other builder methods
+    report.with_notes(&diagnostic.notes);
 
-            let prog_name = args.file.to_string_lossy();
-
-            for diagnoses in semantic::analyze(&ast).diagnostics {
-                let kind = match diagnoses.severity {
-                    semantic::Severity::Hint => ariadne::ReportKind::Advice,
-                    semantic::Severity::Warning => ariadne::ReportKind::Warning,
-                    semantic::Severity::Error => ariadne::ReportKind::Error,
-                };
-                Report::build(kind, (&prog_name, diagnoses.span.range()))
-                    .with_message(diagnoses.reason())
-                    .with_label(
-                        Label::new((&prog_name, diagnoses.span.range()))
-                            .with_message(diagnoses.reason()),
-                    )
-                    .finish()
-                    .print((&prog_name, Source::from(&contents)))
-                    .unwrap();
+    if let Some(fix) = &diagnostic.fix {
+        report = report.with_help(&fix.message);
+    }
+
+    report.finish().print(sources).unwrap();
+}
+
+/// Analyzes a file along with its imports, returns whether no errors were found
+fn check(file: &Path, include: &[PathBuf], fix: bool, format: Format) -> bool {
+    let modules = load(file, include);
+    let mut sources: SourceMap = modules.sources().collect();
+
+    if report_module_errors(&modules, &sources) {
+        return false;
+    }
+
+    let config = match Config::discover(file) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return false;
+        }
+    };
+
+    let mut db = Database::new();
+    db.set_search_paths(include.to_vec());
+    for module in modules.modules() {
+        db.set_source(module.id(), module.source());
+        db.set_config(module.id(), config.clone());
+    }
+
+    let mut success = true;
+    let mut reported = vec![];
+
+    for module in modules.modules() {
+        let mut diagnostics = db.diagnostics(module.id()).to_vec();
+
+        if fix
+            && diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.fix.is_some())
+        {
+            let (fixed, _) = fixes::apply(
+                module.source(),
+                module.id(),
+                diagnostics
+                    .iter()
+                    .filter_map(|diagnostic| diagnostic.fix.as_ref()),
+            );
+
+            let name = module.path().to_string_lossy();
+            if std::fs::write(module.path(), &fixed).is_err() {
+                eprintln!("Failed to write file {name}");
+                success = false;
+                continue;
             }
+            eprintln!("Fixed {name}");
 
-            let mut chip = Chip::new(ast);
+            // what is left is reported against the fixed file, including fixes overlapping
+            // another one, which are left for the next run
+            sources.insert(module.id(), fixed.as_str());
+            db.set_source(module.id(), fixed);
+            diagnostics = db.diagnostics(module.id()).to_vec();
+        }
+
+        success &= diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity != semantic::Severity::Error);
+        reported.extend(diagnostics);
+    }
 
-            match chip.run() {
-                Ok(value) => {
-                    println!("Exited with value \"{value}\"");
-                }
-                Err(err) => {
-                    err.write((prog_ctx, Source::from(&contents)), std::io::stderr());
-                }
+    match format {
+        Format::Human => {
+            for diagnostic in &reported {
+                report(diagnostic, &sources);
             }
         }
+        Format::Json => println!("{}", serde_json::to_string_pretty(&reported).unwrap()),
+        Format::Sarif => println!(
+            "{}",
+            serde_json::to_string_pretty(&sarif::log(&reported, &sources)).unwrap()
+        ),
+    }
 
-        Err(error) => {
-            let prog_name = args.file.to_string_lossy();
+    success
+}
+
+/// Formats a file, returns whether it already was formatted
+fn format_file(path: &Path, config: &FormatConfig, check: bool) -> Result<bool, ()> {
+    let name = path.to_string_lossy();
 
-            for err in error {
-                Report::build(ariadne::ReportKind::Error, (&prog_name, err.span().range()))
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        eprintln!("Failed to open file {name}");
+        return Err(());
+    };
+
+    let id = SourceId::new(name.clone());
+    let formatted = match formatter::format(id, &contents, config) {
+        Ok(formatted) => formatted,
+        Err(errors) => {
+            let sources = SourceMap::from_iter([(id, contents.as_str())]);
+            for err in errors {
+                Report::build(ariadne::ReportKind::Error, err.span().clone())
+                    .with_config(src::report_config())
                     .with_message(err.reason())
-                    .with_label(
-                        Label::new((&prog_name, err.span().range())).with_message(err.reason()),
-                    )
+                    .with_label(Label::new(err.span().clone()).with_message(err.reason()))
                     .finish()
-                    .print((&prog_name, Source::from(&contents)))
+                    .eprint(&sources)
                     .unwrap();
             }
+            return Err(());
+        }
+    };
+
+    if formatted == contents {
+        return Ok(true);
+    }
+
+    if check {
+        println!("{name} is not formatted");
+    } else if std::fs::write(path, formatted).is_err() {
+        eprintln!("Failed to write file {name}");
+        return Err(());
+    }
+
+    Ok(false)
+}
+
+/// Parses `--max-depth`, rejecting limits deeper than [`runtime::DEPTH_LIMIT`]
+fn max_depth(value: &str) -> Result<usize, String> {
+    let depth = value.parse::<usize>().map_err(|err| err.to_string())?;
+    if depth > runtime::DEPTH_LIMIT {
+        return Err(format!("the maximum depth is {}", runtime::DEPTH_LIMIT));
+    }
+
+    Ok(depth)
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Some(Command::Check {
+        file,
+        fix,
+        include,
+        format,
+    }) = &args.command
+    {
+        if !check(file, include, *fix, *format) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Circuit {
+        command:
+            CircuitCommand::Run {
+                file,
+                steps,
+                include,
+                vcd,
+                trace,
+            },
+    }) = &args.command
+    {
+        let traces = circuit::Traces {
+            vcd: vcd.as_deref(),
+            json: trace.as_deref(),
+        };
+        if !circuit::run(file, *steps, include, &traces) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Fmt {
+        files,
+        check,
+        max_width,
+    }) = args.command
+    {
+        let config = FormatConfig {
+            max_width,
+            ..FormatConfig::default()
+        };
+
+        let mut success = true;
+        for file in &files {
+            match format_file(file, &config, check) {
+                Ok(formatted) => success &= formatted || !check,
+                Err(()) => success = false,
+            }
+        }
+
+        if !success {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let Some(file) = args.file else {
+        return;
+    };
+
+    let modules = load(&file, &args.include);
+
+    let sources: SourceMap = modules.sources().collect();
+
+    if report_module_errors(&modules, &sources) {
+        return;
+    }
+
+    let Some(entry) = modules.entry() else {
+        return;
+    };
+
+    let config = match Config::discover(&file) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return;
+        }
+    };
+
+    if args.dump_ast {
+        println!("{}", serde_json::to_string_pretty(entry.program()).unwrap());
+        return;
+    }
+
+    if args.print {
+        print!("{}", entry.program());
+        return;
+    }
+
+    for module in modules.modules() {
+        for diagnostic in semantic::analyze_with(module.program(), &config).diagnostics {
+            report(&diagnostic, &sources);
+        }
+    }
+
+    let mut program = modules.link();
+    if args.fold {
+        program = semantic::fold::fold(&program);
+    }
+
+    let fuel = args.fuel.map_or_else(Fuel::unlimited, Fuel::new);
+    let mut chip = Chip::new(program)
+        .with_fuel(fuel)
+        .with_max_depth(args.max_depth);
+
+    match chip.run() {
+        Ok(value) => {
+            println!("Exited with value \"{value}\"");
+        }
+        Err(err) => {
+            err.write(&sources, std::io::stderr());
         }
     }
 }
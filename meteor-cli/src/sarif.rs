@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+use meteor::{
+    parser::{span::Span, src::SourceMap},
+    semantic::{Diagnostic, Severity},
+};
+use serde_json::{json, Value};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Region of a span, lines and columns are one based and columns count UTF-16 code units
+fn region(span: &Span, sources: &SourceMap) -> Value {
+    let range = span.range();
+    let mut region = json!({
+        "byteOffset": range.start,
+        "byteLength": range.len(),
+    });
+
+    if let Some((start, end)) = sources.locations(span) {
+        region["startLine"] = json!(start.line + 1);
+        region["startColumn"] = json!(start.utf16_column + 1);
+        region["endLine"] = json!(end.line + 1);
+        region["endColumn"] = json!(end.utf16_column + 1);
+    }
+
+    region
+}
+
+fn location(span: &Span, sources: &SourceMap) -> Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": span.src().to_string() },
+            "region": region(span, sources),
+        }
+    })
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Hint => "note",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+fn result(diagnostic: &Diagnostic, sources: &SourceMap) -> Value {
+    let related = diagnostic
+        .related
+        .iter()
+        .enumerate()
+        .map(|(id, label)| {
+            let mut location = location(&label.span, sources);
+            location["id"] = json!(id);
+            location["message"] = json!({ "text": label.message });
+            location
+        })
+        .collect::<Vec<_>>();
+
+    let fixes = diagnostic
+        .fix
+        .iter()
+        .map(|fix| {
+            json!({
+                "description": { "text": fix.message },
+                "artifactChanges": fix.edits.iter().map(|edit| json!({
+                    "artifactLocation": { "uri": edit.span.src().to_string() },
+                    "replacements": [{
+                        "deletedRegion": region(&edit.span, sources),
+                        "insertedContent": { "text": edit.text },
+                    }],
+                })).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "ruleId": diagnostic.kind.code(),
+        "level": level(diagnostic.severity),
+        "message": { "text": diagnostic.reason() },
+        "locations": [location(&diagnostic.span, sources)],
+        "relatedLocations": related,
+        "fixes": fixes,
+        "properties": { "notes": diagnostic.notes },
+    })
+}
+
+/// SARIF log with a single run of the analyzer, with a rule for every code it reported
+pub fn log(diagnostics: &[Diagnostic], sources: &SourceMap) -> Value {
+    let rules = diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.kind.code(), diagnostic.kind.lint()))
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(code, lint)| json!({ "id": code, "name": lint }))
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "meteor",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": diagnostics
+                .iter()
+                .map(|diagnostic| result(diagnostic, sources))
+                .collect::<Vec<_>>(),
+        }]
+    })
+}
@@ -1,11 +1,16 @@
 use std::collections::HashMap;
+use std::sync::Mutex;
 
-use chumsky::span::Span;
 use dashmap::DashMap;
+use meteor::config::Config;
+use meteor::database::Database;
 use meteor::parser::ast::{Expression, Program};
-use meteor::parser::src::SourceId;
+use meteor::parser::span::Span as MSpan;
+use meteor::parser::src::{SourceFile, SourceId};
+use meteor::parser::symbol::Identifier;
+use meteor::semantic::resolve::{DefinitionId, DefinitionKind, NameIndex};
+use meteor::semantic::Fix;
 use meteor_lsp::semantic_token::LEGEND_TYPE;
-use ropey::Rope;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::{self, *};
 use tower_lsp::{Client, LanguageServer, LspService, Server};
@@ -14,7 +19,13 @@ use tower_lsp::{Client, LanguageServer, LspService, Server};
 struct Backend {
     client: Client,
     ast_map: DashMap<String, Program>,
-    document_map: DashMap<String, Rope>,
+    document_map: DashMap<String, SourceFile>,
+
+    /// Analysis of every open document, only recomputing what an edit affects
+    db: Mutex<Database>,
+
+    /// Suggested fixes of each document, along with the range of the diagnostic they resolve
+    fix_map: DashMap<String, Vec<(Range, Fix)>>,
 }
 
 impl Backend {
@@ -22,59 +33,118 @@ impl Backend {
 
     async fn on_change(&self, params: TextDocumentItem) {
         dbg!(&params.version);
-        let rope = ropey::Rope::from_str(&params.text);
+        let file = SourceFile::new(params.text.as_str());
         self.document_map
-            .insert(params.uri.to_string(), rope.clone());
+            .insert(params.uri.to_string(), file.clone());
 
-        let result = meteor::parse(SourceId::new(params.uri.to_string()), &params.text);
+        let id = SourceId::new(params.uri.to_string());
+        let (parsed, analyzed) = {
+            let mut db = self.db.lock().unwrap();
+            db.set_source(id, params.text.as_str());
+            (db.parse(id), db.diagnostics(id))
+        };
+        let mut fixes = vec![];
 
-        let diagnostics = match result {
+        let diagnostics = match &*parsed {
             Err(err) => err
-                .into_iter()
+                .iter()
                 .filter_map(|item| {
-                    let message = item.reason().to_string();
-                    let span = item.span();
-
-                    let start_position = offset_to_position(span.start(), &rope)?;
-                    let end_position = offset_to_position(span.end(), &rope)?;
                     Some(Diagnostic::new_simple(
-                        Range::new(start_position, end_position),
-                        message,
+                        span_to_range(&item.span, &file)?,
+                        item.message.clone(),
                     ))
                 })
                 .collect(),
-            Ok(prog) => meteor::semantic::analyze(&prog)
-                .diagnostics
-                .into_iter()
+            Ok(_) => analyzed
+                .iter()
                 .filter_map(|x| {
                     use meteor::semantic;
 
-                    let message = x.reason();
-                    let span = x.span;
+                    let message = std::iter::once(x.reason())
+                        .chain(x.notes.iter().map(|note| format!("note: {note}")))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    let range = span_to_range(&x.span, &file)?;
+
+                    let related = x
+                        .related
+                        .iter()
+                        .filter_map(|label| {
+                            Some(DiagnosticRelatedInformation {
+                                location: Location::new(
+                                    params.uri.clone(),
+                                    span_to_range(&label.span, &file)?,
+                                ),
+                                message: label.message.clone(),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    if let Some(fix) = &x.fix {
+                        fixes.push((range, fix.clone()));
+                    }
 
-                    let start_position = offset_to_position(span.start(), &rope)?;
-                    let end_position = offset_to_position(span.end(), &rope)?;
                     Some(Diagnostic::new(
-                        Range::new(start_position, end_position),
+                        range,
                         Some(match x.severity {
                             semantic::Severity::Hint => DiagnosticSeverity::HINT,
                             semantic::Severity::Warning => DiagnosticSeverity::WARNING,
                             semantic::Severity::Error => DiagnosticSeverity::ERROR,
                         }),
-                        None,
-                        None,
+                        Some(NumberOrString::String(x.kind.code().to_string())),
+                        Some(format!("meteor({})", x.kind.lint())),
                         message,
-                        None,
+                        (!related.is_empty()).then_some(related),
                         None,
                     ))
                 })
                 .collect(),
         };
 
+        self.fix_map.insert(params.uri.to_string(), fixes);
+
         self.client
             .publish_diagnostics(params.uri, diagnostics, Some(params.version))
             .await;
     }
+
+    /// Reads the project config closest to a document
+    async fn load_config(&self, uri: &Url) {
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+
+        let config = match Config::discover(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                self.client
+                    .show_message(MessageType::WARNING, err.to_string())
+                    .await;
+                Config::default()
+            }
+        };
+
+        self.db
+            .lock()
+            .unwrap()
+            .set_config(SourceId::new(uri.to_string()), config);
+    }
+
+    /// Runs `f` with the definition of the name under the cursor
+    fn with_definition<T>(
+        &self,
+        params: &TextDocumentPositionParams,
+        f: impl FnOnce(&SourceFile, &NameIndex, DefinitionId) -> Option<T>,
+    ) -> Option<T> {
+        let uri = params.text_document.uri.to_string();
+        let file = self.document_map.get(&uri)?;
+        let index = self.db.lock().unwrap().names(SourceId::new(uri));
+
+        let position = params.position;
+        let offset = file.offset(position.line as usize, position.character as usize)?;
+
+        f(&file, &index, index.at(offset)?)
+    }
 }
 
 #[tower_lsp::async_trait]
@@ -142,6 +212,8 @@ impl LanguageServer for Backend {
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
                 rename_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                 ..ServerCapabilities::default()
             },
         })
@@ -157,6 +229,7 @@ impl LanguageServer for Backend {
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         dbg!("file opened");
+        self.load_config(&params.text_document.uri).await;
         self.on_change(TextDocumentItem {
             uri: params.text_document.uri,
             text: params.text_document.text,
@@ -178,6 +251,7 @@ impl LanguageServer for Backend {
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
         dbg!(&params.text);
+        self.load_config(&params.text_document.uri).await;
         if let Some(text) = params.text {
             let item = TextDocumentItem {
                 uri: params.text_document.uri,
@@ -198,11 +272,66 @@ impl LanguageServer for Backend {
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
-        Ok(None)
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri.clone();
+
+        Ok(self.with_definition(&position, |file, index, id| {
+            let range = span_to_range(index.definition(id).span.as_ref()?, file)?;
+            Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+        }))
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        Ok(None)
+        let position = params.text_document_position;
+        let uri = position.text_document.uri.clone();
+        let include_declaration = params.context.include_declaration;
+
+        Ok(self.with_definition(&position, |file, index, id| {
+            let declaration = index
+                .definition(id)
+                .span
+                .as_ref()
+                .filter(|_| include_declaration);
+
+            declaration
+                .into_iter()
+                .chain(index.references_to(id).map(|reference| &reference.span))
+                .map(|span| Some(Location::new(uri.clone(), span_to_range(span, file)?)))
+                .collect()
+        }))
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        Ok(
+            self.with_definition(&params.text_document_position_params, |file, index, id| {
+                let declaration = index.definition(id).span.as_ref().and_then(|span| {
+                    Some(DocumentHighlight {
+                        range: span_to_range(span, file)?,
+                        kind: Some(DocumentHighlightKind::TEXT),
+                    })
+                });
+
+                let references = index.references_to(id).map(|reference| {
+                    Some(DocumentHighlight {
+                        range: span_to_range(&reference.span, file)?,
+                        kind: Some(if reference.write {
+                            DocumentHighlightKind::WRITE
+                        } else {
+                            DocumentHighlightKind::READ
+                        }),
+                    })
+                });
+
+                declaration
+                    .map(Some)
+                    .into_iter()
+                    .chain(references)
+                    .collect()
+            }),
+        )
     }
 
     async fn semantic_tokens_full(
@@ -240,17 +369,101 @@ impl LanguageServer for Backend {
             })
             .collect();
 
-        default_completions.push(CompletionItem {
-            label: "print".into(),
+        let builtins = self.db.lock().unwrap().builtins();
+        default_completions.extend(builtins.iter().map(|(name, signature)| CompletionItem {
+            label: name.to_string(),
             kind: Some(CompletionItemKind::FUNCTION),
+            detail: Some(signature.to_string()),
             ..Default::default()
-        });
+        }));
 
         Ok(Some(CompletionResponse::Array(default_completions)))
     }
 
-    async fn rename(&self, _params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        Ok(None)
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        if Identifier::parse(&params.new_name).is_none() {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "`{}` is not a valid name",
+                params.new_name
+            )));
+        }
+
+        let position = params.text_document_position;
+        let uri = position.text_document.uri.clone();
+
+        Ok(self.with_definition(&position, |file, index, id| {
+            let definition = index.definition(id);
+
+            // imports are named after their file and builtins are not declared anywhere
+            if matches!(
+                definition.kind,
+                DefinitionKind::Import | DefinitionKind::Builtin
+            ) {
+                return None;
+            }
+
+            let edits = definition
+                .span
+                .iter()
+                .chain(index.references_to(id).map(|reference| &reference.span))
+                .map(|span| {
+                    Some(TextEdit::new(
+                        span_to_range(span, file)?,
+                        params.new_name.clone(),
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(WorkspaceEdit::new(HashMap::from([(uri, edits)])))
+        }))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let (Some(file), Some(fixes)) = (
+            self.document_map.get(uri.as_str()),
+            self.fix_map.get(uri.as_str()),
+        ) else {
+            return Ok(None);
+        };
+
+        let overlaps =
+            |range: &Range| range.start <= params.range.end && params.range.start <= range.end;
+
+        let actions = fixes
+            .iter()
+            .filter(|(range, _)| overlaps(range))
+            .filter_map(|(range, fix)| {
+                let edits = fix
+                    .edits
+                    .iter()
+                    .map(|edit| {
+                        Some(TextEdit::new(
+                            span_to_range(&edit.span, &file)?,
+                            edit.text.clone(),
+                        ))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                let diagnostics = params
+                    .context
+                    .diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.range == *range)
+                    .cloned()
+                    .collect();
+
+                Some(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.message.clone(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(diagnostics),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+                    ..CodeAction::default()
+                }))
+            })
+            .collect();
+
+        Ok(Some(actions))
     }
 
     async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
@@ -266,11 +479,20 @@ impl LanguageServer for Backend {
     }
 }
 
-fn offset_to_position(offset: usize, rope: &Rope) -> Option<Position> {
-    let line = rope.try_char_to_line(offset).ok()?;
-    let first_char_of_line = rope.try_line_to_char(line).ok()?;
-    let column = offset - first_char_of_line;
-    Some(Position::new(line as u32, column as u32))
+fn span_to_range(span: &MSpan, file: &SourceFile) -> Option<Range> {
+    let range = span.range();
+    Some(Range::new(
+        offset_to_position(range.start, file)?,
+        offset_to_position(range.end, file)?,
+    ))
+}
+
+fn offset_to_position(offset: usize, file: &SourceFile) -> Option<Position> {
+    let location = file.location(offset)?;
+    Some(Position::new(
+        location.line as u32,
+        location.utf16_column as u32,
+    ))
 }
 
 #[tokio::main]
@@ -282,6 +504,8 @@ async fn main() {
         client,
         ast_map: DashMap::new(),
         document_map: DashMap::new(),
+        db: Mutex::new(Database::new()),
+        fix_map: DashMap::new(),
     });
 
     Server::new(stdin, stdout, socket).serve(service).await;
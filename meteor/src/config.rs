@@ -0,0 +1,133 @@
+//! Project wide settings, read from a `meteor.json` file next to the sources or in one of
+//! their parent directories.
+//!
+//! ```json
+//! { "lints": { "invalid_top_level": "allow", "unused_variable": "deny" } }
+//! ```
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::semantic::{levels::Level, LINTS};
+
+/// Name of the project config file
+pub const CONFIG_FILE: &str = "meteor.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Level of lints by name, overridden by `@allow`, `@warn` and `@deny` directives
+    #[serde(default)]
+    pub lints: HashMap<String, Level>,
+}
+
+#[derive(Debug, Display, Error)]
+pub enum ConfigError {
+    /// Failed to read {path}: {error}
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+
+    /// Invalid config {path}: {error}
+    Invalid {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+
+    /// Unknown lint `{name}` in {path}
+    UnknownLint { name: String, path: PathBuf },
+}
+
+impl Config {
+    /// Parses the contents of a config file
+    ///
+    /// # Errors
+    ///
+    /// If the file is not a valid config or names lints that do not exist
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            serde_json::from_str(contents).map_err(|error| ConfigError::Invalid {
+                path: path.to_path_buf(),
+                error,
+            })?;
+
+        if let Some(name) = config
+            .lints
+            .keys()
+            .find(|name| !LINTS.contains(&name.as_str()))
+        {
+            return Err(ConfigError::UnknownLint {
+                name: name.clone(),
+                path: path.to_path_buf(),
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// # Errors
+    ///
+    /// If the file cannot be read or is not a valid config
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        Self::parse(path, &contents)
+    }
+
+    /// Config file closest to a path, looking in it and all of its parents
+    #[must_use]
+    pub fn find(path: &Path) -> Option<PathBuf> {
+        path.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|config| config.is_file())
+    }
+
+    /// Loads the config closest to a path, the default config if there is none
+    ///
+    /// # Errors
+    ///
+    /// If the config file that was found is not valid
+    pub fn discover(path: &Path) -> Result<Self, ConfigError> {
+        Self::find(path).map_or_else(|| Ok(Self::default()), |config| Self::load(&config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::semantic::levels::Level;
+
+    use super::{Config, ConfigError};
+
+    #[test]
+    fn parse() {
+        let path = Path::new("meteor.json");
+
+        let config = Config::parse(path, r#"{ "lints": { "invalid_top_level": "allow" } }"#);
+        assert_eq!(
+            config.unwrap().lints.get("invalid_top_level"),
+            Some(&Level::Allow)
+        );
+
+        assert_eq!(Config::parse(path, "{}").unwrap(), Config::default());
+        assert!(matches!(
+            Config::parse(path, r#"{ "lints": { "invalid_top_levels": "allow" } }"#),
+            Err(ConfigError::UnknownLint { .. })
+        ));
+        assert!(matches!(
+            Config::parse(path, r#"{ "lints": { "invalid_top_level": "ignore" } }"#),
+            Err(ConfigError::Invalid { .. })
+        ));
+    }
+}
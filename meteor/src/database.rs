@@ -0,0 +1,856 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chumsky::span::Span as _;
+
+use crate::{
+    config::Config,
+    module,
+    parser::{
+        ast::{Expression, Program},
+        cst::Lexed,
+        cst_parser, lower,
+        span::{Span, Spanned},
+        src::SourceId,
+        symbol::Identifier,
+    },
+    semantic::{
+        self,
+        outline::Outline,
+        resolve::{DefinitionKind, NameIndex},
+        Builtins, Context, Diagnostic, ItemDiagnostics, TypeEnv,
+    },
+};
+
+/// Error of parsing a file, owning its message unlike the errors of the parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub span: Span,
+    pub message: String,
+}
+
+pub type Parsed = Arc<Result<Program, Vec<SyntaxError>>>;
+
+/// Type errors of a top level expression, with the types of the globals once it ran
+type ItemTypes = Arc<(Vec<Diagnostic>, TypeEnv)>;
+
+/// Number of changes made to the inputs of a database
+type Revision = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Query {
+    Source,
+    Config,
+    Builtins,
+    SearchPaths,
+    Tokens,
+    Parse,
+    Names,
+    Imports,
+    Exports,
+    Outline,
+    Item,
+    ItemDiagnostics,
+    ItemTypes,
+    Types,
+    Diagnostics,
+}
+
+/// Query along with the file it is about, or the anchor of the top level expression it is about
+type Key = (Query, SourceId);
+
+#[derive(Debug)]
+struct Input<T> {
+    value: T,
+    changed_at: Revision,
+}
+
+#[derive(Debug)]
+struct Memo<T> {
+    value: T,
+
+    /// Last revision the value was known to be up to date in
+    verified_at: Revision,
+
+    /// Last revision the value was different in, which can be older than the last time it was
+    /// computed when recomputing gave the same value
+    changed_at: Revision,
+
+    /// Queries read while computing the value
+    dependencies: Vec<Key>,
+}
+
+type Table<T> = HashMap<SourceId, Memo<T>>;
+
+/// Memoized analysis of source files.
+///
+/// The source text and config of each file are the inputs, everything else is a query computed
+/// from them on demand and cached along with the queries it read. After changing an input a
+/// query is only computed again when one of the queries it read changed, and when it turns out
+/// the same as before nothing that depends on it is computed again either.
+///
+/// Each top level expression is analyzed on its own, with its spans made relative to where it
+/// starts and the rest of its file summed up by an [`Outline`]. Editing a function only analyzes
+/// that function again, and moving code around without changing it analyzes nothing again. A
+/// file only depends on the exports of the files it imports, so editing the bodies of their
+/// functions does not analyze the importers again.
+#[derive(Debug, Default)]
+pub struct Database {
+    revision: Revision,
+
+    sources: HashMap<SourceId, Input<Arc<str>>>,
+    configs: HashMap<SourceId, Input<Arc<Config>>>,
+
+    /// Natives of the chips every file runs on, the standard ones if never set
+    builtins: Option<Input<Arc<Builtins>>>,
+
+    /// Directories imports are looked up in after the directory of the importer
+    search_paths: Option<Input<Arc<Vec<PathBuf>>>>,
+
+    tokens: Table<Arc<Lexed>>,
+    parsed: Table<Parsed>,
+    names: Table<Arc<NameIndex>>,
+    imports: Table<Arc<Vec<(Identifier, SourceId)>>>,
+    exports: Table<Arc<Vec<Identifier>>>,
+    outlines: Table<Arc<Outline>>,
+    items: Table<Arc<Option<Spanned<Expression>>>>,
+    item_diagnostics: Table<Arc<ItemDiagnostics>>,
+    item_types: Table<ItemTypes>,
+    types: Table<Arc<Vec<Diagnostic>>>,
+    diagnostics: Table<Arc<Vec<Diagnostic>>>,
+
+    /// File and position of the top level expression each anchor stands for
+    anchors: HashMap<SourceId, (SourceId, usize)>,
+
+    /// Dependencies of each query being computed, innermost last
+    active: Vec<Vec<Key>>,
+
+    #[cfg(test)]
+    executed: Vec<Key>,
+}
+
+impl Database {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the text of a file, nothing is invalidated if it did not change
+    pub fn set_source(&mut self, id: SourceId, text: impl Into<Arc<str>>) {
+        let text = text.into();
+        if self
+            .sources
+            .get(&id)
+            .is_some_and(|input| input.value == text)
+        {
+            return;
+        }
+
+        self.revision += 1;
+        self.sources.insert(
+            id,
+            Input {
+                value: text,
+                changed_at: self.revision,
+            },
+        );
+    }
+
+    /// Sets the project config a file is analyzed with
+    pub fn set_config(&mut self, id: SourceId, config: Config) {
+        if self
+            .configs
+            .get(&id)
+            .is_some_and(|input| *input.value == config)
+        {
+            return;
+        }
+
+        self.revision += 1;
+        self.configs.insert(
+            id,
+            Input {
+                value: Arc::new(config),
+                changed_at: self.revision,
+            },
+        );
+    }
+
+    /// Sets the native functions every file is analyzed with
+    pub fn set_builtins(&mut self, builtins: Builtins) {
+        if self
+            .builtins
+            .as_ref()
+            .is_some_and(|input| *input.value == builtins)
+        {
+            return;
+        }
+
+        self.revision += 1;
+        self.builtins = Some(Input {
+            value: Arc::new(builtins),
+            changed_at: self.revision,
+        });
+    }
+
+    /// Sets the directories imports are looked up in, like [`module::ModuleLoader`] does
+    pub fn set_search_paths(&mut self, paths: Vec<PathBuf>) {
+        if self
+            .search_paths
+            .as_ref()
+            .is_some_and(|input| *input.value == paths)
+        {
+            return;
+        }
+
+        self.revision += 1;
+        self.search_paths = Some(Input {
+            value: Arc::new(paths),
+            changed_at: self.revision,
+        });
+    }
+
+    /// Text of a file, empty if it was never set
+    pub fn source(&mut self, id: SourceId) -> Arc<str> {
+        self.read((Query::Source, id));
+        self.sources
+            .get(&id)
+            .map_or_else(|| Arc::from(""), |input| input.value.clone())
+    }
+
+    pub fn config(&mut self, id: SourceId) -> Arc<Config> {
+        self.read((Query::Config, id));
+        self.configs
+            .get(&id)
+            .map(|input| input.value.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn builtins(&mut self) -> Arc<Builtins> {
+        self.read((Query::Builtins, SourceId::empty()));
+        self.builtins
+            .as_ref()
+            .map_or_else(|| Arc::new(Builtins::std()), |input| input.value.clone())
+    }
+
+    pub fn search_paths(&mut self) -> Arc<Vec<PathBuf>> {
+        self.read((Query::SearchPaths, SourceId::empty()));
+        self.search_paths
+            .as_ref()
+            .map(|input| input.value.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the text of a file was set, imports only resolve to those
+    fn exists(&mut self, id: SourceId) -> bool {
+        self.read((Query::Source, id));
+        self.sources.contains_key(&id)
+    }
+
+    /// Tokens of a file, trivia included
+    pub fn tokens(&mut self, id: SourceId) -> Arc<Lexed> {
+        self.fetch(
+            (Query::Tokens, id),
+            |db| &mut db.tokens,
+            Self::compute_tokens,
+        )
+    }
+
+    pub fn parse(&mut self, id: SourceId) -> Parsed {
+        self.fetch((Query::Parse, id), |db| &mut db.parsed, Self::compute_parse)
+    }
+
+    /// Names of a file resolved to their declarations, empty when it does not parse
+    pub fn names(&mut self, id: SourceId) -> Arc<NameIndex> {
+        self.fetch((Query::Names, id), |db| &mut db.names, Self::compute_names)
+    }
+
+    /// Files the imports of a file resolve to, along with the namespace of each. Imports of
+    /// files whose text was never set are left out.
+    pub fn imports(&mut self, id: SourceId) -> Arc<Vec<(Identifier, SourceId)>> {
+        self.fetch(
+            (Query::Imports, id),
+            |db| &mut db.imports,
+            Self::compute_imports,
+        )
+    }
+
+    /// Top level declarations of a file visible to its importers
+    pub fn exports(&mut self, id: SourceId) -> Arc<Vec<Identifier>> {
+        self.fetch(
+            (Query::Exports, id),
+            |db| &mut db.exports,
+            Self::compute_exports,
+        )
+    }
+
+    /// Outline of a file, with spans relative to the top level expression they are in
+    pub fn outline(&mut self, id: SourceId) -> Arc<Outline> {
+        self.fetch(
+            (Query::Outline, id),
+            |db| &mut db.outlines,
+            Self::compute_outline,
+        )
+    }
+
+    /// Type errors of a file
+    pub fn types(&mut self, id: SourceId) -> Arc<Vec<Diagnostic>> {
+        self.fetch((Query::Types, id), |db| &mut db.types, Self::compute_types)
+    }
+
+    /// Diagnostics of a file with the levels of its config applied, syntax errors are part of
+    /// [`Database::parse`] instead
+    pub fn diagnostics(&mut self, id: SourceId) -> Arc<Vec<Diagnostic>> {
+        self.fetch(
+            (Query::Diagnostics, id),
+            |db| &mut db.diagnostics,
+            Self::compute_diagnostics,
+        )
+    }
+
+    /// Top level expression an anchor stands for, with spans relative to where it starts
+    fn item(&mut self, anchor: SourceId) -> Arc<Option<Spanned<Expression>>> {
+        self.fetch(
+            (Query::Item, anchor),
+            |db| &mut db.items,
+            Self::compute_item,
+        )
+    }
+
+    fn item_diagnostics(&mut self, anchor: SourceId) -> Arc<ItemDiagnostics> {
+        self.fetch(
+            (Query::ItemDiagnostics, anchor),
+            |db| &mut db.item_diagnostics,
+            Self::compute_item_diagnostics,
+        )
+    }
+
+    fn item_types(&mut self, anchor: SourceId) -> ItemTypes {
+        self.fetch(
+            (Query::ItemTypes, anchor),
+            |db| &mut db.item_types,
+            Self::compute_item_types,
+        )
+    }
+
+    /// Source the spans of the top level expression at a position of a file are relative to
+    fn anchor(&mut self, id: SourceId, position: usize) -> SourceId {
+        let anchor = SourceId::new(format!("{id}#{position}"));
+        self.anchors.insert(anchor, (id, position));
+        anchor
+    }
+
+    /// Anchor of every top level expression of a file, with the offset it starts at
+    fn anchors(&mut self, id: SourceId, program: &Program) -> Vec<(SourceId, usize)> {
+        program
+            .expressions()
+            .iter()
+            .enumerate()
+            .map(|(position, (_, span))| (self.anchor(id, position), span.range().start))
+            .collect()
+    }
+
+    fn compute_tokens(&mut self, id: SourceId) -> Arc<Lexed> {
+        Arc::new(Lexed::new(id, self.source(id)))
+    }
+
+    fn compute_parse(&mut self, id: SourceId) -> Parsed {
+        let tokens = self.tokens(id);
+        let (cst, errors) = cst_parser::parse_lexed(&tokens);
+
+        let parsed = if errors.is_empty() {
+            Ok(lower::lower(&cst))
+        } else {
+            Err(errors
+                .into_iter()
+                .map(|error| SyntaxError {
+                    span: error.span().clone(),
+                    message: error.reason().to_string(),
+                })
+                .collect())
+        };
+        Arc::new(parsed)
+    }
+
+    fn compute_names(&mut self, id: SourceId) -> Arc<NameIndex> {
+        let builtins = self.builtins();
+        Arc::new(match &*self.parse(id) {
+            Ok(program) => NameIndex::with_builtins(program, &builtins),
+            Err(_) => NameIndex::default(),
+        })
+    }
+
+    fn compute_imports(&mut self, id: SourceId) -> Arc<Vec<(Identifier, SourceId)>> {
+        let parsed = self.parse(id);
+        let Ok(program) = &*parsed else {
+            return Arc::default();
+        };
+
+        let search_paths = self.search_paths();
+        let path = id.to_string();
+        let dir = Path::new(&path).parent().unwrap_or(Path::new(""));
+
+        let mut imports = vec![];
+        for (directive, _) in program.directives() {
+            if !module::is_import(directive) {
+                continue;
+            }
+            let Some(path) = module::import_path(directive) else {
+                continue;
+            };
+            let Some(namespace) = module::namespace(path) else {
+                continue;
+            };
+
+            // the candidates that are not set are read too, so setting one resolves it again
+            let resolved = module::candidates(dir, path, &search_paths)
+                .into_iter()
+                .map(|candidate| SourceId::new(candidate.to_string_lossy()))
+                .find(|candidate| self.exists(*candidate));
+
+            if let Some(resolved) = resolved {
+                imports.push((namespace, resolved));
+            }
+        }
+
+        Arc::new(imports)
+    }
+
+    fn compute_exports(&mut self, id: SourceId) -> Arc<Vec<Identifier>> {
+        let parsed = self.parse(id);
+        let Ok(program) = &*parsed else {
+            return Arc::default();
+        };
+
+        let exports = program
+            .expressions()
+            .iter()
+            .filter_map(|(expr, _)| match expr {
+                Expression::Let { meta, .. } if !meta.0.name().starts_with('_') => {
+                    Some(meta.0.name().clone())
+                }
+                _ => None,
+            })
+            .collect();
+        Arc::new(exports)
+    }
+
+    fn compute_outline(&mut self, id: SourceId) -> Arc<Outline> {
+        let parsed = self.parse(id);
+        let Ok(program) = &*parsed else {
+            return Arc::default();
+        };
+
+        let index = self.names(id);
+        let anchors = self.anchors(id, program);
+        let outline = Outline::new(program, &index).map_spans(|position, span| {
+            let (anchor, offset) = anchors[position];
+            relative(span, anchor, offset)
+        });
+        Arc::new(outline)
+    }
+
+    fn compute_item(&mut self, anchor: SourceId) -> Arc<Option<Spanned<Expression>>> {
+        let (id, position) = self.anchors[&anchor];
+        let parsed = self.parse(id);
+
+        let item = parsed
+            .as_ref()
+            .as_ref()
+            .ok()
+            .and_then(|program| program.expressions().get(position))
+            .map(|(expr, span)| {
+                let offset = span.range().start;
+                let relative = |span: &Span| relative(span, anchor, offset);
+                (expr.map_spans(&relative), relative(span))
+            });
+        Arc::new(item)
+    }
+
+    fn compute_item_diagnostics(&mut self, anchor: SourceId) -> Arc<ItemDiagnostics> {
+        let (id, position) = self.anchors[&anchor];
+        let item = self.item(anchor);
+        let Some(item) = &*item else {
+            return Arc::default();
+        };
+
+        let outline = self.outline(id);
+        let builtins = self.builtins();
+        let index = NameIndex::for_item(item, position, &outline, &builtins);
+        let mut context = Context::new(&outline, &builtins);
+
+        // only the exports of the modules the expression uses are read, so it is not analyzed
+        // again when the others change
+        for (namespace, module) in self.imports(id).iter() {
+            let used = index.references().iter().any(|reference| {
+                reference.definition.is_some_and(|definition| {
+                    let definition = index.definition(definition);
+                    definition.kind == DefinitionKind::Import && definition.name == *namespace
+                })
+            });
+
+            if used {
+                let exports = self.exports(*module).to_vec();
+                context = context.with_exports(namespace.clone(), exports);
+            }
+        }
+
+        Arc::new(semantic::analyze_item(item, position, &index, &context))
+    }
+
+    fn compute_item_types(&mut self, anchor: SourceId) -> ItemTypes {
+        let (id, position) = self.anchors[&anchor];
+        let item = self.item(anchor);
+        let outline = self.outline(id);
+        let builtins = self.builtins();
+
+        // what the globals hold carries over from one top level expression to the next
+        let mut env = match position.checked_sub(1) {
+            Some(previous) => {
+                let previous = self.anchor(id, previous);
+                self.item_types(previous).1.clone()
+            }
+            None => TypeEnv::new(&outline),
+        };
+
+        let context = Context::new(&outline, &builtins);
+        let diagnostics = match &*item {
+            Some(item) => semantic::check_item_types(item, &context, &mut env),
+            None => vec![],
+        };
+        Arc::new((diagnostics, env))
+    }
+
+    fn compute_types(&mut self, id: SourceId) -> Arc<Vec<Diagnostic>> {
+        let parsed = self.parse(id);
+        let Ok(program) = &*parsed else {
+            return Arc::default();
+        };
+
+        let anchors = self.anchors(id, program);
+        let absolute = absolute(id, &anchors);
+
+        // in order, so each one only needs the one before it to be up to date
+        let mut types = vec![];
+        for (anchor, _) in anchors {
+            let item = self.item_types(anchor);
+            types.extend(
+                item.0
+                    .iter()
+                    .map(|diagnostic| diagnostic.clone().map_spans(&absolute)),
+            );
+        }
+        Arc::new(types)
+    }
+
+    fn compute_diagnostics(&mut self, id: SourceId) -> Arc<Vec<Diagnostic>> {
+        let parsed = self.parse(id);
+        let Ok(program) = &*parsed else {
+            return Arc::default();
+        };
+
+        let index = self.names(id);
+        let anchors = self.anchors(id, program);
+        let absolute = absolute(id, &anchors);
+
+        let items = anchors
+            .iter()
+            .map(|(anchor, _)| {
+                let item = self.item_diagnostics(*anchor);
+                (*item).clone().map_spans(&absolute)
+            })
+            .collect();
+
+        let types = self.types(id).to_vec();
+        let config = self.config(id);
+        Arc::new(semantic::analyze_file(program, &index, &config, items, types).diagnostics)
+    }
+
+    /// Records that the query being computed read another one
+    fn read(&mut self, key: Key) {
+        if let Some(dependencies) = self.active.last_mut() {
+            dependencies.push(key);
+        }
+    }
+
+    fn fetch<T: Clone + PartialEq>(
+        &mut self,
+        key: Key,
+        table: fn(&mut Self) -> &mut Table<T>,
+        compute: fn(&mut Self, SourceId) -> T,
+    ) -> T {
+        self.read(key);
+        self.update(key, table, compute);
+        table(self)[&key.1].value.clone()
+    }
+
+    /// Brings the memo of a query up to date, returns the last revision its value changed in
+    fn update<T: PartialEq>(
+        &mut self,
+        key: Key,
+        table: fn(&mut Self) -> &mut Table<T>,
+        compute: fn(&mut Self, SourceId) -> T,
+    ) -> Revision {
+        let revision = self.revision;
+
+        if let Some(memo) = table(self).get(&key.1) {
+            if memo.verified_at == revision {
+                return memo.changed_at;
+            }
+
+            let verified_at = memo.verified_at;
+            let dependencies = memo.dependencies.clone();
+            if dependencies
+                .into_iter()
+                .all(|dependency| self.changed_at(dependency) <= verified_at)
+            {
+                let memo = table(self).get_mut(&key.1).expect("memo was just read");
+                memo.verified_at = revision;
+                return memo.changed_at;
+            }
+        }
+
+        self.active.push(vec![]);
+        let value = compute(self, key.1);
+        let dependencies = self.active.pop().expect("Mismatch pop/push");
+
+        #[cfg(test)]
+        self.executed.push(key);
+
+        let changed_at = match table(self).get(&key.1) {
+            Some(memo) if memo.value == value => memo.changed_at,
+            _ => revision,
+        };
+
+        table(self).insert(
+            key.1,
+            Memo {
+                value,
+                verified_at: revision,
+                changed_at,
+                dependencies,
+            },
+        );
+
+        changed_at
+    }
+
+    fn changed_at(&mut self, key: Key) -> Revision {
+        let (query, id) = key;
+        match query {
+            Query::Source => self.sources.get(&id).map_or(0, |input| input.changed_at),
+            Query::Config => self.configs.get(&id).map_or(0, |input| input.changed_at),
+            Query::Builtins => self.builtins.as_ref().map_or(0, |input| input.changed_at),
+            Query::SearchPaths => self
+                .search_paths
+                .as_ref()
+                .map_or(0, |input| input.changed_at),
+            Query::Tokens => self.update(key, |db| &mut db.tokens, Self::compute_tokens),
+            Query::Parse => self.update(key, |db| &mut db.parsed, Self::compute_parse),
+            Query::Names => self.update(key, |db| &mut db.names, Self::compute_names),
+            Query::Imports => self.update(key, |db| &mut db.imports, Self::compute_imports),
+            Query::Exports => self.update(key, |db| &mut db.exports, Self::compute_exports),
+            Query::Outline => self.update(key, |db| &mut db.outlines, Self::compute_outline),
+            Query::Item => self.update(key, |db| &mut db.items, Self::compute_item),
+            Query::ItemDiagnostics => self.update(
+                key,
+                |db| &mut db.item_diagnostics,
+                Self::compute_item_diagnostics,
+            ),
+            Query::ItemTypes => self.update(key, |db| &mut db.item_types, Self::compute_item_types),
+            Query::Types => self.update(key, |db| &mut db.types, Self::compute_types),
+            Query::Diagnostics => {
+                self.update(key, |db| &mut db.diagnostics, Self::compute_diagnostics)
+            }
+        }
+    }
+}
+
+/// Span made relative to the start of the top level expression it is in
+fn relative(span: &Span, anchor: SourceId, offset: usize) -> Span {
+    let range = span.range();
+    Span::new(anchor, range.start - offset..range.end - offset)
+}
+
+/// Maps spans relative to the top level expressions of a file back to the file
+fn absolute(id: SourceId, anchors: &[(SourceId, usize)]) -> impl Fn(&Span) -> Span {
+    let offsets = anchors.iter().copied().collect::<HashMap<_, _>>();
+
+    move |span: &Span| match offsets.get(&span.src()) {
+        Some(offset) => {
+            let range = span.range();
+            Span::new(id, range.start + offset..range.end + offset)
+        }
+        None => span.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        parser::src::SourceId,
+        semantic::{levels::Level, DiagnosticKind},
+    };
+
+    use super::{Database, Query};
+
+    /// Queries computed while running `f`, with the file or anchor each was about
+    fn executed(db: &mut Database, f: impl FnOnce(&mut Database)) -> Vec<(Query, String)> {
+        db.executed.clear();
+        f(db);
+        db.executed
+            .iter()
+            .map(|(query, id)| (*query, id.to_string()))
+            .collect()
+    }
+
+    fn queries(expected: &[(Query, &str)]) -> Vec<(Query, String)> {
+        expected
+            .iter()
+            .map(|(query, id)| (*query, (*id).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn memoizes() {
+        let (a, b) = (SourceId::new("a"), SourceId::new("b"));
+        let mut db = Database::new();
+        db.set_source(a, "func main() { let x = 1 print(x) }");
+        db.set_source(b, "func main() => 1");
+
+        let all = |db: &mut Database| {
+            db.diagnostics(a);
+            db.diagnostics(b);
+        };
+
+        assert_eq!(executed(&mut db, all).len(), 20);
+        assert_eq!(executed(&mut db, all), vec![], "nothing changed");
+
+        db.set_source(a, "func main() { let y = 1 print(2) }");
+        assert_eq!(
+            executed(&mut db, all),
+            queries(&[
+                (Query::Tokens, "a"),
+                (Query::Parse, "a"),
+                (Query::Names, "a"),
+                (Query::Item, "a#0"),
+                (Query::Outline, "a"),
+                (Query::Imports, "a"),
+                (Query::ItemDiagnostics, "a#0"),
+                (Query::ItemTypes, "a#0"),
+                (Query::Types, "a"),
+                (Query::Diagnostics, "a"),
+            ]),
+            "the other file is untouched"
+        );
+        assert_eq!(
+            db.diagnostics(a)[0].kind,
+            DiagnosticKind::UnusedVariable("y".into())
+        );
+
+        db.set_source(a, "func main() { let y = 1 print(2) }");
+        assert_eq!(executed(&mut db, all), vec![], "setting the same text");
+    }
+
+    #[test]
+    fn early_cutoff() {
+        let a = SourceId::new("a");
+        let mut db = Database::new();
+        db.set_source(a, "func main() { let x = 1 print(2) }");
+        db.diagnostics(a);
+
+        let mut config = Config::default();
+        config.lints.insert("unused_variable".into(), Level::Allow);
+        db.set_config(a, config);
+        assert_eq!(
+            executed(&mut db, |db| {
+                db.diagnostics(a);
+            }),
+            queries(&[(Query::Diagnostics, "a")]),
+            "the config is only read by the diagnostics"
+        );
+        assert!(db.diagnostics(a).is_empty());
+
+        // comments are not part of the program, so nothing after parsing runs again
+        db.set_source(a, "func main() { let x = 1 print(2) } // one\n");
+        db.diagnostics(a);
+        db.set_source(a, "func main() { let x = 1 print(2) } // two\n");
+        let reran = executed(&mut db, |db| {
+            db.diagnostics(a);
+        });
+        assert_eq!(reran, queries(&[(Query::Tokens, "a"), (Query::Parse, "a")]));
+    }
+
+    #[test]
+    fn items() {
+        let a = SourceId::new("a");
+        let mut db = Database::new();
+        db.set_source(a, "func f() { let x = 1 print(x) }\nfunc main() { f() }");
+        db.diagnostics(a);
+
+        db.set_source(a, "func f() { let x = 2 print(x) }\nfunc main() { f() }");
+        let reran = executed(&mut db, |db| {
+            db.diagnostics(a);
+        });
+        assert!(reran.contains(&(Query::ItemDiagnostics, "a#0".into())));
+        assert_eq!(
+            reran
+                .into_iter()
+                .filter(|(_, id)| id == "a#1")
+                .collect::<Vec<_>>(),
+            queries(&[(Query::Item, "a#1")]),
+            "only the function that changed is analyzed again"
+        );
+
+        // moves every expression without changing any of them
+        db.set_source(
+            a,
+            "// f\nfunc f() { let x = 2 print(x) }\nfunc main() { f() }",
+        );
+        let reran = executed(&mut db, |db| {
+            db.diagnostics(a);
+        });
+        assert!(
+            !reran
+                .iter()
+                .any(|(query, _)| matches!(query, Query::ItemDiagnostics | Query::ItemTypes)),
+            "{reran:?}"
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let (a, b) = (SourceId::new("a"), SourceId::new("b"));
+        let mut db = Database::new();
+        db.set_source(a, "@import(\"b\")\nfunc main() { print(b.x) }");
+        db.set_source(b, "let y = 1");
+
+        assert_eq!(
+            db.diagnostics(a)[0].kind,
+            DiagnosticKind::UnknownExport {
+                module: "b".into(),
+                name: "x".into()
+            }
+        );
+
+        db.set_source(b, "let x = 1");
+        assert!(
+            db.diagnostics(a).is_empty(),
+            "the importer is analyzed again"
+        );
+
+        db.set_source(b, "let x = 2");
+        let reran = executed(&mut db, |db| {
+            db.diagnostics(a);
+        });
+        assert!(reran.contains(&(Query::Exports, "b".into())));
+        assert!(
+            !reran.iter().any(|(_, id)| id.starts_with('a') && id != "a"),
+            "the exports did not change, so neither did the importer: {reran:?}"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+
+/// Layout document, a tree describing text and where it may be broken into lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Doc {
+    Nil,
+
+    Text(Cow<'static, str>),
+
+    /// Space if the enclosing group fits on one line, otherwise a newline
+    Line,
+
+    /// Nothing if the enclosing group fits on one line, otherwise a newline
+    SoftLine,
+
+    /// Always a newline, forces all enclosing groups to break
+    HardLine,
+
+    /// Only printed if the enclosing group is broken
+    IfBreak(Box<Doc>),
+
+    /// Increases the indentation of lines started inside of it
+    Indent(Box<Doc>),
+
+    /// Printed on one line if it fits, otherwise all of its lines are broken
+    Group(Box<Doc>),
+
+    Concat(Vec<Doc>),
+
+    /// Text moved to the end of the current line, forces all enclosing groups to break
+    LineSuffix(Cow<'static, str>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+impl Doc {
+    pub fn text(text: impl Into<Cow<'static, str>>) -> Self {
+        Self::Text(text.into())
+    }
+
+    #[must_use]
+    pub fn indent(self) -> Self {
+        Self::Indent(Box::new(self))
+    }
+
+    #[must_use]
+    pub fn group(self) -> Self {
+        Self::Group(Box::new(self))
+    }
+
+    #[must_use]
+    pub fn if_break(self) -> Self {
+        Self::IfBreak(Box::new(self))
+    }
+}
+
+impl From<Vec<Doc>> for Doc {
+    fn from(docs: Vec<Doc>) -> Self {
+        Self::Concat(docs)
+    }
+}
+
+impl From<&'static str> for Doc {
+    fn from(text: &'static str) -> Self {
+        Self::text(text)
+    }
+}
+
+/// Renders documents into text
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    /// Column limit lines try to stay under
+    pub max_width: usize,
+
+    /// String for one level of indentation
+    pub indent: String,
+
+    /// Columns a tab counts as when measuring lines
+    pub tab_width: usize,
+}
+
+impl Renderer {
+    fn width(&self, text: &str) -> usize {
+        text.chars()
+            .map(|c| if c == '\t' { self.tab_width } else { 1 })
+            .sum()
+    }
+
+    /// Whether `next` fits in flat mode on the rest of the line, `rest` is what gets printed after
+    fn fits(&self, next: &Doc, rest: &[(usize, Mode, &Doc)], width: usize) -> bool {
+        let mut width = width;
+        let mut rest = rest.iter().rev();
+        let mut stack = vec![(Mode::Flat, next)];
+
+        loop {
+            let (mode, doc) = match stack.pop() {
+                Some(item) => item,
+                None => match rest.next() {
+                    Some(&(_, mode, doc)) => (mode, doc),
+                    None => return true,
+                },
+            };
+
+            match doc {
+                Doc::Text(text) => match width.checked_sub(self.width(text)) {
+                    Some(remaining) => width = remaining,
+                    None => return false,
+                },
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+                Doc::Line => match width.checked_sub(1) {
+                    Some(remaining) => width = remaining,
+                    None => return false,
+                },
+                Doc::Nil | Doc::SoftLine => {}
+                Doc::HardLine => return mode == Mode::Break,
+                Doc::LineSuffix(_) => {
+                    if mode == Mode::Flat {
+                        return false;
+                    }
+                }
+                Doc::IfBreak(doc) => {
+                    if mode == Mode::Break {
+                        stack.push((mode, doc));
+                    }
+                }
+                Doc::Indent(doc) | Doc::Group(doc) => stack.push((mode, doc)),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn render(&self, doc: &Doc) -> String {
+        let mut out = String::new();
+        let mut column = 0;
+        let mut suffixes: Vec<&str> = vec![];
+        let mut stack = vec![(0, Mode::Break, doc)];
+
+        let newline = |out: &mut String, suffixes: &mut Vec<&str>, indent: usize| {
+            for suffix in suffixes.drain(..) {
+                out.push_str(suffix);
+            }
+
+            // no trailing whitespace, this also keeps blank lines empty
+            out.truncate(out.trim_end_matches([' ', '\t']).len());
+            out.push('\n');
+
+            for _ in 0..indent {
+                out.push_str(&self.indent);
+            }
+            indent * self.width(&self.indent)
+        };
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => {}
+                Doc::Text(text) => {
+                    out.push_str(text);
+                    column += self.width(text);
+                }
+                Doc::Line if mode == Mode::Flat => {
+                    out.push(' ');
+                    column += 1;
+                }
+                Doc::SoftLine if mode == Mode::Flat => {}
+                Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                    column = newline(&mut out, &mut suffixes, indent);
+                }
+                Doc::IfBreak(doc) => {
+                    if mode == Mode::Break {
+                        stack.push((indent, mode, doc));
+                    }
+                }
+                Doc::Indent(doc) => stack.push((indent + 1, mode, doc)),
+                Doc::Group(doc) => {
+                    let mode = if mode == Mode::Flat
+                        || self.fits(doc, &stack, self.max_width.saturating_sub(column))
+                    {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+                Doc::LineSuffix(text) => suffixes.push(text),
+            }
+        }
+
+        for suffix in suffixes {
+            out.push_str(suffix);
+        }
+        out.truncate(out.trim_end_matches([' ', '\t']).len());
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Doc, Renderer};
+
+    fn list(items: &[&'static str]) -> Doc {
+        let mut inner = vec![Doc::SoftLine];
+        for (i, item) in items.iter().enumerate() {
+            if i != 0 {
+                inner.push(",".into());
+                inner.push(Doc::Line);
+            }
+            inner.push((*item).into());
+        }
+        inner.push(Doc::from(",").if_break());
+
+        Doc::from(vec![
+            "[".into(),
+            Doc::from(inner).indent(),
+            Doc::SoftLine,
+            "]".into(),
+        ])
+        .group()
+    }
+
+    #[test]
+    fn groups_break_when_too_long() {
+        let renderer = Renderer {
+            max_width: 10,
+            indent: "  ".into(),
+            tab_width: 4,
+        };
+
+        assert_eq!(renderer.render(&list(&["a", "b"])), "[a, b]");
+        assert_eq!(
+            renderer.render(&list(&["aaaa", "bbbb"])),
+            "[\n  aaaa,\n  bbbb,\n]"
+        );
+    }
+
+    #[test]
+    fn suffix_moves_to_line_end() {
+        let renderer = Renderer {
+            max_width: 80,
+            indent: "  ".into(),
+            tab_width: 4,
+        };
+
+        let doc = Doc::from(vec![
+            "a".into(),
+            Doc::LineSuffix(" // comment".into()),
+            " + b".into(),
+            Doc::HardLine,
+            "c".into(),
+        ]);
+
+        assert_eq!(renderer.render(&doc), "a + b // comment\nc");
+    }
+}
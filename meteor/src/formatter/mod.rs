@@ -0,0 +1,824 @@
+//! Opinionated source formatter.
+//!
+//! Works on the concrete syntax tree so comments survive, everything else about the layout is
+//! decided here: indentation, spacing around operators, brace placement and where long lists get
+//! broken into one item per line.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{
+    cst::{NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, Trivia},
+    cst_parser::{parse_cst, ParseError},
+    lexer::Token,
+    operator::Operator,
+    printer::CONTINUATION_CHARS,
+    src::SourceId,
+};
+
+pub use doc::{Doc, Renderer};
+
+mod doc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatConfig {
+    /// Column limit after which lists are broken up
+    pub max_width: usize,
+    pub indent: Indent,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 100,
+            indent: Indent::Tabs,
+        }
+    }
+}
+
+impl FormatConfig {
+    fn renderer(&self) -> Renderer {
+        Renderer {
+            max_width: self.max_width,
+            indent: match self.indent {
+                Indent::Tabs => "\t".into(),
+                Indent::Spaces(n) => " ".repeat(n),
+            },
+            tab_width: 4,
+        }
+    }
+}
+
+/// Formats source code
+///
+/// # Errors
+///
+/// Source that does not parse is left alone and its errors are returned
+pub fn format<'src>(
+    id: SourceId,
+    source: &'src str,
+    config: &FormatConfig,
+) -> Result<String, Vec<ParseError<'src>>> {
+    let (cst, errors) = parse_cst(id, source);
+
+    if errors.is_empty() {
+        Ok(format_cst(&cst, config))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Formats an already parsed program, the tree should be free of errors
+#[must_use]
+pub fn format_cst(program: &SyntaxNode, config: &FormatConfig) -> String {
+    let doc = Formatter::new(program).program(program);
+    config.renderer().render(&doc)
+}
+
+#[derive(Debug)]
+struct Comment<'src> {
+    text: &'src str,
+
+    /// Whether there is an empty line between this and whatever came before
+    blank_before: bool,
+}
+
+/// Significant child of a node
+#[derive(Clone, Copy)]
+enum Element<'a, 'src> {
+    Node(&'a SyntaxNode<'src>),
+    Token(&'a SyntaxToken<'src>),
+}
+
+/// Expression or directive of a program or block, with the semicolon after it
+struct Statement<'a, 'src> {
+    element: Element<'a, 'src>,
+    semicolon: Option<&'a SyntaxToken<'src>>,
+}
+
+impl<'a, 'src> Statement<'a, 'src> {
+    fn first(&self) -> Option<&'a SyntaxToken<'src>> {
+        match self.element {
+            Element::Node(node) => first_token(node),
+            Element::Token(token) => Some(token),
+        }
+    }
+}
+
+/// Builds a [`Doc`] from a syntax tree.
+///
+/// Comments are attached to significant tokens (keyed by their offset): comments on their own
+/// line lead the token after them, comments after code on the same line trail the token before
+/// them. Whenever a token is printed or dropped its comments are printed with it.
+struct Formatter<'src> {
+    leading: HashMap<usize, Vec<Comment<'src>>>,
+    trailing: HashMap<usize, Vec<&'src str>>,
+
+    /// Tokens with an empty line right before them
+    blank_before: HashSet<usize>,
+
+    /// Comments after the last token
+    end: Vec<Comment<'src>>,
+}
+
+fn key(token: &SyntaxToken) -> usize {
+    token.span().range().start
+}
+
+fn significant<'a, 'src>(node: &'a SyntaxNode<'src>) -> Vec<Element<'a, 'src>> {
+    node.children()
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(Element::Node(node)),
+            SyntaxElement::Token(token) if !token.is_trivia() => Some(Element::Token(token)),
+            SyntaxElement::Token(_) => None,
+        })
+        .collect()
+}
+
+fn first_token<'a, 'src>(node: &'a SyntaxNode<'src>) -> Option<&'a SyntaxToken<'src>> {
+    node.tokens().into_iter().find(|token| !token.is_trivia())
+}
+
+fn is_token(element: Option<&Element>, expected: &Token) -> bool {
+    matches!(element, Some(Element::Token(token)) if token.token() == Some(expected))
+}
+
+impl<'src> Formatter<'src> {
+    fn new(program: &SyntaxNode<'src>) -> Self {
+        let mut formatter = Self {
+            leading: HashMap::new(),
+            trailing: HashMap::new(),
+            blank_before: HashSet::new(),
+            end: vec![],
+        };
+
+        let mut previous = None;
+        let mut newlines = 0;
+        let mut pending = vec![];
+
+        for token in program.tokens() {
+            match token.trivia() {
+                Some(Trivia::Whitespace) => newlines += token.text().matches('\n').count(),
+
+                Some(trivia) => {
+                    let text = token.text().trim_end();
+
+                    match previous {
+                        Some(previous) if newlines == 0 => {
+                            formatter.trailing.entry(previous).or_default().push(text);
+                        }
+                        _ => pending.push(Comment {
+                            text,
+                            blank_before: newlines > 1,
+                        }),
+                    }
+
+                    // line comments include their newline
+                    newlines = usize::from(trivia == Trivia::LineComment);
+                }
+
+                None => {
+                    if newlines > 1 {
+                        formatter.blank_before.insert(key(token));
+                    }
+                    if !pending.is_empty() {
+                        formatter
+                            .leading
+                            .insert(key(token), std::mem::take(&mut pending));
+                    }
+
+                    previous = Some(key(token));
+                    newlines = 0;
+                }
+            }
+        }
+
+        formatter.end = pending;
+        formatter
+    }
+
+    fn leading(&self, token: &SyntaxToken) -> Doc {
+        let Some(comments) = self.leading.get(&key(token)) else {
+            return Doc::Nil;
+        };
+
+        let mut docs = vec![];
+        for (i, comment) in comments.iter().enumerate() {
+            if i != 0 && comment.blank_before {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(Doc::text(comment.text.to_owned()));
+            docs.push(Doc::HardLine);
+        }
+
+        if self.blank_before.contains(&key(token)) {
+            docs.push(Doc::HardLine);
+        }
+
+        docs.into()
+    }
+
+    fn trailing(&self, token: &SyntaxToken) -> Doc {
+        self.trailing.get(&key(token)).map_or(Doc::Nil, |comments| {
+            comments
+                .iter()
+                .map(|comment| Doc::LineSuffix(format!(" {comment}").into()))
+                .collect::<Vec<_>>()
+                .into()
+        })
+    }
+
+    /// Token with its comments
+    fn token(&self, token: &SyntaxToken) -> Doc {
+        vec![
+            self.leading(token),
+            Doc::text(token.text().to_owned()),
+            self.trailing(token),
+        ]
+        .into()
+    }
+
+    /// Token whose leading comments were already printed by [`Self::dangling`]
+    fn token_bare(&self, token: &SyntaxToken) -> Doc {
+        vec![Doc::text(token.text().to_owned()), self.trailing(token)].into()
+    }
+
+    /// Comments of a token that is dropped from the output
+    fn comments(&self, token: &SyntaxToken) -> Doc {
+        vec![self.leading(token), self.trailing(token)].into()
+    }
+
+    /// Comments before a closing delimiter, printed on their own lines inside of the delimiters
+    fn dangling(&self, token: Option<&SyntaxToken>, after_content: bool) -> Doc {
+        let Some(comments) = token.and_then(|token| self.leading.get(&key(token))) else {
+            return Doc::Nil;
+        };
+
+        let mut docs = vec![];
+        for (i, comment) in comments.iter().enumerate() {
+            docs.push(Doc::HardLine);
+            if comment.blank_before && (after_content || i != 0) {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(Doc::text(comment.text.to_owned()));
+        }
+
+        docs.into()
+    }
+
+    /// Whether there is an empty line before a statement or its comments
+    fn starts_blank(&self, token: &SyntaxToken) -> bool {
+        match self.leading.get(&key(token)).and_then(|c| c.first()) {
+            Some(comment) => comment.blank_before,
+            None => self.blank_before.contains(&key(token)),
+        }
+    }
+
+    fn element(&self, element: Element<'_, 'src>) -> Doc {
+        match element {
+            Element::Node(node) => self.node(node),
+            Element::Token(token) => self.token(token),
+        }
+    }
+
+    /// Elements separated by `separator`
+    fn join(&self, elements: &[Element<'_, 'src>], separator: &'static str) -> Doc {
+        let mut docs = vec![];
+        for (i, element) in elements.iter().enumerate() {
+            if i != 0 {
+                docs.push(separator.into());
+            }
+            docs.push(self.element(*element));
+        }
+        docs.into()
+    }
+
+    fn program(&self, program: &SyntaxNode<'src>) -> Doc {
+        let mut docs = vec![self.statements(&significant(program))];
+
+        for (i, comment) in self.end.iter().enumerate() {
+            let first = i == 0 && docs.len() == 1 && program.nodes().next().is_none();
+            if !first {
+                docs.push(Doc::HardLine);
+                if comment.blank_before {
+                    docs.push(Doc::HardLine);
+                }
+            }
+            docs.push(Doc::text(comment.text.to_owned()));
+        }
+
+        if program.tokens().iter().any(|token| !token.is_trivia()) || !self.end.is_empty() {
+            docs.push(Doc::HardLine);
+        }
+
+        docs.into()
+    }
+
+    /// Statements of a program or block, one per line
+    fn statements(&self, elements: &[Element<'_, 'src>]) -> Doc {
+        let mut statements: Vec<Statement> = vec![];
+        for element in elements {
+            match element {
+                Element::Token(token) if token.token() == Some(&Token::Semicolon) => {
+                    if let Some(last) = statements.last_mut().filter(|s| s.semicolon.is_none()) {
+                        last.semicolon = Some(token);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            statements.push(Statement {
+                element: *element,
+                semicolon: None,
+            });
+        }
+
+        let mut docs = vec![];
+        for (i, statement) in statements.iter().enumerate() {
+            if i != 0 {
+                docs.push(Doc::HardLine);
+                if statement
+                    .first()
+                    .is_some_and(|token| self.starts_blank(token))
+                {
+                    docs.push(Doc::HardLine);
+                }
+            }
+
+            docs.push(self.element(statement.element));
+
+            // directives can't be followed by a semicolon
+            let is_expression = matches!(statement.element, Element::Node(node) if node.kind() != NodeKind::Directive);
+            let needs_semicolon = is_expression
+                && statements
+                    .get(i + 1)
+                    .and_then(Statement::first)
+                    .is_some_and(|token| token.text().starts_with(CONTINUATION_CHARS));
+
+            docs.push(match (statement.semicolon, needs_semicolon) {
+                (Some(semicolon), true) => self.token(semicolon),
+                (Some(semicolon), false) => self.comments(semicolon),
+                (None, true) => ";".into(),
+                (None, false) => Doc::Nil,
+            });
+        }
+
+        docs.into()
+    }
+
+    /// Splits off the opening and closing delimiters
+    fn delimited<'a, 'b>(
+        elements: &'b [Element<'a, 'src>],
+        close: &Token,
+    ) -> (
+        Option<&'a SyntaxToken<'src>>,
+        &'b [Element<'a, 'src>],
+        Option<&'a SyntaxToken<'src>>,
+    ) {
+        let (open, rest) = match elements.split_first() {
+            Some((Element::Token(open), rest)) => (Some(*open), rest),
+            _ => (None, elements),
+        };
+
+        match rest.split_last() {
+            Some((Element::Token(token), inner)) if token.token() == Some(close) => {
+                (open, inner, Some(*token))
+            }
+            _ => (open, rest, None),
+        }
+    }
+
+    fn block(&self, node: &SyntaxNode<'src>) -> Doc {
+        let elements = significant(node);
+        let (open, inner, close) = Self::delimited(&elements, &Token::CurlyBraceClose);
+
+        let mut docs = vec![open.map_or(Doc::Nil, |open| self.token(open))];
+
+        if inner.is_empty() {
+            let dangling = self.dangling(close, false);
+            if dangling != Doc::Nil {
+                docs.push(dangling.indent());
+                docs.push(Doc::HardLine);
+            }
+        } else {
+            docs.push(
+                Doc::from(vec![
+                    Doc::HardLine,
+                    self.statements(inner),
+                    self.dangling(close, true),
+                ])
+                .indent(),
+            );
+            docs.push(Doc::HardLine);
+        }
+
+        docs.push(close.map_or(Doc::Nil, |close| self.token_bare(close)));
+        docs.into()
+    }
+
+    /// Comma separated items between delimiters, either all on one line or one item per line with
+    /// a trailing comma
+    fn list(&self, elements: &[Element<'_, 'src>], close: &Token, padded: bool) -> Doc {
+        let (open, inner, close) = Self::delimited(elements, close);
+        let line = if padded { Doc::Line } else { Doc::SoftLine };
+
+        let mut items = vec![];
+        let mut separators: Vec<Doc> = vec![];
+        let mut leading_commas = vec![];
+
+        for element in inner {
+            match element {
+                Element::Token(token) if token.token() == Some(&Token::Comma) => {
+                    match separators.last_mut() {
+                        Some(separator) => {
+                            *separator = vec![separator.clone(), self.comments(token)].into();
+                        }
+                        None => leading_commas.push(self.comments(token)),
+                    }
+                }
+                _ => {
+                    items.push(self.element(*element));
+                    separators.push(Doc::Nil);
+                }
+            }
+        }
+
+        let mut docs = vec![open.map_or(Doc::Nil, |open| self.token(open))];
+
+        if items.is_empty() {
+            docs.extend(leading_commas);
+
+            let dangling = self.dangling(close, false);
+            if dangling != Doc::Nil {
+                docs.push(dangling.indent());
+                docs.push(Doc::HardLine);
+            }
+
+            docs.push(close.map_or(Doc::Nil, |close| self.token_bare(close)));
+            return docs.into();
+        }
+
+        let mut inner = vec![line.clone()];
+        inner.extend(leading_commas);
+
+        let count = items.len();
+        for (i, (item, separator)) in items.into_iter().zip(separators).enumerate() {
+            inner.push(item);
+
+            if i + 1 == count {
+                inner.push(Doc::from(",").if_break());
+                inner.push(separator);
+            } else {
+                inner.push(",".into());
+                inner.push(separator);
+                inner.push(Doc::Line);
+            }
+        }
+        inner.push(self.dangling(close, true));
+
+        docs.push(Doc::from(inner).indent());
+        docs.push(line);
+        docs.push(close.map_or(Doc::Nil, |close| self.token_bare(close)));
+
+        Doc::from(docs).group()
+    }
+
+    fn function(&self, elements: &[Element<'_, 'src>]) -> Doc {
+        let mut docs = vec![];
+        let mut parameters = false;
+        let mut arrow = false;
+
+        for (i, element) in elements.iter().enumerate() {
+            match element {
+                // `func`
+                Element::Token(token) if i == 0 => docs.push(self.token(token)),
+
+                // name
+                Element::Token(token) if matches!(token.token(), Some(Token::Identifier(_))) => {
+                    docs.push(" ".into());
+                    docs.push(self.token(token));
+                }
+
+                Element::Node(node) if node.kind() == NodeKind::Parameters => {
+                    parameters = true;
+                    docs.push(self.node(node));
+                }
+
+                // a single parameter without parenthesis gets them added
+                Element::Node(node) if node.kind() == NodeKind::Variable => {
+                    parameters = true;
+                    docs.push(vec!["(".into(), self.node(node), ")".into()].into());
+                }
+
+                _ => {
+                    if !parameters {
+                        parameters = true;
+                        docs.push("()".into());
+                    }
+
+                    if arrow {
+                        docs.push(self.element(*element));
+                    } else {
+                        docs.push(" ".into());
+                        docs.push(self.element(*element));
+                    }
+
+                    if is_token(Some(element), &Token::FatArrow) {
+                        arrow = true;
+                        docs.push(" ".into());
+                    }
+                }
+            }
+        }
+
+        docs.into()
+    }
+
+    fn node(&self, node: &SyntaxNode<'src>) -> Doc {
+        let elements = significant(node);
+
+        match node.kind() {
+            NodeKind::Program => self.program(node),
+
+            NodeKind::Block => self.block(node),
+
+            NodeKind::Function => self.function(&elements),
+
+            NodeKind::Directive => match elements.split_first() {
+                Some((name, [])) => self.element(*name),
+                Some((name, params)) => vec![
+                    self.element(*name),
+                    self.list(params, &Token::ParenClosed, false),
+                ]
+                .into(),
+                None => Doc::Nil,
+            },
+
+            NodeKind::Array => self.list(&elements, &Token::BracketClose, false),
+            NodeKind::Parameters | NodeKind::TypeList => {
+                self.list(&elements, &Token::ParenClosed, false)
+            }
+            NodeKind::Dictionary => self.list(&elements, &Token::CurlyBraceClose, true),
+            NodeKind::Call => match elements.split_first() {
+                Some((function, arguments)) => vec![
+                    self.element(*function),
+                    self.list(arguments, &Token::ParenClosed, false),
+                ]
+                .into(),
+                None => Doc::Nil,
+            },
+
+            NodeKind::UnaryOp => match elements.as_slice() {
+                [Element::Token(operator), Element::Node(operand)] => {
+                    // `- 1` must not turn into the number `-1`
+                    let space = operator.token() != Some(&Token::Operator(Operator::Sub))
+                        || first_token(operand).is_some_and(|token| {
+                            token
+                                .text()
+                                .starts_with(|c: char| c.is_ascii_digit() || c == '-')
+                        });
+
+                    vec![
+                        self.token(operator),
+                        if space { " ".into() } else { Doc::Nil },
+                        self.node(operand),
+                    ]
+                    .into()
+                }
+                _ => self.join(&elements, " "),
+            },
+
+            NodeKind::Variable
+            | NodeKind::PropertyAccess
+            | NodeKind::ArrayIndex
+            | NodeKind::Parenthesized
+            | NodeKind::Literal
+            | NodeKind::Ident => self.join(&elements, ""),
+
+            // `func(number) -> str`
+            NodeKind::FunctionType => vec![
+                self.join(&elements[..elements.len().min(2)], ""),
+                match elements.get(2) {
+                    Some(returns) => vec![" ".into(), self.element(*returns)].into(),
+                    None => Doc::Nil,
+                },
+            ]
+            .into(),
+
+            NodeKind::TypeAnnotation
+            | NodeKind::ReturnType
+            | NodeKind::Let
+            | NodeKind::If
+            | NodeKind::While
+            | NodeKind::Do
+            | NodeKind::BinaryOp
+            | NodeKind::DictEntry => self.join(&elements, " "),
+
+            NodeKind::Error => Doc::text(node.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use proptest::prelude::*;
+
+    use crate::parser::{
+        arbitrary, ast::Program, cst_parser::parse_cst, lower::lower, printer::print_program,
+        src::SourceId,
+    };
+
+    use super::{format, FormatConfig, Indent};
+
+    fn fmt(source: &str) -> String {
+        format(SourceId::empty(), source, &FormatConfig::default()).unwrap()
+    }
+
+    /// Formats and checks that the result means the same and is stable
+    fn check(source: &str, expected: &str) {
+        let formatted = fmt(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(fmt(&formatted), formatted, "not idempotent");
+
+        let canonical =
+            |source: &str| print_program(&lower(&parse_cst(SourceId::empty(), source).0));
+        assert_eq!(canonical(&formatted), canonical(source));
+    }
+
+    #[test]
+    fn attributes() {
+        check(
+            "@deny(unused_variable)\nlet a = 1\n@allow(unused_function, shadowed_variable)\nfunc f() => 1",
+            indoc! {r#"
+                @deny(unused_variable)
+                let a = 1
+                @allow(unused_function, shadowed_variable)
+                func f() => 1
+            "#},
+        );
+    }
+
+    #[test]
+    fn signatures() {
+        check(
+            "func f( a : func( number,str )->bool )->number{a(1, \"\")}\nfunc -> nil => nil",
+            indoc! {r#"
+                func f(a: func(number, str) -> bool) -> number {
+                	a(1, "")
+                }
+                func() -> nil => nil
+            "#},
+        );
+    }
+
+    #[test]
+    fn layout() {
+        check(
+            indoc! {r#"
+                @name
+                @import ( "lib" , )
+                func   main( a:number,b ){let x=[1,2,] ; x= -1+  - 1
+                if a>b{print(a)}else if a==b{ } else{print(b) }
+                while x<10 { x=x*2 }
+                unless not x {do{x}}
+                let d={a=1,b={}}
+                    d.a = x[0] }
+                func f x => x
+                main(1,2)
+            "#},
+            indoc! {r#"
+                @name
+                @import("lib")
+                func main(a: number, b) {
+                	let x = [1, 2]
+                	x = -1 + - 1
+                	if a > b {
+                		print(a)
+                	} else if a == b {} else {
+                		print(b)
+                	}
+                	while x < 10 {
+                		x = x * 2
+                	}
+                	unless not x {
+                		do {
+                			x
+                		}
+                	}
+                	let d = { a = 1, b = {} }
+                	d.a = x[0]
+                }
+                func f(x) => x
+                main(1, 2)
+            "#},
+        );
+    }
+
+    #[test]
+    fn semicolons() {
+        check("a; b;\nc; (d); [e]; -f", "a\nb\nc;\n(d);\n[e];\n-f\n");
+
+        // without a semicolon this was a call all along
+        check("c\n(d)", "c(d)\n");
+    }
+
+    #[test]
+    fn blank_lines() {
+        check("a\n\n\n\nb\n// c\n\nd\n", "a\n\nb\n// c\n\nd\n");
+    }
+
+    #[test]
+    fn comments() {
+        check(
+            indoc! {r"
+                // leading
+                func main() { // after brace
+                    let a = [ // inside list
+                        1, // one
+                        2,
+                        // dangling
+                    ]
+
+                    // before statement
+                    a = 1 // end of line
+                    // before brace
+                }
+                // end
+            "},
+            indoc! {r"
+                // leading
+                func main() { // after brace
+                	let a = [ // inside list
+                		1, // one
+                		2,
+                		// dangling
+                	]
+
+                	// before statement
+                	a = 1 // end of line
+                	// before brace
+                }
+                // end
+            "},
+        );
+
+        check("f(a, // comment\nb)", "f(\n\ta, // comment\n\tb,\n)\n");
+        check("{ } // comment\n", "{} // comment\n");
+        check("// only comment\n", "// only comment\n");
+    }
+
+    #[test]
+    fn wrapping() {
+        let config = FormatConfig {
+            max_width: 20,
+            indent: Indent::Spaces(2),
+        };
+
+        let formatted = format(
+            SourceId::empty(),
+            "call(first, [second, third], { key = 1 })\nf(a)",
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(
+            formatted,
+            indoc! {"
+                call(
+                  first,
+                  [second, third],
+                  { key = 1 },
+                )
+                f(a)
+            "}
+        );
+    }
+
+    #[test]
+    fn refuses_invalid() {
+        assert!(format(SourceId::empty(), "let = ", &FormatConfig::default()).is_err());
+        assert_eq!(fmt(""), "");
+    }
+
+    proptest! {
+        #[test]
+        fn keeps_meaning(expressions in prop::collection::vec(arbitrary::expression(), 0..4)) {
+            let program = Program::new(vec![], expressions);
+            let source = print_program(&program);
+
+            let formatted = fmt(&source);
+            let reparsed = lower(&parse_cst(SourceId::empty(), &formatted).0);
+
+            prop_assert_eq!(print_program(&reparsed), source, "{}", formatted);
+            prop_assert_eq!(fmt(&formatted), formatted);
+        }
+    }
+}
@@ -1,14 +1,12 @@
 #![cfg_attr(debug_assertions, allow(dead_code))]
 
-use chumsky::{
-    error::Rich,
-    input::{Input, Stream},
-    span::Span,
-    Parser,
-};
-use logos::Logos;
-use parser::{ast::Program, lexer::Token, span, src::SourceId};
+use chumsky::error::Rich;
+use parser::{ast::Program, cst::SyntaxNode, lexer::Token, span, src::SourceId};
 
+pub mod config;
+pub mod database;
+pub mod formatter;
+pub mod module;
 pub mod parser;
 pub mod runtime;
 pub mod semantic;
@@ -17,19 +15,21 @@ pub mod vm;
 // pub use runtime::array::Array;
 
 pub fn parse(id: SourceId, source: &str) -> Result<Program, Vec<Rich<'_, Token<'_>, span::Span>>> {
-    let token_iter = Token::lexer(source)
-        .spanned()
-        .map(move |(tok, span)| match tok {
-            Ok(tok) => (tok, span::Span::new(id.clone(), span)),
-            Err(()) => (Token::Error, span::Span::new(id.clone(), span)),
-        });
+    let (cst, errors) = parse_cst(id, source);
 
-    let token_stream = Stream::from_iter(token_iter).map(
-        span::Span::new(id.clone(), 0..source.len()),
-        move |(t, s)| (t, s),
-    );
-
-    let parser = parser::parser::program_parser();
+    if errors.is_empty() {
+        Ok(parser::lower::lower(&cst))
+    } else {
+        Err(errors)
+    }
+}
 
-    parser.parse(token_stream).into_result()
+/// Parses into the lossless concrete syntax tree the AST is lowered from, for tools that need to
+/// keep formatting and comments
+#[must_use]
+pub fn parse_cst(
+    id: SourceId,
+    source: &str,
+) -> (SyntaxNode<'_>, Vec<Rich<'_, Token<'_>, span::Span>>) {
+    parser::cst_parser::parse_cst(id, source)
 }
@@ -0,0 +1,664 @@
+//! Programs spread over multiple files.
+//!
+//! A file imports another with `@import("path")`, the imported module's top level declarations
+//! are then available under its file name, so `@import("lib/vector")` binds `vector.add`.
+//! Declarations whose name starts with `_` are private to their module.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use chumsky::span::Span as _;
+use displaydoc::Display;
+use thiserror::Error;
+
+use crate::{
+    parser::{
+        ast::{Directive, Expression, Program, VariableMeta},
+        span::{Span, Spanned},
+        src::SourceId,
+        symbol::Identifier,
+    },
+    runtime::memory::Mutability,
+};
+
+/// File extension of source files, it may be left out of import paths
+pub const EXTENSION: &str = "moonbrain";
+
+/// Name of the import directive
+pub const IMPORT: &str = "import";
+
+/// Modules of a cyclic import, the first one is repeated at the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportChain(pub Vec<SourceId>);
+
+#[derive(Debug, Display, Error)]
+pub enum ModuleError {
+    /// Failed to read {path}: {error}
+    Io {
+        path: String,
+        error: std::io::Error,
+        span: Option<Span>,
+    },
+
+    /// Cannot find module "{path}"
+    NotFound { path: String, span: Span },
+
+    /// Import expects a single path string
+    InvalidImport { span: Span },
+
+    /// Module name `{name}` is not a valid identifier
+    InvalidNamespace { name: String, span: Span },
+
+    /// Module `{name}` is already imported
+    DuplicateNamespace { name: Identifier, span: Span },
+
+    /// Cyclic import {chain}
+    Cycle { chain: ImportChain, span: Span },
+
+    /// {reason}
+    Syntax { reason: String, span: Span },
+}
+
+/// Module imported by another, bound to `namespace` in the importing module
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub namespace: Identifier,
+
+    /// Index into [`ModuleGraph::modules`]
+    pub module: usize,
+
+    /// Span of the import directive
+    pub span: Span,
+}
+
+/// Parsed source file
+#[derive(Debug)]
+pub struct Module {
+    id: SourceId,
+    path: PathBuf,
+    source: String,
+    program: Program,
+    imports: Vec<Import>,
+}
+
+/// Every module of a program, each one is loaded and parsed once no matter how often it is
+/// imported
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    /// Imported modules come before the modules importing them, the entry module is last
+    modules: Vec<Module>,
+    errors: Vec<ModuleError>,
+}
+
+/// Finds and loads modules, imports are looked up relative to the importing file and then in each
+/// search path
+#[derive(Debug, Clone, Default)]
+pub struct ModuleLoader {
+    search_paths: Vec<PathBuf>,
+}
+
+impl fmt::Display for ImportChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, id) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{id}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ModuleError {
+    #[must_use]
+    pub fn reason(&self) -> String {
+        format!("{self}")
+    }
+
+    /// Where the error happened, `None` if the entry file itself could not be read
+    #[must_use]
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            ModuleError::Io { span, .. } => span.as_ref(),
+            ModuleError::NotFound { span, .. }
+            | ModuleError::InvalidImport { span }
+            | ModuleError::InvalidNamespace { span, .. }
+            | ModuleError::DuplicateNamespace { span, .. }
+            | ModuleError::Cycle { span, .. }
+            | ModuleError::Syntax { span, .. } => Some(span),
+        }
+    }
+}
+
+impl Module {
+    #[must_use]
+    pub fn id(&self) -> SourceId {
+        self.id
+    }
+
+    /// Canonical path of the file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[must_use]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Parsed program, empty if the source has syntax errors
+    #[must_use]
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    #[must_use]
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+
+    /// Top level declarations visible to importers
+    pub fn exports(&self) -> impl Iterator<Item = Spanned<&Identifier>> {
+        self.program
+            .expressions()
+            .iter()
+            .filter_map(|(expr, span)| match expr {
+                Expression::Let { meta, .. } if !meta.0.name().starts_with('_') => {
+                    Some((meta.0.name(), span.clone()))
+                }
+                _ => None,
+            })
+    }
+}
+
+impl ModuleGraph {
+    #[must_use]
+    pub fn modules(&self) -> &[Module] {
+        &self.modules
+    }
+
+    /// File the program was loaded from
+    #[must_use]
+    pub fn entry(&self) -> Option<&Module> {
+        self.modules.last()
+    }
+
+    #[must_use]
+    pub fn errors(&self) -> &[ModuleError] {
+        &self.errors
+    }
+
+    #[must_use]
+    pub fn module(&self, id: SourceId) -> Option<&Module> {
+        self.modules.iter().find(|module| module.id == id)
+    }
+
+    /// Source text of every loaded file
+    pub fn sources(&self) -> impl Iterator<Item = (SourceId, &str)> {
+        self.modules
+            .iter()
+            .map(|module| (module.id, module.source.as_str()))
+    }
+
+    /// Combines all modules into a single program the runtime can execute.
+    ///
+    /// Every imported module becomes a hidden global holding a dictionary of its exports, which
+    /// the importing modules bind to their namespace. The entry module stays at the top level.
+    #[must_use]
+    pub fn link(&self) -> Program {
+        let Some((entry, imported)) = self.modules.split_last() else {
+            return Program::new(vec![], vec![]);
+        };
+
+        let mut expressions = vec![];
+
+        for module in imported {
+            let span = Span::new(module.id, 0..module.source.len());
+
+            let mut body = self.bindings(module);
+            body.extend(module.program.expressions().iter().cloned());
+            body.push((
+                Expression::Dictionary(
+                    module
+                        .exports()
+                        .map(|(name, span)| (name.clone(), (Expression::Ident(name.clone()), span)))
+                        .collect(),
+                ),
+                span.clone(),
+            ));
+
+            expressions.push((
+                Expression::Let {
+                    meta: (
+                        VariableMeta::new(hidden_name(module.id), None, Mutability::Constant),
+                        span.clone(),
+                    ),
+                    init: Box::new((Expression::Block(body), span)),
+                },
+                Span::new(module.id, 0..0),
+            ));
+        }
+
+        expressions.extend(self.bindings(entry));
+        expressions.extend(entry.program.expressions().iter().cloned());
+
+        Program::new(entry.program.directives().clone(), expressions)
+    }
+
+    /// Declarations binding the imports of a module to their namespace
+    fn bindings(&self, module: &Module) -> Vec<Spanned<Expression>> {
+        module
+            .imports
+            .iter()
+            .map(|import| {
+                let imported = hidden_name(self.modules[import.module].id);
+
+                (
+                    Expression::Let {
+                        meta: (
+                            VariableMeta::new(import.namespace.clone(), None, Mutability::Constant),
+                            import.span.clone(),
+                        ),
+                        init: Box::new((Expression::Ident(imported), import.span.clone())),
+                    },
+                    import.span.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Global holding the exports of a module, not a valid identifier so user code can't clash
+fn hidden_name(id: SourceId) -> Identifier {
+    format!("@{IMPORT} {id}").into()
+}
+
+#[must_use]
+pub fn is_import(directive: &Directive) -> bool {
+    directive.name().name() == IMPORT
+}
+
+/// Path of an import directive, `None` if the directive does not have exactly one string
+/// parameter
+#[must_use]
+pub fn import_path(directive: &Directive) -> Option<&str> {
+    match directive.params().as_slice() {
+        [(Expression::String(path), _)] => Some(path),
+        _ => None,
+    }
+}
+
+/// Name an imported module is bound to, the file name without extension. `None` if that is not
+/// a valid identifier.
+#[must_use]
+pub fn namespace(path: &str) -> Option<Identifier> {
+    let stem = Path::new(path).file_stem()?.to_str()?;
+    Identifier::parse(stem)
+}
+
+/// Paths an import in a file of `dir` can refer to, in the order they are tried. `./` and `../`
+/// imports are only relative to `dir`.
+#[must_use]
+pub fn candidates(dir: &Path, import: &str, search_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let relative_only = import.starts_with("./") || import.starts_with("../");
+
+    std::iter::once(dir)
+        .chain(
+            search_paths
+                .iter()
+                .map(PathBuf::as_path)
+                .filter(|_| !relative_only),
+        )
+        .flat_map(|base| {
+            let path = base.join(import);
+            let with_extension = path.with_extension(EXTENSION);
+            [path, with_extension]
+        })
+        .collect()
+}
+
+impl ModuleLoader {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory imports are looked up in when they are not relative to the importer
+    #[must_use]
+    pub fn with_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.search_paths.push(path.into());
+        self
+    }
+
+    /// Loads a file and everything it imports, errors are collected in the graph instead of
+    /// stopping at the first one
+    #[must_use]
+    pub fn load(&self, entry: &Path) -> ModuleGraph {
+        let mut graph = ModuleGraph::default();
+        let mut loading = vec![];
+        let mut loaded = HashMap::new();
+
+        self.visit(entry, None, &mut graph, &mut loading, &mut loaded);
+
+        graph
+    }
+
+    /// Finds the file an import refers to
+    fn resolve(&self, dir: &Path, import: &str) -> Option<PathBuf> {
+        candidates(dir, import, &self.search_paths)
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+
+    fn visit(
+        &self,
+        path: &Path,
+        import_span: Option<&Span>,
+        graph: &mut ModuleGraph,
+        loading: &mut Vec<(PathBuf, SourceId)>,
+        loaded: &mut HashMap<PathBuf, usize>,
+    ) -> Option<usize> {
+        let id = SourceId::new(path.to_string_lossy());
+
+        let io_error = |error| ModuleError::Io {
+            path: path.to_string_lossy().into(),
+            error,
+            span: import_span.cloned(),
+        };
+
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(error) => {
+                graph.errors.push(io_error(error));
+                return None;
+            }
+        };
+
+        if let Some(&index) = loaded.get(&canonical) {
+            return Some(index);
+        }
+
+        if let Some(start) = loading.iter().position(|(path, _)| *path == canonical) {
+            let mut chain: Vec<_> = loading[start..].iter().map(|(_, id)| *id).collect();
+            chain.push(id);
+
+            graph.errors.push(ModuleError::Cycle {
+                chain: ImportChain(chain),
+                span: import_span.cloned().unwrap_or_else(Span::empty),
+            });
+            return None;
+        }
+
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                graph.errors.push(io_error(error));
+                return None;
+            }
+        };
+
+        let program = match crate::parse(id, &source) {
+            Ok(program) => program,
+            Err(errors) => {
+                graph
+                    .errors
+                    .extend(errors.into_iter().map(|error| ModuleError::Syntax {
+                        reason: error.reason().to_string(),
+                        span: error.span().clone(),
+                    }));
+                Program::new(vec![], vec![])
+            }
+        };
+
+        loading.push((canonical.clone(), id));
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut imports: Vec<Import> = vec![];
+
+        for (directive, span) in program.directives() {
+            if !is_import(directive) {
+                continue;
+            }
+
+            let Some(import) = import_path(directive) else {
+                graph
+                    .errors
+                    .push(ModuleError::InvalidImport { span: span.clone() });
+                continue;
+            };
+
+            let Some(namespace) = namespace(import) else {
+                graph.errors.push(ModuleError::InvalidNamespace {
+                    name: import.into(),
+                    span: span.clone(),
+                });
+                continue;
+            };
+
+            if imports.iter().any(|import| import.namespace == namespace) {
+                graph.errors.push(ModuleError::DuplicateNamespace {
+                    name: namespace,
+                    span: span.clone(),
+                });
+                continue;
+            }
+
+            let Some(resolved) = self.resolve(dir, import) else {
+                graph.errors.push(ModuleError::NotFound {
+                    path: import.into(),
+                    span: span.clone(),
+                });
+                continue;
+            };
+
+            if let Some(module) = self.visit(&resolved, Some(span), graph, loading, loaded) {
+                imports.push(Import {
+                    namespace,
+                    module,
+                    span: span.clone(),
+                });
+            }
+        }
+
+        loading.pop();
+
+        graph.modules.push(Module {
+            id,
+            path: canonical.clone(),
+            source,
+            program,
+            imports,
+        });
+
+        let index = graph.modules.len() - 1;
+        loaded.insert(canonical, index);
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use indoc::indoc;
+
+    use crate::runtime::{value::Value, Chip};
+
+    use super::{namespace, ModuleError, ModuleGraph, ModuleLoader};
+
+    /// Writes files into a fresh temporary directory
+    fn files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meteor-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        for (path, source) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+
+        dir
+    }
+
+    fn run(graph: &ModuleGraph) -> Value {
+        assert!(
+            graph.errors().is_empty(),
+            "{:?}",
+            graph
+                .errors()
+                .iter()
+                .map(ModuleError::reason)
+                .collect::<Vec<_>>()
+        );
+        Chip::new(graph.link()).run().unwrap()
+    }
+
+    #[test]
+    fn namespaces() {
+        assert_eq!(namespace("lib/vector.moonbrain"), Some("vector".into()));
+        assert_eq!(namespace("math"), Some("math".into()));
+        assert_eq!(namespace("lib/my-lib"), None);
+        assert_eq!(namespace("lib/1st"), None);
+    }
+
+    #[test]
+    fn imports() {
+        let dir = files(
+            "imports",
+            &[
+                (
+                    "main.moonbrain",
+                    indoc! {r#"
+                        @import("lib/math")
+                        @import("lib/double.moonbrain")
+
+                        func main() => double.twice(math.add(1, 2))
+                    "#},
+                ),
+                (
+                    "lib/math.moonbrain",
+                    indoc! {"
+                        let _offset = 0
+                        func add(a, b) => a + b + _offset
+                    "},
+                ),
+                (
+                    "lib/double.moonbrain",
+                    indoc! {r#"
+                        @import("math")
+
+                        func twice(x) => math.add(x, x)
+                    "#},
+                ),
+            ],
+        );
+
+        let graph = ModuleLoader::new().load(&dir.join("main.moonbrain"));
+
+        // math is shared, parsed once and loaded before its importers
+        assert_eq!(graph.modules().len(), 3);
+        assert!(graph.modules()[0]
+            .id()
+            .to_string()
+            .ends_with("math.moonbrain"));
+        assert_eq!(
+            graph.modules()[0]
+                .exports()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["add"]
+        );
+
+        assert_eq!(run(&graph), Value::Number(6.));
+    }
+
+    #[test]
+    fn search_paths() {
+        let dir = files(
+            "search-paths",
+            &[
+                (
+                    "src/main.moonbrain",
+                    "@import(\"util\")\nfunc main() => util.one",
+                ),
+                ("libs/util.moonbrain", "let one = 1"),
+            ],
+        );
+
+        let graph = ModuleLoader::new().load(&dir.join("src/main.moonbrain"));
+        assert!(matches!(graph.errors(), [ModuleError::NotFound { .. }]));
+
+        let graph = ModuleLoader::new()
+            .with_search_path(dir.join("libs"))
+            .load(&dir.join("src/main.moonbrain"));
+        assert_eq!(run(&graph), Value::Number(1.));
+
+        // explicitly relative imports skip the search paths
+        std::fs::write(dir.join("src/main.moonbrain"), "@import(\"./util\")").unwrap();
+        let graph = ModuleLoader::new()
+            .with_search_path(dir.join("libs"))
+            .load(&dir.join("src/main.moonbrain"));
+        assert!(matches!(graph.errors(), [ModuleError::NotFound { .. }]));
+    }
+
+    #[test]
+    fn cycles() {
+        let dir = files(
+            "cycles",
+            &[
+                ("a.moonbrain", "@import(\"b\")"),
+                ("b.moonbrain", "@import(\"c\")"),
+                ("c.moonbrain", "@import(\"a\")"),
+            ],
+        );
+
+        let graph = ModuleLoader::new().load(&dir.join("a.moonbrain"));
+
+        let [ModuleError::Cycle { chain, span }] = graph.errors() else {
+            panic!("{:?}", graph.errors());
+        };
+
+        let names: Vec<_> = chain
+            .0
+            .iter()
+            .map(|id| namespace(&id.to_string()).unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["a", "b", "c", "a"]);
+
+        // reported at the import closing the cycle
+        assert!(span.src().to_string().ends_with("c.moonbrain"));
+    }
+
+    #[test]
+    fn errors_keep_their_file() {
+        let dir = files(
+            "errors",
+            &[
+                ("main.moonbrain", "@import(\"broken\")\n@import(1)"),
+                ("broken.moonbrain", "let = 1"),
+            ],
+        );
+
+        let graph = ModuleLoader::new().load(&dir.join("main.moonbrain"));
+
+        let files: Vec<_> = graph
+            .errors()
+            .iter()
+            .map(|error| {
+                let id = error.span().unwrap().src();
+                (
+                    namespace(&id.to_string()).unwrap().to_string(),
+                    matches!(error, ModuleError::Syntax { .. }),
+                )
+            })
+            .collect();
+
+        assert!(files.contains(&("broken".into(), true)));
+        assert!(files.contains(&("main".into(), false)));
+        assert!(graph.module(graph.modules()[0].id()).is_some());
+    }
+}
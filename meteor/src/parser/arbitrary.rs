@@ -0,0 +1,162 @@
+//! Proptest strategies generating ASTs that the parser can produce
+
+use proptest::prelude::*;
+
+use crate::{
+    parser::{
+        ast::{Expression, Function, Type, VariableMeta},
+        operator::Operator,
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::memory::Mutability,
+};
+
+fn span<T>(value: T) -> Spanned<T> {
+    (value, Span::empty())
+}
+
+fn boxed(expr: Expression) -> Box<Spanned<Expression>> {
+    Box::new(span(expr))
+}
+
+fn ident() -> impl Strategy<Value = Identifier> {
+    prop::sample::select(vec!["a", "b", "foo", "bar_baz", "x1", "print"]).prop_map(Identifier::from)
+}
+
+fn ty() -> impl Strategy<Value = Type> {
+    let named = prop::sample::select(vec!["number", "str", "bool", "nil", "any"])
+        .prop_map(|ty| Type::Named(ty.into()));
+
+    named.prop_recursive(2, 6, 3, |ty| {
+        (
+            prop::collection::vec(ty.clone(), 0..3),
+            prop::option::of(ty),
+        )
+            .prop_map(|(params, returns)| Type::Function {
+                params,
+                returns: returns.map(Box::new),
+            })
+    })
+}
+
+fn variable() -> impl Strategy<Value = Spanned<VariableMeta>> {
+    (ident(), prop::option::of(ty()))
+        .prop_map(|(name, ty)| span(VariableMeta::new(name, ty.map(span), Mutability::Mutable)))
+}
+
+fn block(
+    expr: impl Strategy<Value = Spanned<Expression>>,
+) -> impl Strategy<Value = Spanned<Expression>> {
+    prop::collection::vec(expr, 0..3).prop_map(|exprs| span(Expression::Block(exprs)))
+}
+
+pub fn expression() -> impl Strategy<Value = Spanned<Expression>> {
+    let leaf = prop_oneof![
+        Just(Expression::Nil),
+        any::<bool>().prop_map(Expression::Bool),
+        (-1000i32..1000, 0u8..4)
+            .prop_map(|(n, d)| Expression::Number(f64::from(n) / f64::from(1 << d))),
+        "[a-z ]{0,6}".prop_map(Expression::String),
+        ident().prop_map(Expression::Ident),
+    ]
+    .prop_map(span);
+
+    leaf.prop_recursive(4, 48, 4, |expr| {
+        let binary = prop::sample::select(vec![
+            Operator::Add,
+            Operator::Sub,
+            Operator::Mul,
+            Operator::Div,
+            Operator::Mod,
+            Operator::Assign,
+            Operator::Or,
+            Operator::And,
+            Operator::Nor,
+            Operator::Xor,
+            Operator::Equals,
+            Operator::NotEqual,
+            Operator::Greater,
+            Operator::GreaterOrEqual,
+            Operator::Less,
+            Operator::LessOrEqual,
+        ]);
+        let unary = prop::sample::select(vec![Operator::Sub, Operator::Not]);
+        let or_else = prop_oneof![
+            Just(span(Expression::Nil)),
+            block(expr.clone()),
+            (expr.clone(), block(expr.clone())).prop_map(|(condition, then)| span(
+                Expression::If {
+                    condition: Box::new(condition),
+                    then: Box::new(then),
+                    or_else: boxed(Expression::Nil),
+                }
+            )),
+        ];
+        let function = (
+            prop::collection::vec(variable(), 0..3),
+            prop::option::of(ty()),
+            prop_oneof![block(expr.clone()), expr.clone()],
+        )
+            .prop_map(|(args, returns, body)| {
+                Function::new(args, body).with_returns(returns.map(span))
+            })
+            .boxed();
+
+        prop_oneof![
+            prop::collection::vec(expr.clone(), 0..3).prop_map(|v| span(Expression::Array(v))),
+            prop::collection::vec((ident(), expr.clone()), 0..3)
+                .prop_map(|v| span(Expression::Dictionary(v))),
+            block(expr.clone()),
+            function
+                .clone()
+                .prop_map(|f| span(Expression::Func(Box::new(f)))),
+            (ident(), function).prop_map(|(name, f)| span(Expression::Let {
+                meta: span(VariableMeta::new(name, None, Mutability::Constant)),
+                init: boxed(Expression::Func(Box::new(f))),
+            })),
+            (variable(), expr.clone()).prop_map(|(meta, init)| span(Expression::Let {
+                meta,
+                init: Box::new(init),
+            })),
+            (expr.clone(), block(expr.clone()), or_else).prop_map(|(condition, then, or_else)| {
+                span(Expression::If {
+                    condition: Box::new(condition),
+                    then: Box::new(then),
+                    or_else: Box::new(or_else),
+                })
+            }),
+            (expr.clone(), block(expr.clone())).prop_map(|(condition, then)| span(
+                Expression::While {
+                    condition: Box::new(condition),
+                    then: Box::new(then),
+                }
+            )),
+            (expr.clone(), ident()).prop_map(|(lhs, property)| span(Expression::PropertyAccess {
+                lhs: Box::new(lhs),
+                property,
+            })),
+            (expr.clone(), expr.clone()).prop_map(|(lhs, index)| span(Expression::ArrayIndex {
+                lhs: Box::new(lhs),
+                index: Box::new(index),
+            })),
+            (expr.clone(), prop::collection::vec(expr.clone(), 0..3)).prop_map(
+                |(function, arguments)| span(Expression::Call {
+                    function: Box::new(function),
+                    arguments,
+                })
+            ),
+            (expr.clone(), binary, expr.clone()).prop_map(|(lhs, operator, rhs)| span(
+                Expression::BinaryOp {
+                    lhs: Box::new(lhs),
+                    operator,
+                    rhs: Box::new(rhs),
+                }
+            )),
+            (unary, expr).prop_map(|(operator, rhs)| span(Expression::UnaryOp {
+                operator,
+                rhs: Box::new(rhs),
+            })),
+        ]
+    })
+}
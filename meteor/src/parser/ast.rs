@@ -5,13 +5,17 @@ use crate::{
     runtime::memory::Mutability,
 };
 
-use super::span::Spanned;
+use super::span::{Span, Spanned};
 
 /// AST Representation of a typical program (*one file*)
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Program {
+    /// Directives at the top of the file, before any expression
     directives: Vec<Spanned<Directive>>,
     expressions: Vec<Spanned<Expression>>,
+
+    /// Directives between top level expressions, with the index of the expression they precede
+    attributes: Vec<(usize, Spanned<Directive>)>,
 }
 
 /// AST Representation of a Directive
@@ -25,10 +29,18 @@ pub struct Directive {
 pub enum Type {
     Named(Identifier),
     Generic(Box<Type>, Vec<Identifier>),
+
+    /// `func(params) -> returns`, without a return type the function may return anything
+    Function {
+        params: Vec<Type>,
+        returns: Option<Box<Type>>,
+    },
 }
 
 /// AST Representation of a variable declaration,
-/// with a name and optional type (unevaluated, so just a identifier binding if provided)
+/// with a name and optional type (unevaluated, so just a identifier binding if provided).
+///
+/// Its span always starts with the name.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct VariableMeta {
     name: Identifier,
@@ -39,6 +51,7 @@ pub struct VariableMeta {
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Function {
     arguments: Vec<Spanned<VariableMeta>>,
+    returns: Option<Spanned<Type>>,
     body: Spanned<Expression>,
 }
 
@@ -117,27 +130,188 @@ pub enum Expression {
 
 impl Function {
     pub fn new(arguments: Vec<Spanned<VariableMeta>>, body: Spanned<Expression>) -> Self {
-        Self { arguments, body }
+        Self {
+            arguments,
+            returns: None,
+            body,
+        }
+    }
+
+    #[must_use]
+    pub fn with_returns(mut self, returns: Option<Spanned<Type>>) -> Self {
+        self.returns = returns;
+        self
     }
 
     pub fn arguments(&self) -> &[Spanned<VariableMeta>] {
         &self.arguments
     }
 
+    /// Annotated return type
+    pub fn returns(&self) -> Option<&Spanned<Type>> {
+        self.returns.as_ref()
+    }
+
     pub fn body(&self) -> &Spanned<Expression> {
         &self.body
     }
 }
 
+impl Expression {
+    /// Expressions directly nested in this one, in evaluation order
+    #[must_use]
+    pub fn children(&self) -> Vec<&Spanned<Self>> {
+        match self {
+            Self::Error
+            | Self::Nil
+            | Self::Ident(_)
+            | Self::String(_)
+            | Self::Bool(_)
+            | Self::Number(_) => vec![],
+
+            Self::Array(exprs) | Self::Block(exprs) => exprs.iter().collect(),
+            Self::Dictionary(pairs) => pairs.iter().map(|(_, value)| value).collect(),
+            Self::Func(function) => vec![function.body()],
+            Self::Let { init, .. } => vec![init],
+
+            Self::If {
+                condition,
+                then,
+                or_else,
+            } => vec![condition, then, or_else],
+
+            Self::While { condition, then } => vec![condition, then],
+            Self::PropertyAccess { lhs, .. } => vec![lhs],
+            Self::ArrayIndex { lhs, index } => vec![lhs, index],
+            Self::BinaryOp { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::UnaryOp { rhs, .. } => vec![rhs],
+
+            Self::Call {
+                function,
+                arguments,
+            } => std::iter::once(function.as_ref())
+                .chain(arguments)
+                .collect(),
+        }
+    }
+
+    /// Copy of the expression with every span in it replaced, including the spans of
+    /// declarations and type annotations
+    #[must_use]
+    pub fn map_spans(&self, f: &impl Fn(&Span) -> Span) -> Self {
+        let map = |expr: &Spanned<Self>| (expr.0.map_spans(f), f(&expr.1));
+        let boxed = |expr: &Spanned<Self>| Box::new(map(expr));
+        let meta = |(meta, span): &Spanned<VariableMeta>| {
+            let data_type = meta
+                .data_type
+                .as_ref()
+                .map(|(ty, span)| (ty.clone(), f(span)));
+            (
+                VariableMeta {
+                    data_type,
+                    ..meta.clone()
+                },
+                f(span),
+            )
+        };
+
+        match self {
+            Self::Error => Self::Error,
+            Self::Nil => Self::Nil,
+            Self::Ident(ident) => Self::Ident(ident.clone()),
+            Self::String(str) => Self::String(str.clone()),
+            Self::Bool(b) => Self::Bool(*b),
+            Self::Number(n) => Self::Number(*n),
+            Self::Array(exprs) => Self::Array(exprs.iter().map(map).collect()),
+            Self::Block(exprs) => Self::Block(exprs.iter().map(map).collect()),
+
+            Self::Dictionary(pairs) => Self::Dictionary(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.clone(), map(value)))
+                    .collect(),
+            ),
+
+            Self::Func(function) => Self::Func(Box::new(Function {
+                arguments: function.arguments.iter().map(meta).collect(),
+                returns: function
+                    .returns
+                    .as_ref()
+                    .map(|(ty, span)| (ty.clone(), f(span))),
+                body: map(&function.body),
+            })),
+
+            Self::Let {
+                meta: variable,
+                init,
+            } => Self::Let {
+                meta: meta(variable),
+                init: boxed(init),
+            },
+
+            Self::If {
+                condition,
+                then,
+                or_else,
+            } => Self::If {
+                condition: boxed(condition),
+                then: boxed(then),
+                or_else: boxed(or_else),
+            },
+
+            Self::While { condition, then } => Self::While {
+                condition: boxed(condition),
+                then: boxed(then),
+            },
+
+            Self::PropertyAccess { lhs, property } => Self::PropertyAccess {
+                lhs: boxed(lhs),
+                property: property.clone(),
+            },
+
+            Self::ArrayIndex { lhs, index } => Self::ArrayIndex {
+                lhs: boxed(lhs),
+                index: boxed(index),
+            },
+
+            Self::BinaryOp { lhs, operator, rhs } => Self::BinaryOp {
+                lhs: boxed(lhs),
+                operator: *operator,
+                rhs: boxed(rhs),
+            },
+
+            Self::UnaryOp { operator, rhs } => Self::UnaryOp {
+                operator: *operator,
+                rhs: boxed(rhs),
+            },
+
+            Self::Call {
+                function,
+                arguments,
+            } => Self::Call {
+                function: boxed(function),
+                arguments: arguments.iter().map(map).collect(),
+            },
+        }
+    }
+}
+
 impl Program {
     #[must_use]
     pub fn new(directives: Vec<Spanned<Directive>>, expressions: Vec<Spanned<Expression>>) -> Self {
         Self {
             directives,
             expressions,
+            attributes: vec![],
         }
     }
 
+    #[must_use]
+    pub fn with_attributes(mut self, attributes: Vec<(usize, Spanned<Directive>)>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
     #[must_use]
     pub fn directives(&self) -> &Vec<Spanned<Directive>> {
         &self.directives
@@ -146,6 +320,19 @@ impl Program {
     pub fn expressions(&self) -> &Vec<Spanned<Expression>> {
         &self.expressions
     }
+
+    /// Directives written in front of the top level expression at an index
+    pub fn attributes(&self, index: usize) -> impl Iterator<Item = &Spanned<Directive>> {
+        self.attributes
+            .iter()
+            .filter(move |(i, _)| *i == index)
+            .map(|(_, directive)| directive)
+    }
+
+    #[must_use]
+    pub fn all_attributes(&self) -> &[(usize, Spanned<Directive>)] {
+        &self.attributes
+    }
 }
 
 impl Directive {
@@ -164,6 +351,22 @@ impl Directive {
     pub fn params(&self) -> &Vec<Spanned<Expression>> {
         &self.params
     }
+
+    /// Whether the directive can be written between top level expressions, only the ones setting
+    /// lint levels can, everything else configures the whole file
+    #[must_use]
+    pub fn is_attribute(&self) -> bool {
+        matches!(self.name.name(), "allow" | "warn" | "deny")
+    }
+
+    /// Error message for a directive written between top level expressions
+    #[must_use]
+    pub fn misplaced(&self) -> String {
+        format!(
+            "@{} must come before the first expression, only lint levels can follow it",
+            self.name
+        )
+    }
 }
 
 impl VariableMeta {
@@ -0,0 +1,433 @@
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+    sync::Arc,
+};
+
+use logos::Logos;
+use serde::Serialize;
+
+use crate::parser::{lexer::Token, span::Span, src::SourceId};
+
+/// Source text skipped by the lexer, these use the same patterns as the skip rule of [`Token`]
+#[derive(Logos, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Trivia {
+    #[regex(r"[ \t\n\f]+")]
+    Whitespace,
+
+    #[regex(r"\/\/.*\n")]
+    LineComment,
+
+    #[regex(r"\/\*.*\*\/")]
+    BlockComment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TokenKind<'src> {
+    /// Token that the parser sees
+    Token(Token<'src>),
+
+    /// Whitespace or comment
+    Trivia(Trivia),
+}
+
+/// Leaf of the concrete syntax tree, every byte of the source belongs to exactly one token
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxToken<'src> {
+    kind: TokenKind<'src>,
+    text: &'src str,
+    span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum NodeKind {
+    Program,
+
+    /// `@name` with optional parenthesised parameters
+    Directive,
+
+    /// Bool, number, string or nil literal
+    Literal,
+    Ident,
+    Array,
+    Dictionary,
+
+    /// `key = value` pair inside of a dictionary
+    DictEntry,
+
+    /// `func name(params) body`
+    Function,
+
+    /// Parenthesised list of [`NodeKind::Variable`]s
+    Parameters,
+
+    /// Name with an optional type annotation
+    Variable,
+
+    /// `: type`
+    TypeAnnotation,
+
+    /// `-> type` after the parameters of a function or function type
+    ReturnType,
+
+    /// `func(types) -> type`
+    FunctionType,
+
+    /// Parenthesised parameter types of a [`NodeKind::FunctionType`]
+    TypeList,
+    Let,
+    Block,
+
+    /// `do` followed by a block
+    Do,
+    Parenthesized,
+    If,
+    While,
+    PropertyAccess,
+    ArrayIndex,
+    Call,
+    BinaryOp,
+    UnaryOp,
+
+    /// Tokens the parser could not make sense of
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SyntaxElement<'src> {
+    Node(SyntaxNode<'src>),
+    Token(SyntaxToken<'src>),
+}
+
+/// Node of the concrete syntax tree.
+///
+/// Unlike the AST this keeps every token including trivia, so printing a tree gives back the exact
+/// source it was parsed from. Trivia between two nodes belongs to their parent, so a node always
+/// starts and ends with a significant token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyntaxNode<'src> {
+    kind: NodeKind,
+    children: Vec<SyntaxElement<'src>>,
+}
+
+impl Trivia {
+    #[must_use]
+    pub fn is_comment(self) -> bool {
+        matches!(self, Trivia::LineComment | Trivia::BlockComment)
+    }
+}
+
+impl<'src> SyntaxToken<'src> {
+    #[must_use]
+    pub fn new(kind: TokenKind<'src>, text: &'src str, span: Span) -> Self {
+        Self { kind, text, span }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> &TokenKind<'src> {
+        &self.kind
+    }
+
+    /// Lexer token, or `None` if this is trivia
+    #[must_use]
+    pub fn token(&self) -> Option<&Token<'src>> {
+        match &self.kind {
+            TokenKind::Token(token) => Some(token),
+            TokenKind::Trivia(_) => None,
+        }
+    }
+
+    #[must_use]
+    pub fn trivia(&self) -> Option<Trivia> {
+        match self.kind {
+            TokenKind::Trivia(trivia) => Some(trivia),
+            TokenKind::Token(_) => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_trivia(&self) -> bool {
+        self.trivia().is_some()
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &'src str {
+        self.text
+    }
+
+    #[must_use]
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl<'src> SyntaxNode<'src> {
+    #[must_use]
+    pub fn new(kind: NodeKind, children: Vec<SyntaxElement<'src>>) -> Self {
+        Self { kind, children }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    #[must_use]
+    pub fn children(&self) -> &[SyntaxElement<'src>] {
+        &self.children
+    }
+
+    pub(crate) fn children_mut(&mut self) -> &mut Vec<SyntaxElement<'src>> {
+        &mut self.children
+    }
+
+    /// Direct child nodes
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode<'src>> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Direct child tokens, excluding trivia
+    pub fn significant_tokens(&self) -> impl Iterator<Item = &SyntaxToken<'src>> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Token(token) if !token.is_trivia() => Some(token),
+            _ => None,
+        })
+    }
+
+    /// Every token of this node and its descendants in source order, including trivia
+    #[must_use]
+    pub fn tokens(&self) -> Vec<&SyntaxToken<'src>> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken<'src>>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// Span from the first to the last significant token, `None` for a node with only trivia
+    #[must_use]
+    pub fn span(&self) -> Option<Span> {
+        use chumsky::span::Span as _;
+
+        let tokens = self.tokens();
+        let mut significant = tokens.iter().filter(|token| !token.is_trivia());
+
+        let first = significant.next()?;
+        let last = significant.next_back().unwrap_or(first);
+
+        Some(Span::new(
+            first.span().src(),
+            first.span().range().start..last.span().range().end,
+        ))
+    }
+}
+
+impl Display for SyntaxNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            f.write_str(token.text())?;
+        }
+        Ok(())
+    }
+}
+
+/// Tokens of a source that own its text instead of borrowing it, so they can be kept and
+/// parsed later with [`super::cst_parser::parse_lexed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexed {
+    id: SourceId,
+    text: Arc<str>,
+
+    /// Kind of each token without its text, with where it is in the source
+    tokens: Vec<(TokenKind<'static>, Range<usize>)>,
+}
+
+impl Lexed {
+    #[must_use]
+    pub fn new(id: SourceId, text: impl Into<Arc<str>>) -> Self {
+        let text = text.into();
+        let tokens = lex(id, &text)
+            .into_iter()
+            .map(|token| (token.kind.with_text(""), token.span.range()))
+            .collect();
+
+        Self { id, text, tokens }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> SourceId {
+        self.id
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The same tokens [`lex`] gives for the text
+    #[must_use]
+    pub fn tokens(&self) -> Vec<SyntaxToken<'_>> {
+        use chumsky::span::Span as _;
+
+        self.tokens
+            .iter()
+            .map(|(kind, range)| {
+                let text = &self.text[range.clone()];
+                SyntaxToken::new(
+                    kind.with_text(text),
+                    text,
+                    Span::new(self.id, range.clone()),
+                )
+            })
+            .collect()
+    }
+}
+
+impl TokenKind<'_> {
+    /// Same kind of token with the text of another one
+    fn with_text<'a>(&self, text: &'a str) -> TokenKind<'a> {
+        let token = match self {
+            TokenKind::Trivia(trivia) => return TokenKind::Trivia(*trivia),
+            TokenKind::Token(token) => token,
+        };
+
+        let quoted = text
+            .get(1..text.len().saturating_sub(1))
+            .unwrap_or_default();
+
+        TokenKind::Token(match token {
+            Token::Bool(_) => Token::Bool(text),
+            Token::Number(_) => Token::Number(text),
+            Token::Identifier(_) => Token::Identifier(text),
+            Token::Directive(_) => Token::Directive(text.get(1..).unwrap_or_default()),
+            Token::String(_) => Token::String(quoted),
+            Token::Operator(operator) => Token::Operator(*operator),
+            Token::CurlyBraceOpen => Token::CurlyBraceOpen,
+            Token::CurlyBraceClose => Token::CurlyBraceClose,
+            Token::BracketOpen => Token::BracketOpen,
+            Token::BracketClose => Token::BracketClose,
+            Token::ParenOpen => Token::ParenOpen,
+            Token::ParenClosed => Token::ParenClosed,
+            Token::Colon => Token::Colon,
+            Token::Comma => Token::Comma,
+            Token::Dot => Token::Dot,
+            Token::Semicolon => Token::Semicolon,
+            Token::FatArrow => Token::FatArrow,
+            Token::ThinArrow => Token::ThinArrow,
+            Token::Nil => Token::Nil,
+            Token::Return => Token::Return,
+            Token::If => Token::If,
+            Token::Unless => Token::Unless,
+            Token::Else => Token::Else,
+            Token::While => Token::While,
+            Token::Until => Token::Until,
+            Token::For => Token::For,
+            Token::In => Token::In,
+            Token::Do => Token::Do,
+            Token::Let => Token::Let,
+            Token::Var => Token::Var,
+            Token::Func => Token::Func,
+            Token::Entrypoint => Token::Entrypoint,
+            Token::Error => Token::Error,
+        })
+    }
+}
+
+/// Lexes the source without losing anything, the gaps between the tokens of [`Token::lexer`] are
+/// filled in with [`Trivia`]
+#[must_use]
+pub fn lex<'src>(id: SourceId, source: &'src str) -> Vec<SyntaxToken<'src>> {
+    use chumsky::span::Span as _;
+
+    let mut tokens = vec![];
+    let mut end = 0;
+
+    let trivia = |tokens: &mut Vec<SyntaxToken<'src>>, range: std::ops::Range<usize>| {
+        for (trivia, span) in Trivia::lexer(&source[range.clone()]).spanned() {
+            let span = (range.start + span.start)..(range.start + span.end);
+
+            tokens.push(SyntaxToken::new(
+                match trivia {
+                    Ok(trivia) => TokenKind::Trivia(trivia),
+                    Err(()) => TokenKind::Token(Token::Error),
+                },
+                &source[span.clone()],
+                Span::new(id, span),
+            ));
+        }
+    };
+
+    for (token, span) in Token::lexer(source).spanned() {
+        trivia(&mut tokens, end..span.start);
+        end = span.end;
+
+        tokens.push(SyntaxToken::new(
+            TokenKind::Token(token.unwrap_or(Token::Error)),
+            &source[span.clone()],
+            Span::new(id, span),
+        ));
+    }
+
+    trivia(&mut tokens, end..source.len());
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{lexer::Token, operator::Operator, src::SourceId};
+
+    use super::{lex, Lexed, Trivia};
+
+    #[test]
+    fn lossless() {
+        let source = "// comment\nlet a =  1  \n\n\ta // trailing\n";
+
+        let tokens = lex(SourceId::empty(), source);
+        let text: String = tokens.iter().map(super::SyntaxToken::text).collect();
+        assert_eq!(text, source);
+
+        let kinds: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.trivia(), token.token().cloned()))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (Some(Trivia::LineComment), None),
+                (None, Some(Token::Let)),
+                (Some(Trivia::Whitespace), None),
+                (None, Some(Token::Identifier("a"))),
+                (Some(Trivia::Whitespace), None),
+                (None, Some(Token::Operator(Operator::Assign))),
+                (Some(Trivia::Whitespace), None),
+                (None, Some(Token::Number("1"))),
+                (Some(Trivia::Whitespace), None),
+                (None, Some(Token::Identifier("a"))),
+                (Some(Trivia::Whitespace), None),
+                (Some(Trivia::LineComment), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn owned() {
+        let source = "@import(\"lib/vector\") let a: str = \"a b\" + x // done\n";
+
+        assert_eq!(
+            Lexed::new(SourceId::empty(), source).tokens(),
+            lex(SourceId::empty(), source)
+        );
+    }
+}
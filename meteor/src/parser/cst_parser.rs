@@ -0,0 +1,577 @@
+use chumsky::error::Rich;
+
+use crate::parser::{
+    ast::Directive,
+    cst::{lex, Lexed, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken},
+    lexer::Token,
+    operator::Operator,
+    span::Span,
+    src::SourceId,
+};
+
+pub type ParseError<'src> = Rich<'src, Token<'src>, Span>;
+
+/// Binary operators of each precedence level, loosest first
+const BINARY_LEVELS: &[&[Operator]] = &[
+    &[Operator::Assign],
+    &[Operator::Or],
+    &[Operator::And, Operator::Nor],
+    &[
+        Operator::Equals,
+        Operator::NotEqual,
+        Operator::Greater,
+        Operator::GreaterOrEqual,
+        Operator::Less,
+        Operator::LessOrEqual,
+    ],
+    &[Operator::Xor],
+    &[Operator::Add, Operator::Sub],
+    &[Operator::Mul, Operator::Div, Operator::Mod],
+];
+
+/// Recursive descent parser building a [`SyntaxNode`] tree, follows the same grammar as
+/// [`super::parser::program_parser`].
+///
+/// Errors do not stop the parser, unexpected tokens are put into [`NodeKind::Error`] nodes so that
+/// a tree covering the whole source is always produced.
+struct CstParser<'src> {
+    tokens: Vec<SyntaxToken<'src>>,
+    pos: usize,
+    stack: Vec<SyntaxNode<'src>>,
+    errors: Vec<ParseError<'src>>,
+    eoi: Span,
+}
+
+impl<'src> CstParser<'src> {
+    fn new(id: SourceId, source: &'src str) -> Self {
+        Self::with_tokens(id, source, lex(id, source))
+    }
+
+    fn with_tokens(id: SourceId, source: &'src str, tokens: Vec<SyntaxToken<'src>>) -> Self {
+        use chumsky::span::Span as _;
+
+        Self {
+            tokens,
+            pos: 0,
+            stack: vec![],
+            errors: vec![],
+            eoi: Span::new(id, source.len()..source.len()),
+        }
+    }
+
+    fn peek_token(&self) -> Option<&SyntaxToken<'src>> {
+        self.tokens[self.pos..]
+            .iter()
+            .find(|token| !token.is_trivia())
+    }
+
+    fn peek(&self) -> Option<&Token<'src>> {
+        self.peek_token().and_then(SyntaxToken::token)
+    }
+
+    fn at(&self, token: &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    fn at_identifier(&self) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(_)))
+    }
+
+    fn at_operator(&self, operators: &[Operator]) -> Option<Operator> {
+        match self.peek() {
+            Some(Token::Operator(op)) if operators.contains(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn at_closing(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token::ParenClosed | Token::BracketClose | Token::CurlyBraceClose)
+        )
+    }
+
+    fn push(&mut self, element: SyntaxElement<'src>) {
+        self.stack
+            .last_mut()
+            .expect("Mismatch start/finish")
+            .children_mut()
+            .push(element);
+    }
+
+    /// Adds trivia before the next significant token to the current node
+    fn flush_trivia(&mut self) {
+        while let Some(token) = self.tokens.get(self.pos).filter(|t| t.is_trivia()) {
+            let token = token.clone();
+            self.pos += 1;
+            self.push(SyntaxElement::Token(token));
+        }
+    }
+
+    fn bump(&mut self) {
+        self.flush_trivia();
+
+        if let Some(token) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            self.push(SyntaxElement::Token(token));
+        }
+    }
+
+    fn start(&mut self, kind: NodeKind) {
+        self.flush_trivia();
+        self.stack.push(SyntaxNode::new(kind, vec![]));
+    }
+
+    fn finish(&mut self) {
+        let node = self.stack.pop().expect("Mismatch start/finish");
+        self.push(SyntaxElement::Node(node));
+    }
+
+    /// Starts a node whose first child is the node that was just finished
+    fn wrap(&mut self, kind: NodeKind) {
+        let last = self
+            .stack
+            .last_mut()
+            .and_then(|node| node.children_mut().pop())
+            .expect("Nothing to wrap");
+
+        debug_assert!(
+            matches!(last, SyntaxElement::Node(..)),
+            "Can only wrap nodes"
+        );
+
+        self.stack.push(SyntaxNode::new(kind, vec![last]));
+    }
+
+    fn error(&mut self, expected: &str) {
+        let (found, span) = match self.peek_token() {
+            Some(token) => (format!("'{}'", token.text()), token.span().clone()),
+            None => ("end of input".into(), self.eoi.clone()),
+        };
+
+        self.errors.push(Rich::custom(
+            span,
+            format!("found {found} expected {expected}"),
+        ));
+    }
+
+    fn expect(&mut self, token: &Token, expected: &str) -> bool {
+        if self.at(token) {
+            self.bump();
+            true
+        } else {
+            self.error(expected);
+            false
+        }
+    }
+
+    /// Puts the next token into an error node, unless it closes an enclosing delimiter
+    fn recover(&mut self) {
+        self.start(NodeKind::Error);
+        if !self.at_closing() {
+            self.bump();
+        }
+        self.finish();
+    }
+
+    fn directive(&mut self) {
+        self.start(NodeKind::Directive);
+        self.bump();
+
+        // optional parameters, `@name(a, b)`
+        if self.at(&Token::ParenOpen) {
+            self.bump();
+            self.items();
+            self.expect(&Token::ParenClosed, "')'");
+        }
+
+        self.finish();
+    }
+
+    fn program(mut self) -> (SyntaxNode<'src>, Vec<ParseError<'src>>) {
+        self.stack.push(SyntaxNode::new(NodeKind::Program, vec![]));
+
+        let mut first = true;
+        while self.peek().is_some() {
+            if let Some(&Token::Directive(name)) = self.peek() {
+                let directive = Directive::new(name, vec![]);
+                if !first && !directive.is_attribute() {
+                    let span = self.peek_token().expect("peeked").span().clone();
+                    self.errors.push(Rich::custom(span, directive.misplaced()));
+                }
+
+                self.directive();
+                continue;
+            }
+            first = false;
+
+            let before = self.pos;
+
+            self.expr();
+            if self.at(&Token::Semicolon) {
+                self.bump();
+            }
+
+            if self.pos == before {
+                self.start(NodeKind::Error);
+                self.bump();
+                self.finish();
+            }
+        }
+
+        // trailing trivia
+        while self.pos < self.tokens.len() {
+            self.bump();
+        }
+
+        let program = self.stack.pop().expect("Mismatch start/finish");
+        (program, self.errors)
+    }
+
+    fn block(&mut self) {
+        if !self.at(&Token::CurlyBraceOpen) {
+            self.error("'{'");
+            self.start(NodeKind::Error);
+            self.finish();
+            return;
+        }
+
+        self.start(NodeKind::Block);
+        self.bump();
+
+        while !self.at_closing() {
+            let before = self.pos;
+
+            self.expr();
+            if self.at(&Token::Semicolon) {
+                self.bump();
+            }
+
+            if self.pos == before {
+                break;
+            }
+        }
+
+        self.expect(&Token::CurlyBraceClose, "'}'");
+        self.finish();
+    }
+
+    /// Comma separated expressions, leading and trailing commas are allowed
+    fn items(&mut self) {
+        if self.at(&Token::Comma) {
+            self.bump();
+        }
+
+        while !self.at_closing() {
+            self.expr();
+
+            if self.at(&Token::Comma) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expr(&mut self) {
+        match self.peek() {
+            Some(Token::While | Token::Unless) => {
+                self.start(NodeKind::While);
+                self.bump();
+                self.expr();
+                self.block();
+                self.finish();
+            }
+            Some(Token::If) => self.if_expr(),
+            _ => self.binary(0),
+        }
+    }
+
+    fn if_expr(&mut self) {
+        self.start(NodeKind::If);
+        self.bump();
+        self.expr();
+        self.block();
+
+        if self.at(&Token::Else) {
+            self.bump();
+
+            if self.at(&Token::If) {
+                self.if_expr();
+            } else {
+                self.block();
+            }
+        }
+
+        self.finish();
+    }
+
+    fn binary(&mut self, level: usize) {
+        let Some(operators) = BINARY_LEVELS.get(level) else {
+            self.unary();
+            return;
+        };
+
+        self.binary(level + 1);
+
+        // left associative, each operation wraps everything parsed so far
+        while self.at_operator(operators).is_some() {
+            self.wrap(NodeKind::BinaryOp);
+            self.bump();
+            self.binary(level + 1);
+            self.finish();
+        }
+    }
+
+    fn unary(&mut self) {
+        if self.at_operator(&[Operator::Sub, Operator::Not]).is_some() {
+            self.start(NodeKind::UnaryOp);
+            self.bump();
+            self.unary();
+            self.finish();
+        } else {
+            self.postfix();
+        }
+    }
+
+    fn postfix(&mut self) {
+        self.atom();
+
+        while self.at(&Token::Dot) {
+            self.wrap(NodeKind::PropertyAccess);
+            self.bump();
+            if self.at_identifier() {
+                self.bump();
+            } else {
+                self.error("identifier");
+            }
+            self.finish();
+        }
+
+        while self.at(&Token::ParenOpen) {
+            self.wrap(NodeKind::Call);
+            self.bump();
+            self.items();
+            self.expect(&Token::ParenClosed, "')'");
+            self.finish();
+        }
+
+        while self.at(&Token::BracketOpen) {
+            self.wrap(NodeKind::ArrayIndex);
+            self.bump();
+            self.expr();
+            self.expect(&Token::BracketClose, "']'");
+            self.finish();
+        }
+    }
+
+    fn atom(&mut self) {
+        match self.peek() {
+            Some(Token::Bool(_) | Token::Number(_) | Token::String(_) | Token::Nil) => {
+                self.start(NodeKind::Literal);
+                self.bump();
+                self.finish();
+            }
+
+            Some(Token::Identifier(_)) => {
+                self.start(NodeKind::Ident);
+                self.bump();
+                self.finish();
+            }
+
+            Some(Token::BracketOpen) => {
+                self.start(NodeKind::Array);
+                self.bump();
+                self.items();
+                self.expect(&Token::BracketClose, "']'");
+                self.finish();
+            }
+
+            Some(Token::Func) => self.function(),
+
+            Some(Token::Let) => {
+                self.start(NodeKind::Let);
+                self.bump();
+                self.variable();
+                self.expect(&Token::Operator(Operator::Assign), "'='");
+                self.expr();
+                self.finish();
+            }
+
+            Some(Token::CurlyBraceOpen) => self.dictionary(),
+
+            Some(Token::Do) => {
+                self.start(NodeKind::Do);
+                self.bump();
+                self.block();
+                self.finish();
+            }
+
+            Some(Token::ParenOpen) => {
+                self.start(NodeKind::Parenthesized);
+                self.bump();
+                self.expr();
+                self.expect(&Token::ParenClosed, "')'");
+                self.finish();
+            }
+
+            _ => {
+                self.error("expression");
+                self.recover();
+            }
+        }
+    }
+
+    fn function(&mut self) {
+        self.start(NodeKind::Function);
+        self.bump();
+
+        // optional name, making this a declaration
+        if self.at_identifier() {
+            self.bump();
+        }
+
+        if self.at_identifier() {
+            // single argument without parenthesis
+            self.variable();
+        } else if self.at(&Token::ParenOpen) {
+            self.start(NodeKind::Parameters);
+            self.bump();
+
+            if self.at(&Token::Comma) {
+                self.bump();
+            }
+
+            while self.at_identifier() {
+                self.variable();
+
+                if self.at(&Token::Comma) {
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+
+            self.expect(&Token::ParenClosed, "')'");
+            self.finish();
+        }
+
+        self.return_type();
+
+        if self.at(&Token::FatArrow) {
+            self.bump();
+            self.expr();
+        } else {
+            self.block();
+        }
+
+        self.finish();
+    }
+
+    fn variable(&mut self) {
+        self.start(NodeKind::Variable);
+
+        if self.at_identifier() {
+            self.bump();
+        } else {
+            self.error("identifier");
+        }
+
+        if self.at(&Token::Colon) {
+            self.start(NodeKind::TypeAnnotation);
+            self.bump();
+            self.r#type();
+            self.finish();
+        }
+
+        self.finish();
+    }
+
+    /// Named type or function type
+    fn r#type(&mut self) {
+        // `nil` is a keyword but also names its type
+        if self.at_identifier() || self.at(&Token::Nil) {
+            self.bump();
+            return;
+        }
+
+        if !self.at(&Token::Func) {
+            self.error("type");
+            return;
+        }
+
+        self.start(NodeKind::FunctionType);
+        self.bump();
+
+        self.start(NodeKind::TypeList);
+        self.expect(&Token::ParenOpen, "'('");
+
+        if self.at(&Token::Comma) {
+            self.bump();
+        }
+
+        while self.at_identifier() || self.at(&Token::Nil) || self.at(&Token::Func) {
+            self.r#type();
+
+            if self.at(&Token::Comma) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        self.expect(&Token::ParenClosed, "')'");
+        self.finish();
+
+        self.return_type();
+        self.finish();
+    }
+
+    fn return_type(&mut self) {
+        if self.at(&Token::ThinArrow) {
+            self.start(NodeKind::ReturnType);
+            self.bump();
+            self.r#type();
+            self.finish();
+        }
+    }
+
+    fn dictionary(&mut self) {
+        self.start(NodeKind::Dictionary);
+        self.bump();
+
+        if self.at(&Token::Comma) {
+            self.bump();
+        }
+
+        while self.at_identifier() {
+            self.start(NodeKind::DictEntry);
+            self.bump();
+            self.expect(&Token::Operator(Operator::Assign), "'='");
+            self.expr();
+            self.finish();
+
+            if self.at(&Token::Comma) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        self.expect(&Token::CurlyBraceClose, "'}'");
+        self.finish();
+    }
+}
+
+/// Parses source into a lossless concrete syntax tree, the tree is produced even if there are
+/// errors
+#[must_use]
+pub fn parse_cst(id: SourceId, source: &str) -> (SyntaxNode<'_>, Vec<ParseError<'_>>) {
+    CstParser::new(id, source).program()
+}
+
+/// Parses tokens lexed beforehand, see [`parse_cst`]
+#[must_use]
+pub fn parse_lexed(lexed: &Lexed) -> (SyntaxNode<'_>, Vec<ParseError<'_>>) {
+    CstParser::with_tokens(lexed.id(), lexed.text(), lexed.tokens()).program()
+}
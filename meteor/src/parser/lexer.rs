@@ -44,6 +44,9 @@ pub enum Token<'a> {
     #[token("=>")]
     FatArrow,
 
+    #[token("->")]
+    ThinArrow,
+
     #[token("-",   |_| Operator::Sub)]
     #[token("+",   |_| Operator::Add)]
     #[token("*",   |_| Operator::Mul)]
@@ -144,6 +147,7 @@ impl Display for Token<'_> {
             Token::Dot => f.write_str("."),
             Token::Semicolon => f.write_str(";"),
             Token::FatArrow => f.write_str("=>"),
+            Token::ThinArrow => f.write_str("->"),
             Token::Operator(operator) => f.write_fmt(format_args!("{operator}")),
             Token::Nil => f.write_str("nil"),
             Token::Return => f.write_str("return"),
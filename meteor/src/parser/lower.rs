@@ -0,0 +1,385 @@
+use crate::{
+    parser::{
+        ast::{Directive, Expression, Function, Program, Type, VariableMeta},
+        cst::{NodeKind, SyntaxElement, SyntaxNode, SyntaxToken},
+        lexer::Token,
+        operator::Operator,
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::memory::Mutability,
+};
+
+/// Lowers a concrete syntax tree into the AST, dropping trivia and parenthesis.
+///
+/// Spans are the same as the ones [`super::parser::program_parser`] produces. Nodes the parser
+/// could not make sense of become [`Expression::Error`].
+#[must_use]
+pub fn lower(program: &SyntaxNode) -> Program {
+    let mut directives = vec![];
+    let mut attributes = vec![];
+    let mut expressions = vec![];
+
+    for node in program.nodes() {
+        if node.kind() == NodeKind::Directive {
+            if let Some(Token::Directive(name)) = first_token(node) {
+                let params = node.nodes().map(lower_expr).collect();
+                let directive = (Directive::new(*name, params), span(node));
+
+                if expressions.is_empty() {
+                    directives.push(directive);
+                } else {
+                    attributes.push((expressions.len(), directive));
+                }
+            }
+        } else {
+            expressions.push(lower_expr(node));
+        }
+    }
+
+    Program::new(directives, expressions).with_attributes(attributes)
+}
+
+fn span(node: &SyntaxNode) -> Span {
+    node.span().unwrap_or_else(Span::empty)
+}
+
+fn first_token<'a, 'src>(node: &'a SyntaxNode<'src>) -> Option<&'a Token<'src>> {
+    node.significant_tokens().find_map(SyntaxToken::token)
+}
+
+fn identifier(node: &SyntaxNode) -> Option<Spanned<Identifier>> {
+    node.significant_tokens()
+        .find_map(|token| match token.token() {
+            Some(Token::Identifier(ident)) => {
+                Some((Identifier::from(*ident), token.span().clone()))
+            }
+            _ => None,
+        })
+}
+
+fn operator(node: &SyntaxNode) -> Option<Operator> {
+    node.significant_tokens()
+        .find_map(|token| match token.token() {
+            Some(Token::Operator(op)) => Some(*op),
+            _ => None,
+        })
+}
+
+/// Lowers the nth child node, or an error expression if it is missing
+fn lower_nth(node: &SyntaxNode, n: usize) -> Spanned<Expression> {
+    node.nodes()
+        .nth(n)
+        .map_or_else(|| (Expression::Error, span(node)), lower_expr)
+}
+
+/// Lowers a named type token or function type node
+fn lower_type(element: &SyntaxElement) -> Option<Spanned<Type>> {
+    match element {
+        SyntaxElement::Token(token) => match token.token() {
+            Some(Token::Identifier(ident)) => {
+                Some((Type::Named(Identifier::from(*ident)), token.span().clone()))
+            }
+            Some(Token::Nil) => Some((Type::Named("nil".into()), token.span().clone())),
+            _ => None,
+        },
+        SyntaxElement::Node(node) if node.kind() == NodeKind::FunctionType => {
+            let params = node
+                .nodes()
+                .find(|child| child.kind() == NodeKind::TypeList)
+                .map(|list| {
+                    list.children()
+                        .iter()
+                        .filter_map(lower_type)
+                        .map(|(ty, _)| ty)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let returns = node
+                .nodes()
+                .find(|child| child.kind() == NodeKind::ReturnType)
+                .and_then(lower_annotation)
+                .map(|(ty, _)| Box::new(ty));
+
+            Some((Type::Function { params, returns }, span(node)))
+        }
+        SyntaxElement::Node(_) => None,
+    }
+}
+
+/// Lowers the type inside of a type annotation or return type
+fn lower_annotation(node: &SyntaxNode) -> Option<Spanned<Type>> {
+    node.children().iter().find_map(lower_type)
+}
+
+fn lower_variable(node: &SyntaxNode) -> Option<Spanned<VariableMeta>> {
+    let (name, _) = identifier(node)?;
+
+    let data_type = node
+        .nodes()
+        .find(|child| child.kind() == NodeKind::TypeAnnotation)
+        .and_then(lower_annotation);
+
+    Some((
+        VariableMeta::new(name, data_type, Mutability::Mutable),
+        span(node),
+    ))
+}
+
+fn lower_function(node: &SyntaxNode) -> Expression {
+    let mut arguments = vec![];
+
+    for child in node.nodes() {
+        match child.kind() {
+            NodeKind::Variable => arguments.extend(lower_variable(child)),
+            NodeKind::Parameters => arguments.extend(child.nodes().filter_map(lower_variable)),
+            _ => {}
+        }
+    }
+
+    let returns = node
+        .nodes()
+        .find(|child| child.kind() == NodeKind::ReturnType)
+        .and_then(lower_annotation);
+
+    let body = node
+        .nodes()
+        .filter(|child| {
+            !matches!(
+                child.kind(),
+                NodeKind::Variable | NodeKind::Parameters | NodeKind::ReturnType
+            )
+        })
+        .last()
+        .map_or_else(|| (Expression::Error, span(node)), lower_expr);
+
+    let func = Expression::Func(Box::new(
+        Function::new(arguments, body).with_returns(returns),
+    ));
+
+    // `func name() {}` declares a constant
+    match identifier(node) {
+        Some((name, name_span)) => Expression::Let {
+            meta: (
+                VariableMeta::new(name, None, Mutability::Constant),
+                name_span,
+            ),
+            init: Box::new((func, span(node))),
+        },
+        None => func,
+    }
+}
+
+fn lower_expr(node: &SyntaxNode) -> Spanned<Expression> {
+    let expr = match node.kind() {
+        NodeKind::Literal => match first_token(node) {
+            Some(Token::Bool(b)) => Expression::Bool(b.parse().unwrap()),
+            Some(Token::Number(n)) => Expression::Number(n.parse().unwrap()),
+            Some(Token::String(str)) => Expression::String((*str).into()),
+            Some(Token::Nil) => Expression::Nil,
+            _ => Expression::Error,
+        },
+
+        NodeKind::Ident => match identifier(node) {
+            Some((ident, _)) => Expression::Ident(ident),
+            None => Expression::Error,
+        },
+
+        NodeKind::Array => Expression::Array(node.nodes().map(lower_expr).collect()),
+
+        NodeKind::Dictionary => Expression::Dictionary(
+            node.nodes()
+                .filter_map(|entry| Some((identifier(entry)?.0, lower_nth(entry, 0))))
+                .collect(),
+        ),
+
+        NodeKind::Function => lower_function(node),
+
+        NodeKind::Let => match node.nodes().next().and_then(lower_variable) {
+            Some(meta) => Expression::Let {
+                meta,
+                init: Box::new(lower_nth(node, 1)),
+            },
+            None => Expression::Error,
+        },
+
+        NodeKind::Block => Expression::Block(node.nodes().map(lower_expr).collect()),
+
+        // the span of these is the one of the inner expression
+        NodeKind::Do | NodeKind::Parenthesized => return lower_nth(node, 0),
+
+        NodeKind::If => Expression::If {
+            condition: Box::new(lower_nth(node, 0)),
+            then: Box::new(lower_nth(node, 1)),
+            or_else: Box::new(match node.nodes().nth(2) {
+                Some(or_else) => lower_expr(or_else),
+                None => (Expression::Nil, span(node)),
+            }),
+        },
+
+        NodeKind::While => {
+            let condition = lower_nth(node, 0);
+
+            let condition = if first_token(node) == Some(&Token::Unless) {
+                let span = condition.1.clone();
+                (
+                    Expression::UnaryOp {
+                        operator: Operator::Not,
+                        rhs: Box::new(condition),
+                    },
+                    span,
+                )
+            } else {
+                condition
+            };
+
+            Expression::While {
+                condition: Box::new(condition),
+                then: Box::new(lower_nth(node, 1)),
+            }
+        }
+
+        NodeKind::PropertyAccess => match identifier(node) {
+            Some((property, _)) => Expression::PropertyAccess {
+                lhs: Box::new(lower_nth(node, 0)),
+                property,
+            },
+            None => Expression::Error,
+        },
+
+        NodeKind::ArrayIndex => Expression::ArrayIndex {
+            lhs: Box::new(lower_nth(node, 0)),
+            index: Box::new(lower_nth(node, 1)),
+        },
+
+        NodeKind::Call => Expression::Call {
+            function: Box::new(lower_nth(node, 0)),
+            arguments: node.nodes().skip(1).map(lower_expr).collect(),
+        },
+
+        NodeKind::BinaryOp => match operator(node) {
+            Some(operator) => Expression::BinaryOp {
+                lhs: Box::new(lower_nth(node, 0)),
+                operator,
+                rhs: Box::new(lower_nth(node, 1)),
+            },
+            None => Expression::Error,
+        },
+
+        NodeKind::UnaryOp => match operator(node) {
+            Some(operator) => Expression::UnaryOp {
+                operator,
+                rhs: Box::new(lower_nth(node, 0)),
+            },
+            None => Expression::Error,
+        },
+
+        NodeKind::Program
+        | NodeKind::Directive
+        | NodeKind::DictEntry
+        | NodeKind::Parameters
+        | NodeKind::Variable
+        | NodeKind::TypeAnnotation
+        | NodeKind::ReturnType
+        | NodeKind::FunctionType
+        | NodeKind::TypeList
+        | NodeKind::Error => Expression::Error,
+    };
+
+    (expr, span(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::{
+        error::Rich,
+        input::{Input, Stream},
+        span::Span as _,
+        Parser,
+    };
+    use indoc::indoc;
+    use logos::Logos;
+    use proptest::prelude::*;
+
+    use crate::parser::{
+        arbitrary, ast::Program, cst_parser::parse_cst, lexer::Token, parser::program_parser,
+        printer::print_program, span::Span, src::SourceId,
+    };
+
+    use super::lower;
+
+    /// Parses with the chumsky parser, keeping real spans
+    fn reference(source: &str) -> Result<Program, Vec<Rich<'_, Token<'_>, Span>>> {
+        let id = SourceId::empty();
+        let token_iter = Token::lexer(source)
+            .spanned()
+            .map(move |(tok, span)| (tok.unwrap_or(Token::Error), Span::new(id, span)));
+
+        let token_stream = Stream::from_iter(token_iter)
+            .map(Span::new(id, 0..source.len()), |(t, s): (_, _)| (t, s));
+
+        program_parser().parse(token_stream).into_result()
+    }
+
+    fn check(source: &str) {
+        let (cst, errors) = parse_cst(SourceId::empty(), source);
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(cst.to_string(), source);
+        assert_eq!(lower(&cst), reference(source).unwrap());
+    }
+
+    #[test]
+    fn keeps_trivia() {
+        check(indoc! {r#"
+            @name // directive
+            @import( "lib", )
+
+            // comment
+            func main(a: number, b) {
+                // the answer
+                let x = [1, 2.5, "hi", ]; x = (x)
+
+                if a > b { print(a) } else if a == b { print(nil) }
+                unless x { x = not - x }
+                do { }.y
+            }
+
+            main(1, 2)
+        "#});
+    }
+
+    #[test]
+    fn attributes() {
+        check("@name let a = 1 @allow(unused_variable) let b = 2 @deny");
+
+        let source = "let a = 1 @import(\"lib\")";
+        let (cst, errors) = parse_cst(SourceId::empty(), source);
+        assert_eq!(errors.len(), 1);
+        assert!(reference(source).is_err());
+        assert_eq!(cst.to_string(), source);
+    }
+
+    #[test]
+    fn errors_still_produce_tree() {
+        let source = "let a = (1 + ] \n func b() { c";
+        let (cst, errors) = parse_cst(SourceId::empty(), source);
+
+        assert!(!errors.is_empty());
+        assert!(reference(source).is_err());
+        assert_eq!(cst.to_string(), source);
+    }
+
+    proptest! {
+        #[test]
+        fn same_as_reference(expressions in prop::collection::vec(arbitrary::expression(), 0..4)) {
+            let source = print_program(&Program::new(vec![], expressions));
+            let (cst, errors) = parse_cst(SourceId::empty(), &source);
+
+            prop_assert!(errors.is_empty());
+            prop_assert_eq!(cst.to_string(), source.clone());
+            prop_assert_eq!(Some(lower(&cst)), reference(&source).ok(), "{}", source);
+        }
+    }
+}
@@ -1,7 +1,13 @@
+#[cfg(test)]
+pub(crate) mod arbitrary;
 pub mod ast;
+pub mod cst;
+pub mod cst_parser;
 pub mod lexer;
+pub mod lower;
 pub mod operator;
 pub mod parser;
+pub mod printer;
 pub mod span;
 pub mod src;
 pub mod symbol;
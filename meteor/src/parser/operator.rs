@@ -45,6 +45,39 @@ impl Operator {
                 | Operator::Xor
         )
     }
+
+    /// Operators taking two numbers and producing a number
+    #[must_use]
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Operator::Sub | Operator::Add | Operator::Mul | Operator::Div | Operator::Mod
+        )
+    }
+
+    /// Operators taking two numbers and producing a bool
+    #[must_use]
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Operator::Greater | Operator::GreaterOrEqual | Operator::Less | Operator::LessOrEqual
+        )
+    }
+
+    /// Operators taking two bools and producing a bool
+    #[must_use]
+    pub fn is_logical(&self) -> bool {
+        matches!(
+            self,
+            Operator::Or | Operator::And | Operator::Nor | Operator::Xor
+        )
+    }
+
+    /// Operators taking two values of the same type and producing a bool
+    #[must_use]
+    pub fn is_equality(&self) -> bool {
+        matches!(self, Operator::Equals | Operator::NotEqual)
+    }
 }
 
 impl Display for Operator {
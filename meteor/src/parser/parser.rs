@@ -24,21 +24,55 @@ where
 {
     let expr = expr_parser::<'src, I>();
 
+    let params = expr
+        .clone()
+        .separated_by(just(Token::Comma))
+        .allow_leading()
+        .allow_trailing()
+        .collect::<Vec<_>>()
+        .delimited_by(just(Token::ParenOpen), just(Token::ParenClosed));
+
     let directive = select! {
-        Token::Directive(dir) => Directive::new(dir, vec![])
+        Token::Directive(dir) => dir
     }
-    .map_with(|x, e| (x, e.span()));
-
-    let directives = directive.repeated().collect::<Vec<_>>();
+    .then(params.or_not())
+    .map_with(|(dir, params), e| (Directive::new(dir, params.unwrap_or_default()), e.span()));
+
+    let directives = directive.clone().repeated().collect::<Vec<_>>();
+
+    let attributes = directive
+        .validate(|(directive, span): Spanned<Directive>, _, emitter| {
+            if !directive.is_attribute() {
+                emitter.emit(Rich::custom(span.clone(), directive.misplaced()));
+            }
+            (directive, span)
+        })
+        .repeated()
+        .collect::<Vec<_>>();
 
-    let expressions = expr
-        .then_ignore(just(Token::Semicolon).or_not())
+    let expressions = attributes
+        .clone()
+        .then(expr.then_ignore(just(Token::Semicolon).or_not()))
         .repeated()
         .collect::<Vec<_>>();
 
     directives
         .then(expressions)
-        .map_with(|(directives, exprs), _| Program::new(directives, exprs))
+        .then(attributes)
+        .map_with(|((directives, exprs), trailing), _| {
+            let mut attributes = vec![];
+            let mut expressions = vec![];
+
+            for (i, (attached, expr)) in exprs.into_iter().enumerate() {
+                attributes.extend(attached.into_iter().map(|directive| (i, directive)));
+                expressions.push(expr);
+            }
+
+            let end = expressions.len();
+            attributes.extend(trailing.into_iter().map(|directive| (end, directive)));
+
+            Program::new(directives, expressions).with_attributes(attributes)
+        })
 }
 
 #[allow(clippy::too_many_lines)]
@@ -72,13 +106,38 @@ where
         .delimited_by(just(Token::CurlyBraceOpen), just(Token::CurlyBraceClose))
         .map_with(|pairs, e| (Expression::Dictionary(pairs), e.span()));
 
-        let r#type = ident
-            .clone()
-            .map_with(|ident, e| (Type::Named(ident), e.span()));
+        let r#type = recursive(|r#type| {
+            let params = r#type
+                .clone()
+                .separated_by(just(Token::Comma))
+                .allow_leading()
+                .allow_trailing()
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::ParenOpen), just(Token::ParenClosed));
+
+            // func(number, str) -> bool
+            let function = just(Token::Func)
+                .ignore_then(params)
+                .then(just(Token::ThinArrow).ignore_then(r#type).or_not())
+                .map(|(params, returns)| Type::Function {
+                    params,
+                    returns: returns.map(Box::new),
+                });
+
+            // `nil` is a keyword but also names its type
+            let named = ident
+                .clone()
+                .or(just(Token::Nil).to(Identifier::from("nil")));
+
+            named.map(Type::Named).or(function)
+        })
+        .labelled("type")
+        .map_with(|ty, e| (ty, e.span()))
+        .boxed();
 
         let variable_declare = ident
             .clone()
-            .then(just(Token::Colon).ignore_then(r#type).or_not())
+            .then(just(Token::Colon).ignore_then(r#type.clone()).or_not())
             .map_with(|(name, r#type), e| {
                 (
                     VariableMeta::new(name, r#type, Mutability::Mutable),
@@ -104,7 +163,7 @@ where
             .boxed();
 
         let lambda = just(Token::Func)
-            .ignore_then(ident.clone().or_not())
+            .ignore_then(ident.clone().map_with(|name, e| (name, e.span())).or_not())
             .then(
                 // single/no argument like func x => x*2
                 variable_declare
@@ -120,20 +179,22 @@ where
                         .delimited_by(just(Token::ParenOpen), just(Token::ParenClosed)))
                     .or_not(),
             )
+            .then(just(Token::ThinArrow).ignore_then(r#type.clone()).or_not())
             .then(
                 block
                     .clone()
                     .or(just(Token::FatArrow).ignore_then(expr.clone())),
             )
-            .map_with(|((name, args), expr), e| {
-                let func =
-                    Expression::Func(Box::new(Function::new(args.unwrap_or_default(), expr)));
-                if let Some(name) = name {
+            .map_with(|(((name, args), returns), expr), e| {
+                let func = Expression::Func(Box::new(
+                    Function::new(args.unwrap_or_default(), expr).with_returns(returns),
+                ));
+                if let Some((name, name_span)) = name {
                     (
                         Expression::Let {
                             meta: (
                                 VariableMeta::new(name, None, Mutability::Constant),
-                                e.span(),
+                                name_span,
                             ),
                             init: Box::new((func, e.span())),
                         },
@@ -378,7 +439,10 @@ mod tests {
 
         use crate::parser::span::{Span, Spanned};
         use crate::parser::{
-            ast::Expression, lexer::Token, operator::Operator, parser::expr_parser,
+            ast::{Expression, Type},
+            lexer::Token,
+            operator::Operator,
+            parser::expr_parser,
         };
 
         fn parse(source: &str) -> Result<Spanned<Expression>, Vec<Rich<'_, Token<'_>, Span>>> {
@@ -462,6 +526,27 @@ mod tests {
             );
         }
 
+        #[test]
+        fn function_signature() {
+            let Expression::Func(function) =
+                parse("func(a: number) -> func() -> bool => a").unwrap().0
+            else {
+                panic!("not a function");
+            };
+
+            assert_eq!(
+                function.arguments()[0].0.data_type().map(|ty| &ty.0),
+                Some(&Type::Named("number".into()))
+            );
+            assert_eq!(
+                function.returns().map(|ty| &ty.0),
+                Some(&Type::Function {
+                    params: vec![],
+                    returns: Some(Box::new(Type::Named("bool".into()))),
+                })
+            );
+        }
+
         #[test]
         fn array_index() {
             let a = parse("me[10]").unwrap();
@@ -519,6 +604,23 @@ mod tests {
                 )
             );
 
+            assert_eq!(
+                parse(r#"@import("lib", "other") x"#).unwrap(),
+                Program::new(
+                    vec![(
+                        Directive::new(
+                            "import",
+                            vec![
+                                (Expression::String("lib".into()), Span::empty()),
+                                (Expression::String("other".into()), Span::empty()),
+                            ]
+                        ),
+                        Span::empty()
+                    )],
+                    vec![(Expression::Ident("x".into()), Span::empty())]
+                )
+            );
+
             assert_eq!(
                 parse(indoc! {r#"
                     @x
@@ -534,5 +636,16 @@ mod tests {
                 )
             );
         }
+
+        #[test]
+        fn attributes() {
+            let program = parse("let a = 1 @allow(unused_variable) let b = 2 @deny").unwrap();
+            assert_eq!(program.attributes(1).count(), 1);
+            assert_eq!(program.attributes(2).count(), 1);
+
+            // everything else configures the whole file and would be ignored there
+            assert!(parse("let a = 1 @import(\"lib\")").is_err());
+            assert!(parse("let a = 1 @output(level, number) let b = 2").is_err());
+        }
     }
 }
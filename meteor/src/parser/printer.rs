@@ -0,0 +1,589 @@
+use std::fmt::{self, Display, Write};
+
+use crate::{
+    parser::{
+        ast::{Directive, Expression, Function, Program, Type, VariableMeta},
+        operator::Operator,
+        span::Spanned,
+    },
+    runtime::memory::Mutability,
+};
+
+/// String used for one level of indentation
+const INDENT: &str = "\t";
+
+/// Characters that would continue the previous expression if a statement started with them, these
+/// statements get separated with a semicolon
+pub(crate) const CONTINUATION_CHARS: [char; 3] = ['(', '[', '-'];
+
+/// Binding strength of an expression, in the same order the parser nests its rules (loosest first)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    /// Only valid as a whole expression (`if`, `while`)
+    Statement,
+    Assignment,
+    Or,
+    And,
+    Comparison,
+    Xor,
+    Sum,
+    Product,
+    Unary,
+    Index,
+    Call,
+    Property,
+    Atom,
+}
+
+/// Where an expression is being printed
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    /// Loosest precedence that may be printed without parenthesis
+    min: Precedence,
+
+    /// Whether nothing else of the enclosing expression follows, expressions which greedily parse
+    /// an expression on their right (`let` and `func`) can only be printed bare at the tail
+    tail: bool,
+}
+
+impl Position {
+    const FULL: Position = Position {
+        min: Precedence::Statement,
+        tail: true,
+    };
+
+    fn operand(min: Precedence, tail: bool) -> Self {
+        Self { min, tail }
+    }
+}
+
+/// Converts an AST back into Meteor source code.
+///
+/// The output is canonical (one layout per AST) and parenthesised so that parsing it gives back an
+/// equal AST. Strings are printed as stored, which is their escaped source form.
+#[derive(Debug, Default)]
+pub struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    pub fn program(&mut self, program: &Program) {
+        for (directive, _) in program.directives() {
+            self.directive(directive);
+            self.out.push('\n');
+        }
+
+        if !program.directives().is_empty() && !program.expressions().is_empty() {
+            self.out.push('\n');
+        }
+
+        // directives in between split the statements
+        let mut start = 0;
+        for (index, (directive, _)) in program.all_attributes() {
+            if start < *index {
+                self.statements(&program.expressions()[start..*index], "\n\n");
+                self.out.push_str("\n\n");
+                start = *index;
+            }
+
+            self.directive(directive);
+            self.out.push('\n');
+        }
+
+        if start < program.expressions().len() {
+            self.statements(&program.expressions()[start..], "\n\n");
+            self.out.push('\n');
+        }
+    }
+
+    pub fn directive(&mut self, directive: &Directive) {
+        self.out.push('@');
+        self.out.push_str(directive.name());
+
+        if !directive.params().is_empty() {
+            self.out.push('(');
+            self.list(directive.params());
+            self.out.push(')');
+        }
+    }
+
+    pub fn expression(&mut self, expr: &Spanned<Expression>) {
+        self.expr(expr, Position::FULL);
+    }
+
+    fn statements(&mut self, exprs: &[Spanned<Expression>], separator: &str) {
+        let mut previous_end = None;
+
+        for (i, expr) in exprs.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(separator);
+                self.newline_indent();
+            }
+
+            let start = self.out.len();
+            self.expr(expr, Position::FULL);
+
+            // `a` followed by `(b)` would otherwise be read back as the call `a(b)`
+            if let Some(end) = previous_end {
+                if self.out[start..].starts_with(CONTINUATION_CHARS) {
+                    self.out.insert(end, ';');
+                }
+            }
+            previous_end = Some(self.out.len());
+        }
+    }
+
+    fn newline_indent(&mut self) {
+        // separators end with a newline, the indentation for the line is written here
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn block(&mut self, exprs: &[Spanned<Expression>]) {
+        if exprs.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.newline_indent();
+        self.statements(exprs, "\n");
+        self.indent -= 1;
+        self.out.push('\n');
+        self.newline_indent();
+        self.out.push('}');
+    }
+
+    /// Prints an expression in a place where the grammar expects a block
+    fn body(&mut self, expr: &Spanned<Expression>) {
+        match &expr.0 {
+            Expression::Block(exprs) => self.block(exprs),
+            _ => self.block(std::slice::from_ref(expr)),
+        }
+    }
+
+    fn list(&mut self, exprs: &[Spanned<Expression>]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(expr, Position::FULL);
+        }
+    }
+
+    fn expr(&mut self, expr: &Spanned<Expression>, pos: Position) {
+        let open_ended = matches!(expr.0, Expression::Let { .. } | Expression::Func(..));
+
+        if precedence(&expr.0) < pos.min || (open_ended && !pos.tail) {
+            self.out.push('(');
+            self.expr_unwrapped(expr, Position::FULL);
+            self.out.push(')');
+        } else {
+            self.expr_unwrapped(expr, pos);
+        }
+    }
+
+    fn expr_unwrapped(&mut self, expr: &Spanned<Expression>, pos: Position) {
+        match &expr.0 {
+            Expression::Error => self.out.push_str("/* error */ nil"),
+            Expression::Nil => self.out.push_str("nil"),
+            Expression::Ident(ident) => self.out.push_str(ident),
+            Expression::String(str) => {
+                self.out.push('"');
+                self.out.push_str(str);
+                self.out.push('"');
+            }
+            Expression::Bool(b) => write!(self.out, "{b}").unwrap(),
+            Expression::Number(n) => write!(self.out, "{n}").unwrap(),
+
+            Expression::Array(items) => {
+                self.out.push('[');
+                self.list(items);
+                self.out.push(']');
+            }
+
+            Expression::Dictionary(pairs) => {
+                if pairs.is_empty() {
+                    self.out.push_str("{}");
+                    return;
+                }
+
+                self.out.push_str("{ ");
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    write!(self.out, "{key} = ").unwrap();
+                    self.expr(value, Position::FULL);
+                }
+                self.out.push_str(" }");
+            }
+
+            Expression::Func(function) => self.function(None, function, pos),
+
+            Expression::Let { meta, init } => match &init.0 {
+                // `func name() {}` is sugar for a constant binding without a type
+                Expression::Func(function)
+                    if meta.0.mutablity() == Mutability::Constant
+                        && meta.0.data_type().is_none() =>
+                {
+                    self.function(Some(meta.0.name()), function, pos);
+                }
+                _ => {
+                    self.out.push_str("let ");
+                    self.variable(&meta.0);
+                    self.out.push_str(" = ");
+                    self.expr(init, Position::operand(Precedence::Statement, pos.tail));
+                }
+            },
+
+            Expression::Block(exprs) => {
+                self.out.push_str("do ");
+                self.block(exprs);
+            }
+
+            Expression::If {
+                condition,
+                then,
+                or_else,
+            } => {
+                self.out.push_str("if ");
+                self.expr(condition, Position::FULL);
+                self.out.push(' ');
+                self.body(then);
+
+                match &or_else.0 {
+                    Expression::Nil => {}
+                    Expression::If { .. } => {
+                        self.out.push_str(" else ");
+                        self.expr(or_else, Position::FULL);
+                    }
+                    _ => {
+                        self.out.push_str(" else ");
+                        self.body(or_else);
+                    }
+                }
+            }
+
+            Expression::While { condition, then } => {
+                self.out.push_str("while ");
+                self.expr(condition, Position::FULL);
+                self.out.push(' ');
+                self.body(then);
+            }
+
+            Expression::PropertyAccess { lhs, property } => {
+                self.expr(lhs, Position::operand(Precedence::Property, false));
+                write!(self.out, ".{property}").unwrap();
+            }
+
+            Expression::ArrayIndex { lhs, index } => {
+                self.expr(lhs, Position::operand(Precedence::Index, false));
+                self.out.push('[');
+                self.expr(index, Position::FULL);
+                self.out.push(']');
+            }
+
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.expr(function, Position::operand(Precedence::Call, false));
+                self.out.push('(');
+                self.list(arguments);
+                self.out.push(')');
+            }
+
+            Expression::BinaryOp { lhs, operator, rhs } => {
+                let precedence = binary_precedence(*operator);
+
+                // all binary operators are left associative
+                self.expr(lhs, Position::operand(precedence, false));
+                write!(self.out, " {operator} ").unwrap();
+                self.expr(rhs, Position::operand(next(precedence), pos.tail));
+            }
+
+            Expression::UnaryOp { operator, rhs } => {
+                write!(self.out, "{operator}").unwrap();
+
+                let start = self.out.len();
+                self.expr(rhs, Position::operand(Precedence::Unary, pos.tail));
+
+                // `not` is a keyword, and `-1` would be lexed as a single negative number
+                let operand = &self.out[start..];
+                if *operator != Operator::Sub || operand.starts_with(|c: char| c.is_ascii_digit()) {
+                    self.out.insert(start, ' ');
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, name: Option<&str>, function: &Function, pos: Position) {
+        self.out.push_str("func");
+
+        if let Some(name) = name {
+            self.out.push(' ');
+            self.out.push_str(name);
+        }
+
+        self.out.push('(');
+        for (i, (arg, _)) in function.arguments().iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            self.variable(arg);
+        }
+        self.out.push_str(") ");
+
+        if let Some((returns, _)) = function.returns() {
+            write!(self.out, "-> {returns} ").unwrap();
+        }
+
+        let body = function.body();
+        if let Expression::Block(exprs) = &body.0 {
+            self.block(exprs);
+        } else {
+            self.out.push_str("=> ");
+            self.expr(body, Position::operand(Precedence::Statement, pos.tail));
+        }
+    }
+
+    fn variable(&mut self, meta: &VariableMeta) {
+        self.out.push_str(meta.name());
+
+        if let Some((data_type, _)) = meta.data_type() {
+            write!(self.out, ": {data_type}").unwrap();
+        }
+    }
+}
+
+fn precedence(expr: &Expression) -> Precedence {
+    match expr {
+        Expression::If { .. } | Expression::While { .. } => Precedence::Statement,
+        Expression::BinaryOp { operator, .. } => binary_precedence(*operator),
+        Expression::UnaryOp { .. } => Precedence::Unary,
+        Expression::ArrayIndex { .. } => Precedence::Index,
+        Expression::Call { .. } => Precedence::Call,
+        Expression::PropertyAccess { .. } => Precedence::Property,
+        _ => Precedence::Atom,
+    }
+}
+
+fn binary_precedence(operator: Operator) -> Precedence {
+    match operator {
+        Operator::Mul | Operator::Div | Operator::Mod => Precedence::Product,
+        Operator::Add | Operator::Sub => Precedence::Sum,
+        Operator::Xor => Precedence::Xor,
+        Operator::Equals
+        | Operator::NotEqual
+        | Operator::Greater
+        | Operator::GreaterOrEqual
+        | Operator::Less
+        | Operator::LessOrEqual => Precedence::Comparison,
+        Operator::And | Operator::Nor => Precedence::And,
+        Operator::Or => Precedence::Or,
+        Operator::Assign => Precedence::Assignment,
+        Operator::Not => Precedence::Unary,
+    }
+}
+
+/// Precedence one step tighter than the given one
+fn next(precedence: Precedence) -> Precedence {
+    match precedence {
+        Precedence::Statement => Precedence::Assignment,
+        Precedence::Assignment => Precedence::Or,
+        Precedence::Or => Precedence::And,
+        Precedence::And => Precedence::Comparison,
+        Precedence::Comparison => Precedence::Xor,
+        Precedence::Xor => Precedence::Sum,
+        Precedence::Sum => Precedence::Product,
+        Precedence::Product => Precedence::Unary,
+        Precedence::Unary => Precedence::Index,
+        Precedence::Index => Precedence::Call,
+        Precedence::Call => Precedence::Property,
+        Precedence::Property | Precedence::Atom => Precedence::Atom,
+    }
+}
+
+/// Prints a whole program as Meteor source
+#[must_use]
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer::new();
+    printer.program(program);
+    printer.finish()
+}
+
+/// Prints a single expression as Meteor source
+#[must_use]
+pub fn print_expression(expr: &Spanned<Expression>) -> String {
+    let mut printer = Printer::new();
+    printer.expression(expr);
+    printer.finish()
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&print_program(self))
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Named(name) => f.write_str(name),
+            Type::Generic(base, params) => {
+                write!(f, "{base}<")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(param)?;
+                }
+                f.write_char('>')
+            }
+            Type::Function { params, returns } => {
+                f.write_str("func(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    param.fmt(f)?;
+                }
+                f.write_char(')')?;
+
+                match returns {
+                    Some(returns) => write!(f, " -> {returns}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::{
+        error::Rich,
+        input::{Input, Stream},
+        Parser,
+    };
+    use indoc::indoc;
+    use logos::Logos;
+    use proptest::prelude::*;
+
+    use crate::parser::{
+        arbitrary, ast::Program, lexer::Token, parser::program_parser, span::Span,
+    };
+
+    use super::print_program;
+
+    fn parse(source: &str) -> Result<Program, Vec<Rich<'_, Token<'_>, Span>>> {
+        let token_iter = Token::lexer(source).spanned().map(|(tok, _)| match tok {
+            Ok(tok) => (tok, Span::empty()),
+            Err(()) => (Token::Error, Span::empty()),
+        });
+
+        let token_stream =
+            Stream::from_iter(token_iter).map(Span::empty(), |(t, s): (_, _)| (t, s));
+
+        program_parser().parse(token_stream).into_result()
+    }
+
+    fn roundtrip(source: &str) -> String {
+        let program = parse(source).unwrap();
+        let printed = print_program(&program);
+        assert_eq!(parse(&printed).unwrap(), program, "{printed}");
+        printed
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let printed = roundtrip(indoc! {r#"
+            @name
+            func main(a: number, b) {
+                let x = [1, 2.5, "hi"]
+                if a > b { print(a) } else if a == b { print(nil) } else { print(b) }
+                while x { x = not x }
+            }
+        "#});
+
+        assert_eq!(
+            printed,
+            indoc! {r#"
+                @name
+
+                func main(a: number, b) {
+                	let x = [1, 2.5, "hi"]
+                	if a > b {
+                		print(a)
+                	} else if a == b {
+                		print(nil)
+                	} else {
+                		print(b)
+                	}
+                	while x {
+                		x = not x
+                	}
+                }
+            "#}
+        );
+    }
+
+    #[test]
+    fn parenthesis() {
+        assert_eq!(roundtrip("(a + b) * c"), "(a + b) * c\n");
+        assert_eq!(roundtrip("a - (b - c)"), "a - (b - c)\n");
+        assert_eq!(roundtrip("a - b - c"), "a - b - c\n");
+        assert_eq!(roundtrip("a = (b = c)"), "a = (b = c)\n");
+        assert_eq!(roundtrip("(f(x)).y"), "(f(x)).y\n");
+        assert_eq!(roundtrip("-(1)"), "- 1\n");
+        assert_eq!(roundtrip("(func() => a) + 1"), "(func() => a) + 1\n");
+        assert_eq!(roundtrip("1 + func() => a"), "1 + func() => a\n");
+        assert_eq!(roundtrip("(if a {})[0]"), "(if a {})[0]\n");
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(
+            roundtrip("func f(a: func(number) -> str, b: func()) -> number => a(1)"),
+            "func f(a: func(number) -> str, b: func()) -> number => a(1)\n"
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            roundtrip("@deny(unused_variable) let a = 1 @allow(unused_function) func f() => 1 @warn"),
+            "@deny(unused_variable)\n\nlet a = 1\n\n@allow(unused_function)\nfunc f() => 1\n\n@warn\n"
+        );
+    }
+
+    #[test]
+    fn statement_separation() {
+        assert_eq!(roundtrip("a; [b]"), "a;\n\n[b]\n");
+        assert_eq!(roundtrip("a; -b"), "a;\n\n-b\n");
+    }
+
+    proptest! {
+        #[test]
+        fn print_then_parse(expressions in prop::collection::vec(arbitrary::expression(), 0..4)) {
+            let program = Program::new(vec![], expressions);
+            let printed = print_program(&program);
+
+            prop_assert_eq!(parse(&printed).ok(), Some(program), "{}", printed);
+        }
+    }
+}
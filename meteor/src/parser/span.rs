@@ -22,7 +22,7 @@ impl Span {
 
     pub fn repl(range: Range<usize>) -> Self {
         use chumsky::span::Span;
-        Self::new(SourceId::new("[repl]"), range)
+        Self::new(SourceId::repl(), range)
     }
 
     pub fn src(&self) -> SourceId {
@@ -34,6 +34,14 @@ impl Span {
         self.start()..self.end()
     }
 
+    /// Whether another span lies entirely within this one
+    #[must_use]
+    pub fn contains(&self, other: &Span) -> bool {
+        self.src == other.src
+            && self.range().start <= other.range().start
+            && other.range().end <= self.range().end
+    }
+
     pub fn union(self, other: &Span) -> Self {
         use chumsky::span::Span;
 
@@ -1,13 +1,18 @@
 use core::fmt;
 use std::{
     cmp::Ordering,
-    ops::{Deref, DerefMut},
+    collections::HashMap,
+    ops::{Deref, DerefMut, Range},
+    sync::Arc,
 };
 
+use ariadne::{Cache, Config, IndexType, Source};
 use chumsky::span::SimpleSpan;
 use internment::Intern;
 use serde::{Deserialize, Serialize};
 
+use super::span::Span;
+
 #[derive(Clone)]
 pub struct Sourced<T: ?Sized> {
     inner: Box<T>,
@@ -25,6 +30,223 @@ impl SourceId {
     pub fn new(str: impl Into<String>) -> Self {
         Self(Intern::new(str.into()))
     }
+
+    /// Source of lines entered into a REPL
+    pub fn repl() -> Self {
+        Self::new("[repl]")
+    }
+}
+
+/// Report config matching spans, which are byte offsets rather than character indices
+#[must_use]
+pub fn report_config() -> Config {
+    Config::default().with_index_type(IndexType::Byte)
+}
+
+/// Position of a byte offset, all fields are zero based
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub line: usize,
+
+    /// Column in characters
+    pub column: usize,
+
+    /// Column in UTF-16 code units, which is what LSP clients count in
+    pub utf16_column: usize,
+}
+
+/// Text of a single source along with an index of where its lines start
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    text: Arc<str>,
+
+    /// Byte offset of the start of each line
+    lines: Vec<usize>,
+
+    /// Line index ariadne renders reports with
+    source: Source<Arc<str>>,
+}
+
+/// Owns the text of every source spans can point into
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: HashMap<SourceId, SourceFile>,
+}
+
+impl SourceFile {
+    pub fn new(text: impl Into<Arc<str>>) -> Self {
+        let text: Arc<str> = text.into();
+
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            source: Source::from(text.clone()),
+            lines,
+            text,
+        }
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    #[must_use]
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Byte range of a line, without its line break
+    #[must_use]
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.lines.get(line)?;
+        let end = self
+            .lines
+            .get(line + 1)
+            .map_or(self.text.len(), |next| next - 1);
+
+        Some(start..end)
+    }
+
+    #[must_use]
+    pub fn line(&self, line: usize) -> Option<&str> {
+        self.line_range(line).map(|range| &self.text[range])
+    }
+
+    /// Line and column of a byte offset, `None` if it is out of bounds or inside of a character
+    #[must_use]
+    pub fn location(&self, offset: usize) -> Option<Location> {
+        if !self.text.is_char_boundary(offset) {
+            return None;
+        }
+
+        let line = self.lines.partition_point(|&start| start <= offset) - 1;
+        let before = &self.text[self.lines[line]..offset];
+
+        Some(Location {
+            line,
+            column: before.chars().count(),
+            utf16_column: before.encode_utf16().count(),
+        })
+    }
+
+    /// Byte offset of a line and UTF-16 column, columns past the end of the line are clamped to it
+    #[must_use]
+    pub fn offset(&self, line: usize, utf16_column: usize) -> Option<usize> {
+        let range = self.line_range(line)?;
+
+        let mut units = 0;
+        for (i, c) in self.text[range.clone()].char_indices() {
+            if units >= utf16_column {
+                return Some(range.start + i);
+            }
+            units += c.len_utf16();
+        }
+
+        Some(range.end)
+    }
+}
+
+impl SourceMap {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a source, replacing the previous text of `id`
+    pub fn insert(&mut self, id: SourceId, text: impl Into<Arc<str>>) -> &SourceFile {
+        self.files.insert(id, SourceFile::new(text));
+        &self.files[&id]
+    }
+
+    /// Appends text to a source, creating it if needed, and returns the byte range the text ended
+    /// up at. Meant for the REPL, where every line is part of one growing source.
+    pub fn append(&mut self, id: SourceId, text: &str) -> Range<usize> {
+        let existing = self.text(id).unwrap_or_default();
+        let range = existing.len()..existing.len() + text.len();
+
+        let combined = format!("{existing}{text}");
+        self.insert(id, combined);
+
+        range
+    }
+
+    pub fn remove(&mut self, id: SourceId) -> Option<SourceFile> {
+        self.files.remove(&id)
+    }
+
+    #[must_use]
+    pub fn get(&self, id: SourceId) -> Option<&SourceFile> {
+        self.files.get(&id)
+    }
+
+    #[must_use]
+    pub fn text(&self, id: SourceId) -> Option<&str> {
+        self.get(id).map(SourceFile::text)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SourceId, &SourceFile)> {
+        self.files.iter().map(|(id, file)| (*id, file))
+    }
+
+    /// Start and end location of a span
+    #[must_use]
+    pub fn locations(&self, span: &Span) -> Option<(Location, Location)> {
+        let file = self.get(span.src())?;
+        let range = span.range();
+
+        Some((file.location(range.start)?, file.location(range.end)?))
+    }
+}
+
+impl<S: Into<Arc<str>>> FromIterator<(SourceId, S)> for SourceMap {
+    fn from_iter<T: IntoIterator<Item = (SourceId, S)>>(iter: T) -> Self {
+        Self {
+            files: iter
+                .into_iter()
+                .map(|(id, text)| (id, SourceFile::new(text)))
+                .collect(),
+        }
+    }
+}
+
+impl SourceMap {
+    /// Lookup behind both `Cache` implementations, the one for references cannot reach `&mut self`
+    fn source(&self, id: SourceId) -> Result<&Source<Arc<str>>, Box<dyn fmt::Debug + '_>> {
+        match self.files.get(&id) {
+            Some(file) => Ok(&file.source),
+            None => Err(Box::new(format!("Unknown source {id}"))),
+        }
+    }
+}
+
+// ariadne 0.5.1 turned these return types into `impl Trait`, the boxed ones fit both versions
+#[allow(refining_impl_trait)]
+impl Cache<SourceId> for SourceMap {
+    type Storage = Arc<str>;
+
+    fn fetch(&mut self, id: &SourceId) -> Result<&Source<Arc<str>>, Box<dyn fmt::Debug + '_>> {
+        self.source(*id)
+    }
+
+    fn display<'a>(&self, id: &'a SourceId) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(id))
+    }
+}
+
+#[allow(refining_impl_trait)]
+impl Cache<SourceId> for &SourceMap {
+    type Storage = Arc<str>;
+
+    fn fetch(&mut self, id: &SourceId) -> Result<&Source<Arc<str>>, Box<dyn fmt::Debug + '_>> {
+        self.source(*id)
+    }
+
+    fn display<'a>(&self, id: &'a SourceId) -> Option<Box<dyn fmt::Display + 'a>> {
+        <SourceMap as Cache<SourceId>>::display(self, id)
+    }
 }
 
 impl fmt::Display for SourceId {
@@ -101,3 +323,81 @@ impl<T: fmt::Debug> fmt::Debug for Sourced<T> {
         write!(f, "{:#?} @ {:?}", self.inner, self.span)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ariadne::{Label, Report, ReportKind};
+
+    use super::{Location, SourceFile, SourceId, SourceMap};
+    use crate::parser::span::Span;
+
+    #[test]
+    fn locations() {
+        let file = SourceFile::new("let a = 1\n\nlet é = \"😀\" + b\n");
+
+        assert_eq!(file.line_count(), 4);
+        assert_eq!(file.line(2), Some("let é = \"😀\" + b"));
+        assert_eq!(file.line(3), Some(""));
+        assert_eq!(file.line(4), None);
+
+        let location = |line, column, utf16_column| {
+            Some(Location {
+                line,
+                column,
+                utf16_column,
+            })
+        };
+        assert_eq!(file.location(4), location(0, 4, 4));
+        assert_eq!(file.location(10), location(1, 0, 0));
+
+        // after the emoji, which is 4 bytes and 2 UTF-16 units
+        let b = file.text().find('b').unwrap();
+        assert_eq!(file.location(b), location(2, 14, 15));
+        assert_eq!(file.offset(2, 15), Some(b));
+
+        // inside of the 'é'
+        assert_eq!(file.location(16), None);
+        assert_eq!(file.location(file.text().len() + 1), None);
+
+        // columns past the end are clamped
+        assert_eq!(file.offset(0, 100), Some(9));
+    }
+
+    #[test]
+    fn append() {
+        let mut sources = SourceMap::new();
+        let repl = SourceId::repl();
+
+        assert_eq!(sources.append(repl, "a = 1\n"), 0..6);
+        assert_eq!(sources.append(repl, "b"), 6..7);
+        assert_eq!(sources.text(repl), Some("a = 1\nb"));
+        assert_eq!(
+            sources
+                .locations(&Span::repl(6..7))
+                .map(|(start, _)| start.line),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn renders_any_source() {
+        let lib = SourceId::new("lib.moonbrain");
+        let main = SourceId::new("main.moonbrain");
+        let sources: SourceMap = [(lib, "let ä = oops"), (main, "let a = 1")]
+            .into_iter()
+            .collect();
+
+        let span: Span = chumsky::span::Span::new(lib, 9..13);
+        let mut out = vec![];
+        Report::build(ReportKind::Error, span.clone())
+            .with_config(super::report_config().with_color(false))
+            .with_label(Label::new(span).with_message("here"))
+            .finish()
+            .write(&sources, &mut out)
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("lib.moonbrain:1:9"), "{out}");
+        assert!(out.contains("let ä = oops"), "{out}");
+    }
+}
@@ -1,7 +1,10 @@
 use std::{fmt::Display, ops::Deref, sync::Arc};
 
+use logos::Logos;
 use serde::Serialize;
 
+use super::lexer::Token;
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Identifier(Arc<str>);
 
@@ -19,6 +22,16 @@ impl Identifier {
     pub fn name(&self) -> &str {
         &self.0
     }
+
+    /// Identifier spelled by `text`, `None` unless it lexes as exactly one identifier
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut tokens = Token::lexer(text);
+        match (tokens.next(), tokens.next()) {
+            (Some(Ok(Token::Identifier(name))), None) if name == text => Some(name.into()),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Identifier {
@@ -0,0 +1,873 @@
+//! Networks of chips wired together.
+//!
+//! A circuit advances in steps, each of them ticks every chip once. Chips tick after the chips
+//! they read from, and right before a chip ticks its inputs take the values of the outputs wired
+//! to them, so a value written during a step reaches every chip downstream of it in that same
+//! step. Wires can form loops, including from a chip to itself: the chips of a loop tick after
+//! the chips feeding into it, starting from the chip added first along the loop, which reads the
+//! values the outputs feeding back into it had at the end of the previous step. Chips no wire
+//! puts in order tick in the order they were added.
+//!
+//! [`Circuit::loops`] reports the loops of a circuit, and circuits made with
+//! [`Circuit::without_loops`] reject the wires that would close one.
+//!
+//! Circuits are described in JSON, with the sources of the chips relative to the description:
+//!
+//! ```json
+//! {
+//!     "chips": [
+//!         { "name": "sensor", "source": "sensor.moonbrain" },
+//!         { "name": "alarm", "source": "alarm.moonbrain" }
+//!     ],
+//!     "wires": [{ "from": "sensor.level", "to": "alarm.level" }],
+//!     "fuel": 1000
+//! }
+//! ```
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, VecDeque},
+    fmt,
+    path::PathBuf,
+};
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::parser::symbol::Identifier;
+
+use super::{
+    error::RuntimeError,
+    io::{Socket, WireError},
+    trace::{Recorder, Trace},
+    value::Value,
+    Chip,
+};
+
+/// Port of a chip in a circuit, written `chip.port`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Endpoint {
+    pub chip: Identifier,
+    pub port: Identifier,
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.chip, self.port)
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.split_once('.') {
+            Some((chip, port)) if !chip.is_empty() && !port.is_empty() => Ok(Self {
+                chip: chip.into(),
+                port: port.into(),
+            }),
+            _ => Err(format!("expected `chip.port` but found `{value}`")),
+        }
+    }
+}
+
+impl From<Endpoint> for String {
+    fn from(endpoint: Endpoint) -> Self {
+        endpoint.to_string()
+    }
+}
+
+/// Connection from an output of a chip to an input of another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wire {
+    pub from: Endpoint,
+    pub to: Endpoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChipDescription {
+    pub name: String,
+    pub source: PathBuf,
+}
+
+/// Chips of a circuit and the wires between them, as read from a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Description {
+    pub chips: Vec<ChipDescription>,
+
+    #[serde(default)]
+    pub wires: Vec<Wire>,
+
+    /// Fuel every chip gets for each step, chips are not metered without it
+    #[serde(default)]
+    pub fuel: Option<u64>,
+}
+
+impl Description {
+    /// # Errors
+    ///
+    /// If the contents are not a valid description
+    pub fn parse(contents: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(contents)
+    }
+}
+
+/// Chips along a loop of wires, the first one is repeated at the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChipChain(pub Vec<Identifier>);
+
+impl fmt::Display for ChipChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, chip) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{chip}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum CircuitError {
+    /// Chip {0} is added more than once
+    DuplicateChip(Identifier),
+
+    /// There is no chip named {0}
+    UnknownChip(Identifier),
+
+    /// Input {0} is already wired
+    AlreadyWired(Endpoint),
+
+    /// Cannot wire {from} to {to}: {error}
+    Wire {
+        from: Endpoint,
+        to: Endpoint,
+        error: Box<WireError>,
+    },
+
+    /// Wires form a combinational loop {0}
+    CombinationalLoop(ChipChain),
+
+    /// Chip {chip} failed: {error}
+    Chip {
+        chip: Identifier,
+        error: Box<RuntimeError>,
+    },
+}
+
+/// Carries the value of an output to an input once per step, the input reads the socket of the
+/// relay instead of the output itself
+#[derive(Debug)]
+struct Relay {
+    from: usize,
+    to: usize,
+
+    /// Values the output sends
+    output: watch::Receiver<Value>,
+
+    socket: Socket,
+}
+
+#[derive(Debug, Default)]
+pub struct Circuit {
+    chips: Vec<(Identifier, Chip)>,
+    wires: Vec<Wire>,
+
+    /// Relay of each wire, in the same order
+    relays: Vec<Relay>,
+
+    /// Indices of the chips in the order they tick
+    order: Vec<usize>,
+
+    /// Fuel every chip is refilled with before each step
+    fuel: Option<u64>,
+
+    /// Whether wires that close a loop are rejected
+    acyclic: bool,
+
+    steps: u64,
+
+    /// Records the outputs of every chip, `None` unless asked for
+    recorder: Option<Recorder>,
+}
+
+impl Circuit {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives every chip a budget of fuel for each step, a chip that runs out pauses until the
+    /// next one
+    #[must_use]
+    pub fn with_fuel(mut self, budget: u64) -> Self {
+        self.fuel = Some(budget);
+        self
+    }
+
+    /// Rejects the wires that would close a loop, for circuits whose values have to settle
+    /// within a step
+    #[must_use]
+    pub fn without_loops(mut self) -> Self {
+        self.acyclic = true;
+        self
+    }
+
+    /// Records the values of the outputs of every chip added from now on, see [`Circuit::trace`]
+    #[must_use]
+    pub fn with_recording(mut self) -> Self {
+        self.recorder = Some(Recorder::new());
+        self
+    }
+
+    /// # Errors
+    ///
+    /// If there is a chip of the same name already
+    pub fn add(&mut self, name: impl Into<Identifier>, chip: Chip) -> Result<(), CircuitError> {
+        let name = name.into();
+        if self.index(&name).is_ok() {
+            return Err(CircuitError::DuplicateChip(name));
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            for socket in chip.ports().outputs() {
+                recorder.record(format!("{name}.{}", socket.name()), socket);
+            }
+        }
+
+        // nothing is wired to it yet, so it can go last
+        self.order.push(self.chips.len());
+        self.chips.push((name, chip));
+        Ok(())
+    }
+
+    /// Connects an output of a chip to an input of another
+    ///
+    /// # Errors
+    ///
+    /// If a port does not exist, the input already has a wire, the types of the ports do not
+    /// match or the circuit is [without loops](Circuit::without_loops) and the wire closes one
+    pub fn connect(&mut self, wire: Wire) -> Result<(), CircuitError> {
+        let (from, to) = (self.index(&wire.from.chip)?, self.index(&wire.to.chip)?);
+
+        if self.wires.iter().any(|existing| existing.to == wire.to) {
+            return Err(CircuitError::AlreadyWired(wire.to));
+        }
+
+        if self.acyclic {
+            let mut edges = self.edges();
+            edges.push((from, to));
+            // the circuit had no loops before, so any there is goes through the wire
+            if let Some(chain) = self.chains(&edges).into_iter().next() {
+                return Err(CircuitError::CombinationalLoop(chain));
+            }
+        }
+
+        let error = |error| CircuitError::Wire {
+            from: wire.from.clone(),
+            to: wire.to.clone(),
+            error: Box::new(error),
+        };
+        let output = self.chips[from]
+            .1
+            .ports()
+            .output(&wire.from.port)
+            .ok_or_else(|| error(WireError::UnknownPort(wire.from.port.clone())))?;
+        let input = self.chips[to]
+            .1
+            .ports()
+            .input(&wire.to.port)
+            .ok_or_else(|| error(WireError::UnknownPort(wire.to.port.clone())))?;
+
+        // holds the current value of the output until the next step relays it again
+        let socket = Socket::new(wire.from.port.clone(), output.data_type().clone());
+        socket.send(output.value()).map_err(error)?;
+        input.connect(&socket).map_err(error)?;
+
+        self.wires.push(wire);
+        self.relays.push(Relay {
+            from,
+            to,
+            output: output.subscribe(),
+            socket,
+        });
+        self.order = self.schedule();
+        Ok(())
+    }
+
+    /// Ticks every chip once
+    ///
+    /// # Errors
+    ///
+    /// If a chip fails, the chips after it do not tick in this step
+    pub fn step(&mut self) -> Result<(), CircuitError> {
+        for &i in &self.order {
+            let relays = self.wires.iter().zip(&self.relays);
+            for (wire, relay) in relays.filter(|(_, relay)| relay.to == i) {
+                let value = relay.output.borrow().clone();
+                relay
+                    .socket
+                    .send(value)
+                    .map_err(|error| CircuitError::Wire {
+                        from: wire.from.clone(),
+                        to: wire.to.clone(),
+                        error: Box::new(error),
+                    })?;
+            }
+
+            let (name, chip) = &mut self.chips[i];
+
+            if let Some(budget) = self.fuel {
+                chip.fuel_mut().refill(budget);
+            }
+
+            chip.tick().map_err(|error| CircuitError::Chip {
+                chip: name.clone(),
+                error: Box::new(error),
+            })?;
+        }
+
+        self.steps += 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.sample(self.steps);
+        }
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// If a chip fails, the circuit stops at the step it failed in
+    pub fn run(&mut self, steps: u64) -> Result<(), CircuitError> {
+        for _ in 0..steps {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Number of steps completed
+    #[must_use]
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Values the outputs took at the end of each step, `None` if the circuit is not recording
+    #[must_use]
+    pub fn trace(&self) -> Option<&Trace> {
+        self.recorder.as_ref().map(Recorder::trace)
+    }
+
+    #[must_use]
+    pub fn chip(&self, name: &Identifier) -> Option<&Chip> {
+        self.index(name).ok().map(|i| &self.chips[i].1)
+    }
+
+    /// Chips in the order they were added
+    pub fn chips(&self) -> impl Iterator<Item = (&Identifier, &Chip)> {
+        self.chips.iter().map(|(name, chip)| (name, chip))
+    }
+
+    #[must_use]
+    pub fn wires(&self) -> &[Wire] {
+        &self.wires
+    }
+
+    /// Loops the wires form, one for each group of chips that feed back into each other. Each
+    /// goes around from the chip added first in its group, which is the one that reads the
+    /// values of the previous step.
+    #[must_use]
+    pub fn loops(&self) -> Vec<ChipChain> {
+        self.chains(&self.edges())
+    }
+
+    /// Loops some wires between the chips form, see [`Circuit::loops`]
+    fn chains(&self, edges: &[(usize, usize)]) -> Vec<ChipChain> {
+        let chips = (0..self.chips.len()).collect::<Vec<_>>();
+        components(&chips, edges)
+            .into_iter()
+            .filter_map(|component| cycle(&component, edges))
+            .map(|cycle| ChipChain(cycle.into_iter().map(|i| self.chips[i].0.clone()).collect()))
+            .collect()
+    }
+
+    /// Chips each wire goes from and to
+    fn edges(&self) -> Vec<(usize, usize)> {
+        self.relays
+            .iter()
+            .map(|relay| (relay.from, relay.to))
+            .collect()
+    }
+
+    fn index(&self, name: &Identifier) -> Result<usize, CircuitError> {
+        self.chips
+            .iter()
+            .position(|(chip, _)| chip == name)
+            .ok_or_else(|| CircuitError::UnknownChip(name.clone()))
+    }
+
+    /// Order the chips tick in, see [`sequence`]
+    fn schedule(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.chips.len());
+        sequence(
+            &(0..self.chips.len()).collect::<Vec<_>>(),
+            &self.edges(),
+            &mut order,
+        );
+        order
+    }
+}
+
+/// Appends some chips in the order they tick, each after the chips wired to it. The chips of a
+/// loop tick right after each other, starting from the one added first, which reads the values
+/// fed back into it at the end of the previous step. The rest of the loop is ordered the same
+/// way without the wires into that chip.
+fn sequence(chips: &[usize], edges: &[(usize, usize)], order: &mut Vec<usize>) {
+    for component in components(chips, edges) {
+        order.push(component[0]);
+        sequence(&component[1..], edges, order);
+    }
+}
+
+/// Strongly connected components of some chips and the wires between them. Every component
+/// comes after the ones wired to it, and among the ones that could go next the component with
+/// the earliest added chip goes first. The chips of a component are in the order they were
+/// added.
+fn components(chips: &[usize], edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    /// Tarjan's algorithm, over the positions of the chips in `chips`
+    struct Search {
+        successors: Vec<Vec<usize>>,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        components: Vec<Vec<usize>>,
+        visited: usize,
+    }
+
+    impl Search {
+        fn visit(&mut self, v: usize) {
+            let index = self.visited;
+            self.visited += 1;
+            self.index[v] = Some(index);
+            self.low[v] = index;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+
+            for i in 0..self.successors[v].len() {
+                let w = self.successors[v][i];
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+
+            if self.low[v] == index {
+                let start = self.stack.iter().rposition(|&w| w == v).unwrap_or(0);
+                let component = self.stack.split_off(start);
+                for &w in &component {
+                    self.on_stack[w] = false;
+                }
+                self.components.push(component);
+            }
+        }
+    }
+
+    let position = |chip: usize| chips.iter().position(|&c| c == chip);
+    let edges = edges
+        .iter()
+        .filter_map(|&(from, to)| Some((position(from)?, position(to)?)))
+        .collect::<Vec<_>>();
+
+    let mut search = Search {
+        successors: vec![Vec::new(); chips.len()],
+        index: vec![None; chips.len()],
+        low: vec![0; chips.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; chips.len()],
+        components: Vec::new(),
+        visited: 0,
+    };
+    for &(from, to) in &edges {
+        search.successors[from].push(to);
+    }
+    for v in 0..chips.len() {
+        if search.index[v].is_none() {
+            search.visit(v);
+        }
+    }
+
+    let mut components = search.components;
+    let mut component = vec![0; chips.len()];
+    for (c, members) in components.iter_mut().enumerate() {
+        members.sort_unstable();
+        for &v in members.iter() {
+            component[v] = c;
+        }
+    }
+
+    // wires between the components put them in order, the wires within one do not
+    let mut incoming = vec![0; components.len()];
+    for &(from, to) in &edges {
+        if component[from] != component[to] {
+            incoming[component[to]] += 1;
+        }
+    }
+    let mut ready = (0..components.len())
+        .filter(|&c| incoming[c] == 0)
+        .map(|c| Reverse((components[c][0], c)))
+        .collect::<BinaryHeap<_>>();
+    let mut order = Vec::with_capacity(components.len());
+
+    while let Some(Reverse((_, c))) = ready.pop() {
+        order.push(components[c].iter().map(|&v| chips[v]).collect());
+        for &(from, to) in &edges {
+            if component[from] == c && component[to] != c {
+                incoming[component[to]] -= 1;
+                if incoming[component[to]] == 0 {
+                    ready.push(Reverse((components[component[to]][0], component[to])));
+                }
+            }
+        }
+    }
+
+    order
+}
+
+/// Shortest way along the wires from the first chip of a component back to itself, `None` if
+/// the component is a single chip that is not wired to itself
+fn cycle(component: &[usize], edges: &[(usize, usize)]) -> Option<Vec<usize>> {
+    let start = component[0];
+    let mut previous = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(chip) = queue.pop_front() {
+        for &(from, to) in edges {
+            if from != chip || !component.contains(&to) {
+                continue;
+            }
+
+            if to == start {
+                let mut cycle = vec![start];
+                let mut at = chip;
+                while at != start {
+                    cycle.push(at);
+                    at = previous[&at];
+                }
+                cycle.push(start);
+                cycle.reverse();
+                return Some(cycle);
+            }
+
+            if let Entry::Vacant(entry) = previous.entry(to) {
+                entry.insert(chip);
+                queue.push_back(to);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{src::SourceId, symbol::Identifier};
+
+    use super::{
+        super::{value::Value, Chip},
+        ChipChain, Circuit, CircuitError, Description, Wire,
+    };
+
+    fn chip(source: &str) -> Chip {
+        Chip::new(crate::parse(SourceId::empty(), source).unwrap())
+    }
+
+    fn wire(from: &str, to: &str) -> Wire {
+        Wire {
+            from: from.to_string().try_into().unwrap(),
+            to: to.to_string().try_into().unwrap(),
+        }
+    }
+
+    fn output(circuit: &Circuit, chip: &str, port: &str) -> Value {
+        let chip = circuit.chip(&chip.into()).unwrap();
+        chip.ports().output(&port.into()).unwrap().value()
+    }
+
+    #[test]
+    fn steps() {
+        let mut circuit = Circuit::new();
+        circuit
+            .add(
+                "double",
+                chip("@input(x, number) @output(y, number) func main() => write(\"y\", read(\"x\") * 2)"),
+            )
+            .unwrap();
+        circuit
+            .add(
+                "counter",
+                chip("@output(count, number) let n = 0 func main() { while true { n = n + 1 write(\"count\", n) yield() } }"),
+            )
+            .unwrap();
+        circuit.connect(wire("counter.count", "double.x")).unwrap();
+
+        circuit.run(3).unwrap();
+        assert_eq!(circuit.steps(), 3);
+        assert_eq!(
+            output(&circuit, "double", "y"),
+            Value::Number(6.),
+            "values reach the chips downstream in the same step"
+        );
+    }
+
+    #[test]
+    fn wiring() {
+        let mut circuit = Circuit::new();
+        for name in ["a", "b", "c"] {
+            circuit
+                .add(
+                    name,
+                    chip("@input(x, number) @output(y, number) func main() => nil"),
+                )
+                .unwrap();
+        }
+        circuit
+            .add("text", chip("@input(x, str) func main() => nil"))
+            .unwrap();
+
+        assert!(matches!(
+            circuit.add("a", chip("func main() => nil")),
+            Err(CircuitError::DuplicateChip(_))
+        ));
+        assert!(matches!(
+            circuit.connect(wire("a.y", "d.x")),
+            Err(CircuitError::UnknownChip(name)) if name.name() == "d"
+        ));
+        assert!(matches!(
+            circuit.connect(wire("a.y", "text.x")),
+            Err(CircuitError::Wire { .. })
+        ));
+
+        assert!(matches!(
+            circuit.connect(wire("a.z", "b.x")),
+            Err(CircuitError::Wire { .. })
+        ));
+
+        circuit.connect(wire("a.y", "b.x")).unwrap();
+        circuit.connect(wire("b.y", "c.x")).unwrap();
+        assert!(matches!(
+            circuit.connect(wire("c.y", "b.x")),
+            Err(CircuitError::AlreadyWired(_))
+        ));
+        assert_eq!(circuit.wires().len(), 2, "failed wires are not added");
+
+        circuit.connect(wire("c.y", "a.x")).unwrap();
+        assert_eq!(
+            circuit.order,
+            [0, 1, 2, 3],
+            "the loop starts at the chip added first, chips outside of it tick in the order they were added"
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let mut circuit = Circuit::new().without_loops();
+        for name in ["a", "b", "c"] {
+            circuit
+                .add(
+                    name,
+                    chip("@input(x, number) @output(y, number) func main() => nil"),
+                )
+                .unwrap();
+        }
+
+        circuit.connect(wire("a.y", "b.x")).unwrap();
+        circuit.connect(wire("b.y", "c.x")).unwrap();
+        assert!(matches!(
+            circuit.connect(wire("c.y", "a.x")),
+            Err(CircuitError::CombinationalLoop(ChipChain(chain)))
+                if chain == ["a", "b", "c", "a"].map(Identifier::from)
+        ));
+        assert!(matches!(
+            circuit.connect(wire("a.y", "a.x")),
+            Err(CircuitError::CombinationalLoop(_))
+        ));
+        assert_eq!(
+            circuit.wires().len(),
+            2,
+            "wires closing a loop are not added"
+        );
+        assert_eq!(circuit.loops(), []);
+
+        let mut circuit = Circuit::new();
+        for name in ["a", "b", "c", "d"] {
+            circuit
+                .add(
+                    name,
+                    chip("@input(x, number) @output(y, number) func main() => nil"),
+                )
+                .unwrap();
+        }
+        circuit.connect(wire("a.y", "b.x")).unwrap();
+        circuit.connect(wire("b.y", "c.x")).unwrap();
+        circuit.connect(wire("c.y", "a.x")).unwrap();
+        circuit.connect(wire("d.y", "d.x")).unwrap();
+        assert_eq!(
+            circuit.loops(),
+            [
+                ChipChain(["a", "b", "c", "a"].map(Identifier::from).to_vec()),
+                ChipChain(["d", "d"].map(Identifier::from).to_vec()),
+            ],
+            "loops are reported from the chip added first along them"
+        );
+        assert_eq!(circuit.loops()[0].to_string(), "a -> b -> c -> a",);
+    }
+
+    #[test]
+    fn downstream_of_loop() {
+        let mut circuit = Circuit::new();
+        for name in ["d", "a", "b"] {
+            circuit
+                .add(
+                    name,
+                    chip("@input(x, number) @output(y, number) func main() => write(\"y\", read(\"x\") + 1)"),
+                )
+                .unwrap();
+        }
+        circuit.connect(wire("a.y", "b.x")).unwrap();
+        circuit.connect(wire("b.y", "a.x")).unwrap();
+        circuit
+            .add(
+                "e",
+                chip("@input(x, number) @input(z, number) @output(y, number) func main() => write(\"y\", read(\"x\") + read(\"z\"))"),
+            )
+            .unwrap();
+        circuit.connect(wire("b.y", "d.x")).unwrap();
+        circuit.connect(wire("d.y", "e.x")).unwrap();
+        circuit.connect(wire("a.y", "e.z")).unwrap();
+
+        assert_eq!(
+            circuit.order,
+            [1, 2, 0, 3],
+            "chips fed by a loop tick after it even if they were added first"
+        );
+
+        circuit.run(1).unwrap();
+        assert_eq!(output(&circuit, "b", "y"), Value::Number(2.));
+        assert_eq!(
+            output(&circuit, "d", "y"),
+            Value::Number(3.),
+            "chips downstream of a loop read the values of the same step"
+        );
+        assert_eq!(output(&circuit, "e", "y"), Value::Number(4.));
+    }
+
+    #[test]
+    fn feedback() {
+        let mut circuit = Circuit::new();
+        circuit
+            .add(
+                "increment",
+                chip("@input(x, number) @output(y, number) func main() => write(\"y\", read(\"x\") + 1)"),
+            )
+            .unwrap();
+        circuit
+            .add(
+                "double",
+                chip("@input(x, number) @output(y, number) func main() => write(\"y\", read(\"x\") * 2)"),
+            )
+            .unwrap();
+        circuit.connect(wire("double.y", "increment.x")).unwrap();
+        circuit.connect(wire("increment.y", "double.x")).unwrap();
+
+        circuit.run(2).unwrap();
+        assert_eq!(
+            output(&circuit, "increment", "y"),
+            Value::Number(3.),
+            "values fed back are the ones of the previous step"
+        );
+        assert_eq!(output(&circuit, "double", "y"), Value::Number(6.));
+
+        let mut circuit = Circuit::new();
+        circuit
+            .add(
+                "counter",
+                chip("@input(x, number) @output(y, number) func main() { write(\"y\", read(\"x\") + 1) write(\"y\", read(\"x\") + 1) }"),
+            )
+            .unwrap();
+        circuit.connect(wire("counter.y", "counter.x")).unwrap();
+
+        circuit.run(3).unwrap();
+        assert_eq!(
+            output(&circuit, "counter", "y"),
+            Value::Number(3.),
+            "writes reach the inputs once per step"
+        );
+    }
+
+    #[test]
+    fn fuel() {
+        let mut circuit = Circuit::new().with_fuel(100);
+        circuit
+            .add("spin", chip("func main() { while true { } }"))
+            .unwrap();
+        circuit
+            .add(
+                "done",
+                chip("@output(out) func main() => write(\"out\", 1)"),
+            )
+            .unwrap();
+
+        circuit.run(2).unwrap();
+        assert_eq!(output(&circuit, "done", "out"), Value::Number(1.));
+    }
+
+    #[test]
+    fn recording() {
+        let mut circuit = Circuit::new().with_recording();
+        circuit
+            .add(
+                "counter",
+                chip("@output(count, number) let n = 0 func main() { while true { n = n + 1 write(\"count\", n) wait(2) } }"),
+            )
+            .unwrap();
+
+        circuit.run(4).unwrap();
+        let trace = circuit.trace().unwrap();
+        let changes = trace.signals[0]
+            .changes
+            .iter()
+            .map(|change| (change.time, change.value.as_f64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(trace.signals[0].name, "counter.count");
+        assert_eq!(changes, [(0, 0.), (1, 1.), (3, 2.)]);
+        assert_eq!(trace.end, 4);
+    }
+
+    #[test]
+    fn descriptions() {
+        let description = Description::parse(
+            r#"{
+                "chips": [{ "name": "a", "source": "a.moonbrain" }],
+                "wires": [{ "from": "a.out", "to": "a.in" }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(description.wires[0], wire("a.out", "a.in"));
+        assert_eq!(description.fuel, None);
+
+        assert!(
+            Description::parse(r#"{ "chips": [], "wires": [{ "from": "a", "to": "a.in" }] }"#)
+                .is_err()
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use std::io::Write;
+use std::{fmt, io::Write};
 
-use ariadne::{Cache, Label, Report};
+use ariadne::{Color, Label, Report};
 use displaydoc::Display;
 use lazy_static::lazy_static;
 use thiserror::Error;
@@ -9,22 +9,44 @@ use crate::parser::{
     ast::{self, Expression},
     operator::Operator,
     span::{Span as MSpan, Spanned},
-    src::SourceId,
+    src::{self, SourceMap},
     symbol::Identifier,
 };
 
 use super::value::{Type, Value};
 
+/// Call of a function that had not returned yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Name the function was called by, `None` if it was not called by name, like the
+    /// functions natives call back
+    pub name: Option<Identifier>,
+
+    /// Where the function was called, `None` for `main`, which the chip calls
+    pub call: Option<MSpan>,
+
+    /// Body of the function
+    pub callee: MSpan,
+}
+
+/// Calls that led to an error, the outermost first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallStack(Vec<Frame>);
+
 #[derive(Debug, Display, Error)]
 pub enum RuntimeError {
     /// Failed to find variable of name {name}
     UnknownVariable {
         name: Identifier,
         expr: Spanned<Expression>,
+        stack: CallStack,
     },
 
     /// Unknown type {data_type:?}
-    UnknownType { data_type: Spanned<ast::Type> },
+    UnknownType {
+        data_type: Spanned<ast::Type>,
+        stack: CallStack,
+    },
 
     /// Global 'main' is not a function
     InvalidMainFunc,
@@ -34,31 +56,154 @@ pub enum RuntimeError {
         name: Identifier,
         data_type: Type,
         expr: Spanned<Expression>,
+        stack: CallStack,
     },
 
     /// Unsupported operation in expression
-    UnsupportedOperation(Spanned<Expression>),
+    UnsupportedOperation(Spanned<Expression>, CallStack),
 
     /// Unsupported operation in expression
-    UnsupportedUnaryOperation(Operator, Spanned<Expression>, Value),
+    UnsupportedUnaryOperation(Operator, Spanned<Expression>, Value, CallStack),
 
     /// Invalid property access
     InvalidPropertyAccess {
         obj: Spanned<Expression>,
         property: Identifier,
+        stack: CallStack,
     },
 
     /// Array index out of bounds
     ArrayOutOfBounds {
         array: Spanned<Expression>,
         index: Spanned<Expression>,
+        stack: CallStack,
     },
 
     /// Invalid property access
     CannotIndexIntoType {
         array: Spanned<Expression>,
         data_type: Type,
+        stack: CallStack,
+    },
+
+    /// Expected {expected} arguments but got {found}
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+        span: MSpan,
+        stack: CallStack,
+    },
+
+    /// Expected argument of type {expected} but got {found}
+    ArgumentType {
+        expected: Type,
+        found: Type,
+        span: MSpan,
+        stack: CallStack,
+    },
+
+    /// Ran out of fuel
+    OutOfFuel { span: MSpan, stack: CallStack },
+
+    /// Calls nest deeper than the maximum depth of {depth}
+    StackOverflow {
+        depth: usize,
+        span: MSpan,
+        stack: CallStack,
+    },
+
+    /// Cannot pause inside a function called by a native function
+    CannotPause { span: MSpan, stack: CallStack },
+
+    /// Ports are declared with a name and an optional type
+    InvalidPort { span: MSpan, stack: CallStack },
+
+    /// Port {name} is declared more than once
+    DuplicatePort {
+        name: Identifier,
+        span: MSpan,
+        stack: CallStack,
     },
+
+    /// Cannot call a value of type {data_type}
+    NotCallable {
+        data_type: Type,
+        span: MSpan,
+        stack: CallStack,
+    },
+
+    /// Native function {name} failed: {message}
+    Native {
+        name: Identifier,
+        message: String,
+        span: MSpan,
+        stack: CallStack,
+    },
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}"),
+            None => f.write_str("anonymous function"),
+        }
+    }
+}
+
+impl CallStack {
+    #[must_use]
+    pub fn new(frames: Vec<Frame>) -> Self {
+        Self(frames)
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> &[Frame] {
+        &self.0
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        self.0.push(frame);
+    }
+
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.0.pop()
+    }
+}
+
+/// Names of the functions, a function calling itself shows up once with how many times it did
+impl fmt::Display for CallStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut frames = self.0.iter().peekable();
+        let mut first = true;
+
+        while let Some(frame) = frames.next() {
+            let mut times = 1;
+            while frames.next_if(|next| next.name == frame.name).is_some() {
+                times += 1;
+            }
+
+            if !first {
+                f.write_str(" -> ")?;
+            }
+            first = false;
+
+            write!(f, "{frame}")?;
+            if times > 1 {
+                write!(f, " ({times} times)")?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl RuntimeError {
@@ -77,12 +222,13 @@ impl RuntimeError {
             } => span,
             RuntimeError::UnknownType {
                 data_type: (_, span),
+                ..
             } => span,
             RuntimeError::MismatchType {
                 expr: (_, span), ..
             } => span,
-            RuntimeError::UnsupportedOperation((_, span)) => span,
-            RuntimeError::UnsupportedUnaryOperation(_, (_, span), _) => span,
+            RuntimeError::UnsupportedOperation((_, span), _) => span,
+            RuntimeError::UnsupportedUnaryOperation(_, (_, span), _, _) => span,
             RuntimeError::InvalidPropertyAccess { obj: (_, span), .. } => span,
             RuntimeError::ArrayOutOfBounds {
                 index: (_, span), ..
@@ -90,22 +236,126 @@ impl RuntimeError {
             RuntimeError::CannotIndexIntoType {
                 array: (_, span), ..
             } => span,
+            RuntimeError::ArgumentCount { span, .. }
+            | RuntimeError::ArgumentType { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::OutOfFuel { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::CannotPause { span, .. }
+            | RuntimeError::InvalidPort { span, .. }
+            | RuntimeError::DuplicatePort { span, .. }
+            | RuntimeError::Native { span, .. } => span,
             RuntimeError::InvalidMainFunc => &EMPTY_SPAN,
         }
     }
 
-    pub fn write(&self, cache: impl Cache<SourceId>, writer: impl Write) {
+    /// Calls that had not returned when the error happened, empty for errors outside of functions
+    #[must_use]
+    pub fn stack(&self) -> &CallStack {
+        lazy_static! {
+            pub static ref EMPTY_STACK: CallStack = CallStack::default();
+        }
+
+        match self {
+            RuntimeError::UnsupportedOperation(_, stack)
+            | RuntimeError::UnsupportedUnaryOperation(_, _, _, stack)
+            | RuntimeError::UnknownVariable { stack, .. }
+            | RuntimeError::UnknownType { stack, .. }
+            | RuntimeError::MismatchType { stack, .. }
+            | RuntimeError::InvalidPropertyAccess { stack, .. }
+            | RuntimeError::ArrayOutOfBounds { stack, .. }
+            | RuntimeError::CannotIndexIntoType { stack, .. }
+            | RuntimeError::ArgumentCount { stack, .. }
+            | RuntimeError::ArgumentType { stack, .. }
+            | RuntimeError::OutOfFuel { stack, .. }
+            | RuntimeError::StackOverflow { stack, .. }
+            | RuntimeError::CannotPause { stack, .. }
+            | RuntimeError::InvalidPort { stack, .. }
+            | RuntimeError::DuplicatePort { stack, .. }
+            | RuntimeError::NotCallable { stack, .. }
+            | RuntimeError::Native { stack, .. } => stack,
+            RuntimeError::InvalidMainFunc => &EMPTY_STACK,
+        }
+    }
+
+    /// Records the calls that led to the error, unless it has a call stack already
+    pub fn trace(&mut self, stack: &CallStack) {
+        if let Some(entry) = self.stack_mut().filter(|entry| entry.is_empty()) {
+            entry.clone_from(stack);
+        }
+    }
+
+    fn stack_mut(&mut self) -> Option<&mut CallStack> {
+        match self {
+            RuntimeError::UnsupportedOperation(_, stack)
+            | RuntimeError::UnsupportedUnaryOperation(_, _, _, stack)
+            | RuntimeError::UnknownVariable { stack, .. }
+            | RuntimeError::UnknownType { stack, .. }
+            | RuntimeError::MismatchType { stack, .. }
+            | RuntimeError::InvalidPropertyAccess { stack, .. }
+            | RuntimeError::ArrayOutOfBounds { stack, .. }
+            | RuntimeError::CannotIndexIntoType { stack, .. }
+            | RuntimeError::ArgumentCount { stack, .. }
+            | RuntimeError::ArgumentType { stack, .. }
+            | RuntimeError::OutOfFuel { stack, .. }
+            | RuntimeError::StackOverflow { stack, .. }
+            | RuntimeError::CannotPause { stack, .. }
+            | RuntimeError::InvalidPort { stack, .. }
+            | RuntimeError::DuplicatePort { stack, .. }
+            | RuntimeError::NotCallable { stack, .. }
+            | RuntimeError::Native { stack, .. } => Some(stack),
+            RuntimeError::InvalidMainFunc => None,
+        }
+    }
+
+    /// Renders the error along with the calls that led to it, the innermost first
+    pub fn write(&self, sources: &SourceMap, writer: impl Write) {
         let span: &MSpan = self.span();
-        Report::build(ariadne::ReportKind::Error, span.clone())
+        let stack = self.stack();
+
+        // recursive calls come from the same place over and over, it is labelled once
+        let mut labelled = Vec::new();
+        let mut calls = Vec::new();
+        for frame in stack.frames().iter().rev() {
+            let Some(call) = &frame.call else { continue };
+            if labelled.contains(&call) {
+                continue;
+            }
+            labelled.push(call);
+
+            let order = i32::try_from(calls.len() + 1).unwrap_or(i32::MAX);
+            calls.push(
+                Label::new(call.clone())
+                    .with_message(format!("{frame} was called here"))
+                    .with_color(Color::Yellow)
+                    .with_order(order),
+            );
+        }
+
+        // the error is somewhere in the body of the innermost function, which names it
+        if let Some(frame) = stack.frames().last() {
+            calls.push(
+                Label::new(frame.callee.clone())
+                    .with_message(format!("in {frame}"))
+                    .with_color(Color::Blue)
+                    .with_order(i32::MAX),
+            );
+        }
+
+        let mut report = Report::build(ariadne::ReportKind::Error, span.clone())
+            .with_config(src::report_config())
             .with_code(3)
             .with_message("Error")
             .with_label(
                 Label::new(span.clone())
                     .with_message(format!("{self}"))
-                    .with_color(ariadne::Color::Red),
+                    .with_color(Color::Red),
             )
-            .finish()
-            .write(cache, writer)
-            .unwrap();
+            .with_labels(calls);
+        if !stack.is_empty() {
+            report.set_note(format!("Call stack: {stack}"));
+        }
+
+        report.finish().write(sources, writer).unwrap();
     }
 }
@@ -0,0 +1,129 @@
+/// Fuel each kind of work costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Costs {
+    /// Evaluating any expression
+    pub node: u64,
+
+    /// Calling a function, on top of evaluating the call
+    pub call: u64,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Self { node: 1, call: 1 }
+    }
+}
+
+/// Budget of work a chip can do before it has to stop, so that a program that never finishes
+/// cannot freeze its host.
+///
+/// Hosts running chips every tick refill the budget before each of them to give programs a
+/// fixed amount of work per tick, and read how much was used to price chips by compute.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fuel {
+    /// `None` if the chip is not metered
+    remaining: Option<u64>,
+
+    /// What the fuel was last filled with, no single charge takes more than that
+    budget: Option<u64>,
+
+    used: u64,
+    costs: Costs,
+}
+
+impl Fuel {
+    /// Fuel that never runs out, work is still counted
+    #[must_use]
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn new(budget: u64) -> Self {
+        Self {
+            remaining: Some(budget),
+            budget: Some(budget),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn with_costs(mut self, costs: Costs) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    #[must_use]
+    pub fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+
+    /// Total fuel charged so far, across refills
+    #[must_use]
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    #[must_use]
+    pub fn costs(&self) -> Costs {
+        self.costs
+    }
+
+    /// Replaces what is left of the budget
+    pub fn refill(&mut self, budget: u64) {
+        self.remaining = Some(budget);
+        self.budget = Some(budget);
+    }
+
+    /// Takes fuel for some work, nothing is taken if there is not enough left for all of it.
+    /// Work costing more than a whole budget takes all of it instead, otherwise a program
+    /// waiting for the refill to cover it would never carry on.
+    #[must_use]
+    pub fn charge(&mut self, cost: u64) -> bool {
+        let cost = self.budget.map_or(cost, |budget| cost.min(budget));
+
+        if let Some(remaining) = &mut self.remaining {
+            let Some(left) = remaining.checked_sub(cost) else {
+                return false;
+            };
+            *remaining = left;
+        }
+
+        self.used = self.used.saturating_add(cost);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Costs, Fuel};
+
+    #[test]
+    fn charge() {
+        let mut fuel = Fuel::new(5).with_costs(Costs { node: 2, call: 3 });
+        assert!(fuel.charge(fuel.costs().call));
+        assert!(!fuel.charge(fuel.costs().call), "only 2 left");
+        assert!(fuel.charge(fuel.costs().node));
+        assert_eq!((fuel.remaining(), fuel.used()), (Some(0), 5));
+
+        fuel.refill(1);
+        assert!(fuel.charge(1));
+        assert_eq!(fuel.used(), 6);
+
+        let mut unlimited = Fuel::unlimited();
+        assert!(unlimited.charge(u64::MAX));
+        assert!(unlimited.charge(u64::MAX));
+        assert_eq!(unlimited.remaining(), None);
+        assert_eq!(unlimited.used(), u64::MAX, "usage saturates");
+
+        let mut fuel = Fuel::new(3);
+        assert!(fuel.charge(1));
+        assert!(!fuel.charge(10));
+        fuel.refill(4);
+        assert!(
+            fuel.charge(10),
+            "work costing more than the budget takes all of it"
+        );
+        assert_eq!((fuel.remaining(), fuel.used()), (Some(0), 5));
+    }
+}
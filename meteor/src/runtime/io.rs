@@ -1,17 +1,48 @@
+//! Ports chips exchange values through.
+//!
+//! A program declares its ports with directives at the top of the file, `@input(name, type)` and
+//! `@output(name, type)`, the type defaults to `any`. It reads its inputs with `read("name")` and
+//! writes its outputs with `write("name", value)`, and hosts connect an output of one chip to an
+//! input of another with [`Sink::connect`].
+
+use std::{cell::RefCell, collections::HashSet};
+
 use tokio::sync::watch;
 
-use super::value::{Type, Value};
+use crate::parser::{
+    ast::{self, Directive, Expression, Program},
+    span::{Span, Spanned},
+    symbol::Identifier,
+};
+
+use super::{
+    error::{CallStack, RuntimeError},
+    value::{Type, Value},
+};
 
+/// Name of the directive declaring an input
+pub const INPUT: &str = "input";
+
+/// Name of the directive declaring an output
+pub const OUTPUT: &str = "output";
+
+/// Output of a chip, any number of inputs can be connected to it
 #[derive(Debug)]
 pub struct Socket {
+    name: Identifier,
     data_type: Type,
     receiver: watch::Receiver<Value>,
     transceiver: watch::Sender<Value>,
 }
 
+/// Input of a chip, it reads the latest value sent to the socket it is connected to
 #[derive(Debug)]
 pub struct Sink {
+    name: Identifier,
     data_type: Type,
+
+    /// Changed through a shared reference, since running programs hold on to their ports
+    source: RefCell<Option<watch::Receiver<Value>>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -19,21 +50,44 @@ pub enum WireError {
     #[error("Incorrect type provided")]
     IncorrectType,
 
+    #[error("Cannot connect an output of type {output} to an input of type {input}")]
+    MismatchedTypes { output: Type, input: Type },
+
+    #[error("Unknown port {0}")]
+    UnknownPort(Identifier),
+
     #[error(transparent)]
     ChannelClosed(#[from] watch::error::SendError<Value>),
 }
 
 impl Socket {
-    pub fn new(data_type: Type) -> Self {
+    pub fn new(name: impl Into<Identifier>, data_type: Type) -> Self {
         let (transceiver, receiver) = watch::channel(data_type.default());
 
         Self {
+            name: name.into(),
             data_type,
             receiver,
             transceiver,
         }
     }
 
+    #[must_use]
+    pub fn name(&self) -> &Identifier {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn data_type(&self) -> &Type {
+        &self.data_type
+    }
+
+    /// Last value sent, the default of the type until something is
+    #[must_use]
+    pub fn value(&self) -> Value {
+        self.receiver.borrow().clone()
+    }
+
     pub fn send(&self, value: Value) -> Result<(), WireError> {
         let coerced = value
             .try_coerce(&self.data_type)
@@ -43,4 +97,269 @@ impl Socket {
 
         Ok(())
     }
+
+    /// Receiver of the values sent from now on, seeing the last one as already read
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Value> {
+        self.transceiver.subscribe()
+    }
+}
+
+impl Sink {
+    pub fn new(name: impl Into<Identifier>, data_type: Type) -> Self {
+        Self {
+            name: name.into(),
+            data_type,
+            source: RefCell::new(None),
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &Identifier {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn data_type(&self) -> &Type {
+        &self.data_type
+    }
+
+    /// Reads from a socket from now on, replacing the one it was connected to before
+    ///
+    /// # Errors
+    ///
+    /// If the socket sends values the input does not accept
+    pub fn connect(&self, socket: &Socket) -> Result<(), WireError> {
+        if self.data_type != Type::Any && self.data_type != socket.data_type {
+            return Err(WireError::MismatchedTypes {
+                output: socket.data_type.clone(),
+                input: self.data_type.clone(),
+            });
+        }
+
+        *self.source.borrow_mut() = Some(socket.subscribe());
+        Ok(())
+    }
+
+    pub fn disconnect(&self) {
+        *self.source.borrow_mut() = None;
+    }
+
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.source.borrow().is_some()
+    }
+
+    /// Latest value of the connected socket, the default of the type while there is none
+    #[must_use]
+    pub fn value(&self) -> Value {
+        match &*self.source.borrow() {
+            Some(receiver) => receiver.borrow().clone(),
+            None => self.data_type.default(),
+        }
+    }
+}
+
+/// Ports of a chip, in the order its program declares them
+#[derive(Debug, Default)]
+pub struct Ports {
+    inputs: Vec<Sink>,
+    outputs: Vec<Socket>,
+}
+
+impl Ports {
+    /// Ports the directives of a program declare, invalid declarations are left out and only
+    /// fail once the program runs, see [`Ports::check`]
+    #[must_use]
+    pub fn new(program: &Program) -> Self {
+        let mut ports = Self::default();
+
+        for declaration in declarations(program).flatten() {
+            let name = &declaration.name;
+            if ports.input(name).is_some() || ports.output(name).is_some() {
+                continue;
+            }
+
+            match declaration.kind {
+                PortKind::Input => ports
+                    .inputs
+                    .push(Sink::new(declaration.name, declaration.data_type)),
+                PortKind::Output => ports
+                    .outputs
+                    .push(Socket::new(declaration.name, declaration.data_type)),
+            }
+        }
+
+        ports
+    }
+
+    /// Fails on the first invalid port declaration of a program
+    ///
+    /// # Errors
+    ///
+    /// If a declaration has no name, an unknown type, or a name declared before
+    pub fn check(program: &Program) -> super::Result<()> {
+        let mut names = HashSet::new();
+
+        for declaration in declarations(program) {
+            let declaration = declaration?;
+            if !names.insert(declaration.name.clone()) {
+                return Err(RuntimeError::DuplicatePort {
+                    name: declaration.name,
+                    span: declaration.span,
+                    stack: CallStack::default(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn inputs(&self) -> &[Sink] {
+        &self.inputs
+    }
+
+    #[must_use]
+    pub fn outputs(&self) -> &[Socket] {
+        &self.outputs
+    }
+
+    #[must_use]
+    pub fn input(&self, name: &Identifier) -> Option<&Sink> {
+        self.inputs.iter().find(|sink| sink.name() == name)
+    }
+
+    #[must_use]
+    pub fn output(&self, name: &Identifier) -> Option<&Socket> {
+        self.outputs.iter().find(|socket| socket.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortKind {
+    Input,
+    Output,
+}
+
+/// Port as a directive declares it
+struct Declaration {
+    kind: PortKind,
+    name: Identifier,
+    data_type: Type,
+    span: Span,
+}
+
+fn declarations(program: &Program) -> impl Iterator<Item = super::Result<Declaration>> + '_ {
+    program.directives().iter().filter_map(declaration)
+}
+
+/// Port a directive declares, `None` if it is not a port directive
+fn declaration((directive, span): &Spanned<Directive>) -> Option<super::Result<Declaration>> {
+    let kind = match directive.name().name() {
+        INPUT => PortKind::Input,
+        OUTPUT => PortKind::Output,
+        _ => return None,
+    };
+
+    Some(match directive.params().as_slice() {
+        [(Expression::Ident(name), _)] => Ok(Declaration {
+            kind,
+            name: name.clone(),
+            data_type: Type::Any,
+            span: span.clone(),
+        }),
+        [(Expression::Ident(name), _), (Expression::Ident(ty), ty_span)] => {
+            let ty = (ast::Type::Named(ty.clone()), ty_span.clone());
+            Type::resolve(&ty.0)
+                .ok_or(RuntimeError::UnknownType {
+                    data_type: ty,
+                    stack: CallStack::default(),
+                })
+                .map(|data_type| Declaration {
+                    kind,
+                    name: name.clone(),
+                    data_type,
+                    span: span.clone(),
+                })
+        }
+        _ => Err(RuntimeError::InvalidPort {
+            span: span.clone(),
+            stack: CallStack::default(),
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use super::{
+        super::{
+            error::RuntimeError,
+            value::{Type, Value},
+        },
+        Ports, Sink, Socket, WireError,
+    };
+
+    #[test]
+    fn wires() {
+        let socket = Socket::new("level", Type::Number);
+        let sink = Sink::new("level", Type::Number);
+        assert_eq!(
+            sink.value(),
+            Value::Number(0.),
+            "unconnected inputs read the default"
+        );
+
+        sink.connect(&socket).unwrap();
+        socket.send(Value::Bool(true)).unwrap();
+        assert_eq!(
+            sink.value(),
+            Value::Number(1.),
+            "values are coerced to the output type"
+        );
+        assert!(matches!(
+            socket.send("a".into()),
+            Err(WireError::IncorrectType)
+        ));
+
+        assert!(matches!(
+            Sink::new("name", Type::String).connect(&socket),
+            Err(WireError::MismatchedTypes {
+                output: Type::Number,
+                input: Type::String
+            })
+        ));
+        assert!(Sink::new("any", Type::Any).connect(&socket).is_ok());
+    }
+
+    #[test]
+    fn declarations() {
+        let program = |source| crate::parse(SourceId::empty(), source).unwrap();
+
+        let ports = Ports::new(&program(
+            "@input(speed, number) @input(raw) @output(on, bool)",
+        ));
+        let inputs = ports
+            .inputs()
+            .iter()
+            .map(|sink| (sink.name().name(), sink.data_type().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, [("speed", Type::Number), ("raw", Type::Any)]);
+        assert_eq!(ports.outputs()[0].data_type(), &Type::Bool);
+
+        assert!(matches!(
+            Ports::check(&program("@input(\"speed\")")),
+            Err(RuntimeError::InvalidPort { .. })
+        ));
+        assert!(matches!(
+            Ports::check(&program("@output(speed, fast)")),
+            Err(RuntimeError::UnknownType { .. })
+        ));
+        assert!(matches!(
+            Ports::check(&program("@input(speed) @output(speed)")),
+            Err(RuntimeError::DuplicatePort { .. })
+        ));
+    }
 }
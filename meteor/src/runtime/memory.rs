@@ -9,7 +9,7 @@ use crate::{
 };
 
 use super::{
-    error::RuntimeError,
+    error::{CallStack, RuntimeError},
     value::{Type, Value},
 };
 
@@ -121,7 +121,7 @@ impl MemEnviornment {
         expr: &Spanned<Expression>,
     ) -> Result<()> {
         if let Some(var) = self.variables.get_mut(ident) {
-            return if var.value.is_type(var.data_type()) {
+            return if value.is_type(var.data_type()) {
                 var.value = value;
                 Ok(())
             } else {
@@ -129,6 +129,7 @@ impl MemEnviornment {
                     name: ident.clone(),
                     data_type: var.data_type.clone(),
                     expr: expr.clone(),
+                    stack: CallStack::default(),
                 })
             };
         }
@@ -138,6 +139,7 @@ impl MemEnviornment {
             _ => Err(RuntimeError::UnknownVariable {
                 name: ident.clone(),
                 expr: expr.clone(),
+                stack: CallStack::default(),
             }),
         }
     }
@@ -153,6 +155,7 @@ impl MemEnviornment {
             _ => Err(RuntimeError::UnknownVariable {
                 name: name.clone(),
                 expr: expr.clone(),
+                stack: CallStack::default(),
             }),
         }
     }
@@ -1,48 +1,329 @@
 // pub mod array;
+pub mod circuit;
 pub mod collections;
 pub mod error;
+pub mod fuel;
 pub mod io;
 pub mod memory;
+pub mod native;
+pub mod prelude;
+pub mod trace;
 pub mod value;
 
-use std::{borrow::Borrow, cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    pin::{pin, Pin},
+    rc::Rc,
+    sync::Arc,
+    task::{Poll, Wake, Waker},
+};
 
-use error::RuntimeError;
-use io::{Sink, Socket};
-use memory::{Memory, Variable};
+use error::{CallStack, Frame, RuntimeError};
+use fuel::{Costs, Fuel};
+use io::{Ports, WireError};
+use memory::{Memory, Mutability, Variable};
+use native::{Context, NativeError, NativeFunction, Natives};
 use value::{Function, Type, Value};
 
 use crate::parser::{
     ast::{self, Expression, Program},
     operator::Operator,
     span::{Span, Spanned},
+    symbol::Identifier,
 };
 
 pub type Result<T> = std::result::Result<T, error::RuntimeError>;
 
+/// Result of evaluation, the error is boxed since every call nests the futures evaluating it
+/// on the native stack, and large results make each of them take up more of it
+type Eval<T> = std::result::Result<T, Box<RuntimeError>>;
+
+/// Calls a chip can nest by default, see [`Chip::with_max_depth`], enough to recurse over a few
+/// hundred elements
+///
+/// Every call the program makes nests the futures evaluating it on the native stack. Evaluation
+/// moves to a new segment of stack whenever the one it runs on gets low, so
+/// deep calls take up memory rather than overflowing the stack of the host's thread. The goal is
+/// an evaluator that keeps its frames on the heap instead of recursing.
+pub const MAX_DEPTH: usize = 256;
+
+/// Deepest limit hosts should allow, every call takes a few kilobytes of stack in release builds
+/// and tens of them in debug builds
+pub const DEPTH_LIMIT: usize = 4096;
+
+/// Stack left below which evaluation moves to a new segment, more than the polls of the
+/// expressions between two checks take up in a debug build
+const RED_ZONE: usize = 256 << 10;
+
+/// Size of the segments of stack evaluation moves to
+const STACK_SEGMENT: usize = 4 << 20;
+
 #[derive(Debug)]
 pub struct Chip {
-    inputs: Vec<Sink>,
-    outputs: Vec<Socket>,
+    ports: Rc<Ports>,
     program: Rc<Program>,
     memory: Memory,
+    natives: Natives,
+    fuel: Rc<RefCell<Fuel>>,
+
+    /// Calls that can be nested before the program fails
+    max_depth: usize,
+
+    /// Run of the program that paused in a previous tick
+    task: Option<Task>,
+}
+
+/// Where the program stopped at the end of a tick
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tick {
+    /// The program paused and continues from there on the next tick
+    Paused,
+
+    /// The program finished with what `main` returned, the next tick starts it over
+    Finished(Value),
 }
 
 impl Chip {
     pub fn new(program: Program) -> Self {
         Self {
-            inputs: vec![],
-            outputs: vec![],
+            ports: Rc::new(Ports::new(&program)),
             memory: Memory::new(),
             program: Rc::new(program),
+            natives: Natives::std(),
+            fuel: Rc::default(),
+            max_depth: MAX_DEPTH,
+            task: None,
         }
     }
 
+    /// Limits how much work the program can do, it stops with [`RuntimeError::OutOfFuel`] once
+    /// the fuel runs out, or pauses until it is refilled when it runs over ticks
+    #[must_use]
+    pub fn with_fuel(self, fuel: Fuel) -> Self {
+        *self.fuel.borrow_mut() = fuel;
+        self
+    }
+
+    #[must_use]
+    pub fn fuel(&self) -> Ref<'_, Fuel> {
+        RefCell::borrow(&self.fuel)
+    }
+
+    pub fn fuel_mut(&mut self) -> RefMut<'_, Fuel> {
+        self.fuel.borrow_mut()
+    }
+
+    /// Limits how deep calls can nest, a program that calls deeper stops with
+    /// [`RuntimeError::StackOverflow`] rather than taking its host down with it
+    #[must_use]
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    #[must_use]
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    #[must_use]
+    pub fn with_native(mut self, native: NativeFunction) -> Self {
+        self.register(native);
+        self
+    }
+
+    /// Makes a native function available to the program, replacing any builtin of the same name
+    pub fn register(&mut self, native: NativeFunction) {
+        self.natives.register(native);
+    }
+
+    #[must_use]
+    pub fn natives(&self) -> &Natives {
+        &self.natives
+    }
+
+    /// Inputs and outputs the program declares
+    #[must_use]
+    pub fn ports(&self) -> &Ports {
+        &self.ports
+    }
+
+    /// Connects an input of this chip to an output of another, or of itself
+    ///
+    /// # Errors
+    ///
+    /// If either port does not exist, or the output sends values the input does not accept
+    pub fn connect(
+        &self,
+        input: &str,
+        from: &Chip,
+        output: &str,
+    ) -> std::result::Result<(), WireError> {
+        let (input, output) = (Identifier::from(input), Identifier::from(output));
+        let sink = self
+            .ports
+            .input(&input)
+            .ok_or_else(|| WireError::UnknownPort(input.clone()))?;
+        let socket = from
+            .ports
+            .output(&output)
+            .ok_or_else(|| WireError::UnknownPort(output.clone()))?;
+
+        sink.connect(socket)
+    }
+
+    /// Runs the program to the end, `yield` and `wait` do not pause it
     pub fn run(&mut self) -> Result<Value> {
-        let programs = self.program.clone();
+        let program = self.program.clone();
+        let mut machine = self.start(false)?;
+        finish(machine.run(&program)).map_err(|error| *error)
+    }
+
+    /// Runs the program until it pauses, for hosts that call it every frame. The program
+    /// continues where it left off on the next tick, and starts over after it finished or
+    /// failed.
+    ///
+    /// A metered program that runs out of fuel pauses instead of failing, it continues once the
+    /// host refilled the fuel before a later tick.
+    ///
+    /// # Errors
+    ///
+    /// If the program fails, it is not resumed after
+    pub fn tick(&mut self) -> Result<Tick> {
+        let mut task = if let Some(task) = self.task.take() {
+            task
+        } else {
+            let program = self.program.clone();
+            let mut machine = self.start(true)?;
+            Task(Box::pin(async move {
+                machine.run(&program).await.map_err(|error| *error)
+            }))
+        };
+
+        match task.poll() {
+            Poll::Pending => {
+                self.task = Some(task);
+                Ok(Tick::Paused)
+            }
+            Poll::Ready(result) => result.map(Tick::Finished),
+        }
+    }
+
+    /// Machine for a new run of the program, with the natives defined in the global scope
+    fn start(&mut self, ticking: bool) -> Result<Machine> {
+        Ports::check(&self.program)?;
+
+        // natives are globals like any other, the program can shadow them or pass them around
+        for native in self.natives.iter() {
+            let name = native.name().clone();
+            self.memory.define(
+                name.clone(),
+                Variable {
+                    data_type: Type::Any,
+                    mutability: Mutability::Constant,
+                    value: Value::NativeFunction(native.clone()),
+                },
+                &(Expression::Ident(name), Span::empty()),
+            )?;
+        }
+
+        Ok(Machine {
+            memory: self.memory.clone(),
+            ports: self.ports.clone(),
+            fuel: self.fuel.clone(),
+            ticking,
+            natives: 0,
+            waiting: 0,
+            stack: CallStack::default(),
+            max_depth: self.max_depth,
+        })
+    }
+}
+
+/// Run of a program that can be resumed where it paused
+struct Task(Pin<Box<dyn Future<Output = Result<Value>>>>);
+
+impl Task {
+    fn poll(&mut self) -> Poll<Result<Value>> {
+        let waker = Waker::from(Arc::new(Resume));
+        self.0
+            .as_mut()
+            .poll(&mut std::task::Context::from_waker(&waker))
+    }
+}
+
+impl Debug for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Task").finish_non_exhaustive()
+    }
+}
+
+/// Nothing has to be woken, paused programs are resumed by the next tick
+struct Resume;
+
+impl Wake for Resume {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Drives a run that never pauses to its end
+fn finish<T>(future: impl Future<Output = T>) -> T {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Resume));
+    let mut context = std::task::Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(value) = future.as_mut().poll(&mut context) {
+            return value;
+        }
+    }
+}
+
+/// Stops a run for a number of ticks, it is polled once every tick and resumes once they passed
+struct Pause(u64);
+
+impl Future for Pause {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut std::task::Context<'_>) -> Poll<()> {
+        if self.0 == 0 {
+            Poll::Ready(())
+        } else {
+            self.0 -= 1;
+            Poll::Pending
+        }
+    }
+}
+
+/// State of one run of a program, evaluation is asynchronous so that it can pause in the
+/// middle of an expression and pick up from there
+struct Machine {
+    memory: Memory,
+    ports: Rc<Ports>,
+    fuel: Rc<RefCell<Fuel>>,
+
+    /// Whether the run is spread over ticks, otherwise it never pauses
+    ticking: bool,
+
+    /// Natives running at the moment, which have to return before the run can pause
+    natives: usize,
+
+    /// Ticks a native asked to wait for once it returns
+    waiting: u64,
+
+    /// Functions called that have not returned yet
+    stack: CallStack,
+
+    /// Calls the stack can hold
+    max_depth: usize,
+}
 
-        for p in programs.expressions().iter() {
-            self.eval(p)?;
+impl Machine {
+    async fn run(&mut self, program: &Program) -> Eval<Value> {
+        for p in program.expressions().iter() {
+            self.eval(p).await?;
         }
 
         let main = "main".into();
@@ -52,39 +333,92 @@ impl Chip {
             .retrieve(&main, &(Expression::Ident(main.clone()), Span::empty()))?;
 
         match func.value() {
-            Value::Function(func) => self.run_func(func, &[]),
-            _ => Err(RuntimeError::InvalidMainFunc),
+            Value::Function(func) => {
+                let body = &func.inner().body().1;
+                let expected = func.inner().arguments().len();
+                arity(expected == 0, expected, 0, body)?;
+
+                let frame = Frame {
+                    name: Some(main),
+                    call: None,
+                    callee: body.clone(),
+                };
+                self.invoke(func, Vec::new(), frame).await
+            }
+            _ => Err(Box::new(RuntimeError::InvalidMainFunc)),
         }
     }
 
+    /// Asks to pause for a number of ticks once the running native returns
+    fn wait(&mut self, ticks: u64) {
+        self.waiting = ticks;
+    }
+
+    fn costs(&self) -> Costs {
+        RefCell::borrow(&self.fuel).costs()
+    }
+
     fn resolve_type(&self, ty: &Spanned<ast::Type>) -> Result<Type> {
-        match &ty.0 {
-            ast::Type::Named(identifier) => Ok({
-                match identifier.name() {
-                    "int" | "float" | "number" => Type::Number,
-                    "bool" => Type::Bool,
-                    "str" => Type::String,
-                    "nil" => Type::Nil,
-                    "any" => Type::Any,
-                    "dict" => Type::Dictionary {
-                        key: Box::new(Type::Any),
-                        value: Box::new(Type::Any),
-                    },
-                    "array" => Type::Array(Box::new(Type::Any)),
+        Type::resolve(&ty.0).ok_or_else(|| RuntimeError::UnknownType {
+            data_type: ty.clone(),
+            stack: CallStack::default(),
+        })
+    }
 
-                    // TODO: support custom types
-                    _ => {
-                        return Err(RuntimeError::UnknownType {
-                            data_type: ty.clone(),
-                        })
-                    }
-                }
-            }),
-            ast::Type::Generic(..) => todo!(),
+    /// Whether the run can stop here until the next tick
+    fn can_pause(&self) -> bool {
+        self.ticking && self.natives == 0
+    }
+
+    /// Stops the run for a number of ticks, runs that do not go over ticks carry on at once
+    async fn pause(&mut self, ticks: u64, span: &Span) -> Eval<()> {
+        if !self.ticking {
+            return Ok(());
+        }
+
+        if !self.can_pause() {
+            return Err(Box::new(RuntimeError::CannotPause {
+                span: span.clone(),
+                stack: CallStack::default(),
+            }));
         }
+
+        Pause(ticks).await;
+        Ok(())
     }
 
-    fn eval(&mut self, expr: &Spanned<Expression>) -> Result<Value> {
+    async fn charge(&mut self, cost: u64, span: &Span) -> Eval<()> {
+        // a paused run tries again once the host refilled the fuel
+        while !self.fuel.borrow_mut().charge(cost) {
+            if !self.can_pause() {
+                return Err(Box::new(RuntimeError::OutOfFuel {
+                    span: span.clone(),
+                    stack: CallStack::default(),
+                }));
+            }
+
+            Pause(1).await;
+        }
+
+        Ok(())
+    }
+
+    /// Boxed, since it recurses through the futures of the expressions it is made of. It is
+    /// polled on a new segment of stack when the current one runs low, see [`MAX_DEPTH`].
+    fn eval<'a>(
+        &'a mut self,
+        expr: &'a Spanned<Expression>,
+    ) -> impl Future<Output = Eval<Value>> + 'a {
+        let mut future: Pin<Box<dyn Future<Output = Eval<Value>> + 'a>> =
+            Box::pin(self.evaluate(expr));
+        std::future::poll_fn(move |context| {
+            stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || future.as_mut().poll(context))
+        })
+    }
+
+    async fn evaluate(&mut self, expr: &Spanned<Expression>) -> Eval<Value> {
+        self.charge(self.costs().node, &expr.1).await?;
+
         Ok(match &expr.0 {
             Expression::Nil => Value::Nil,
             Expression::Ident(identifier) => {
@@ -93,14 +427,14 @@ impl Chip {
             Expression::String(str) => Value::String(str.clone()),
             Expression::Bool(b) => Value::Bool(*b),
             Expression::Number(n) => Value::Number(*n),
-            Expression::Array(vec) => Value::Array(Rc::new(
-                vec.iter()
-                    .try_fold(Vec::with_capacity(vec.len()), |mut v, x| {
-                        v.push(self.eval(x)?);
-                        Ok(v)
-                    })?
-                    .into(),
-            )),
+            Expression::Array(vec) => {
+                let mut values = Vec::with_capacity(vec.len());
+                for x in vec {
+                    values.push(self.eval(x).await?);
+                }
+
+                Value::Array(Rc::new(values.into()))
+            }
 
             Expression::Func(function) => Value::Function(Rc::new(Function::new(
                 Rc::new(ast::Function::clone(function)),
@@ -116,7 +450,7 @@ impl Chip {
                     Default::default()
                 };
 
-                let value = self.eval(init)?;
+                let value = self.eval(init).await?;
 
                 self.memory.define(
                     meta.name().clone(),
@@ -136,7 +470,7 @@ impl Chip {
 
                 self.memory.push_env();
                 for expr in vec.iter() {
-                    v = self.eval(expr)?;
+                    v = self.eval(expr).await?;
                 }
                 self.memory.pop_env();
 
@@ -148,20 +482,21 @@ impl Chip {
                 then,
                 or_else,
             } => {
-                let condition = self.eval(condition)?;
+                let condition = self.eval(condition).await?;
 
-                self.eval(if condition.truthy() { then } else { or_else })?
+                self.eval(if condition.truthy() { then } else { or_else })
+                    .await?
             }
 
             Expression::While { condition, then } => {
-                while self.eval(condition)?.truthy() {
-                    self.eval(then)?;
+                while self.eval(condition).await?.truthy() {
+                    self.eval(then).await?;
                 }
 
                 Value::Nil
             }
 
-            Expression::PropertyAccess { lhs, property } => match self.eval(lhs)? {
+            Expression::PropertyAccess { lhs, property } => match self.eval(lhs).await? {
                 Value::Dictionary(values) => {
                     values.get(property).map(Value::clone).unwrap_or(Value::Nil)
                 }
@@ -170,185 +505,812 @@ impl Chip {
                 | Value::Bool(..)
                 | Value::Number(..)
                 | Value::Function(..)
+                | Value::NativeFunction(..)
                 | Value::Nil => {
-                    return Err(RuntimeError::InvalidPropertyAccess {
+                    return Err(Box::new(RuntimeError::InvalidPropertyAccess {
                         obj: *lhs.clone(),
                         property: property.clone(),
-                    })
+                        stack: CallStack::default(),
+                    }))
                 }
             },
 
             Expression::ArrayIndex { lhs, index } => {
-                let lhs_value = self.eval(lhs)?;
-                let idx_value = self.eval(index)?;
+                let lhs_value = self.eval(lhs).await?;
+                let idx_value = self.eval(index).await?;
 
                 match (lhs_value, idx_value) {
-                    (Value::Array(arr), Value::Number(i)) => RefCell::borrow(&arr)
-                        .get(i.floor() as usize)
-                        .ok_or_else(|| RuntimeError::ArrayOutOfBounds {
-                            array: *lhs.clone(),
-                            index: *index.clone(),
-                        })?
-                        .clone(),
+                    (Value::Array(arr), Value::Number(i)) => {
+                        let arr = RefCell::borrow(&arr);
+                        element(i, arr.len())
+                            .map(|i| arr[i].clone())
+                            .ok_or_else(|| RuntimeError::ArrayOutOfBounds {
+                                array: *lhs.clone(),
+                                index: *index.clone(),
+                                stack: CallStack::default(),
+                            })?
+                    }
 
                     (obj, _) => {
-                        return Err(RuntimeError::CannotIndexIntoType {
+                        return Err(Box::new(RuntimeError::CannotIndexIntoType {
                             array: *lhs.clone(),
                             data_type: obj.get_type(),
-                        })
+                            stack: CallStack::default(),
+                        }))
                     }
                 }
             }
 
             Expression::BinaryOp { lhs, operator, rhs } => {
-                let unsupported = || Err(RuntimeError::UnsupportedOperation(expr.clone()));
-
-                if operator == &Operator::Assign {
-                    let value = self.eval(rhs)?;
-
-                    match &lhs.0 {
-                        Expression::Ident(ref ident) => {
-                            self.memory.store(ident, value, expr)?;
-                            return Ok(Value::Nil);
-                        }
-                        Expression::ArrayIndex { lhs, index } => {
-                            match (self.eval(lhs)?, self.eval(index)?) {
-                                (Value::Array(arr), Value::Number(n)) => {
-                                    arr.borrow_mut()[n as usize] = value;
-                                    return Ok(Value::Nil);
-                                }
-                                _ => {}
-                            }
-                        }
+                self.binary(expr, lhs, *operator, rhs).await?
+            }
 
-                        _ => {}
-                    }
+            Expression::UnaryOp { operator, rhs } => self.unary(*operator, rhs).await?,
 
-                    return unsupported();
-                }
+            Expression::Call {
+                function,
+                arguments,
+            } => self.call_expr(expr, function, arguments).await?,
+
+            Expression::Dictionary(vec) => {
+                let mut map = HashMap::new();
 
-                match (self.eval(lhs)?, self.eval(rhs)?) {
-                    (Value::Number(a), Value::Number(b)) => Value::Number(match operator {
-                        Operator::Sub => a - b,
-                        Operator::Add => a + b,
-                        Operator::Mul => a * b,
-                        Operator::Div => a / b,
-                        Operator::Mod => a % b,
-                        Operator::Equals => return Ok(Value::Bool(a == b)),
-                        Operator::NotEqual => return Ok(Value::Bool(a != b)),
-                        Operator::Greater => return Ok(Value::Bool(a > b)),
-                        Operator::GreaterOrEqual => return Ok(Value::Bool(a >= b)),
-                        Operator::Less => return Ok(Value::Bool(a < b)),
-                        Operator::LessOrEqual => return Ok(Value::Bool(a <= b)),
-                        _ => return unsupported(),
-                    }),
-                    (Value::Bool(a), Value::Bool(b)) => Value::Bool(match operator {
-                        Operator::Or => a || b,
-                        Operator::And => a && b,
-                        Operator::Nor => !a && !b,
-                        Operator::Xor => a ^ b,
-                        Operator::Equals => a == b,
-                        Operator::NotEqual => a != b,
-
-                        _ => todo!(),
-                    }),
-                    _ => Value::Nil,
+                for (key, val) in vec {
+                    map.insert(key.clone(), self.eval(val).await?);
                 }
+
+                Value::Dictionary(Rc::new(map))
             }
 
-            Expression::UnaryOp { operator, rhs } => {
-                debug_assert!(
-                    operator.is_unary(),
-                    "Operator for unary expression must be unary"
-                );
+            Expression::Error => Value::Nil,
+        })
+    }
 
-                let value = self.eval(rhs)?;
+    // Operators and calls are evaluated apart from `evaluate`, nested calls go through all of
+    // their futures and every local of `evaluate` adds to the native stack each of them takes
 
-                match (operator, value) {
-                    (Operator::Sub, Value::Number(n)) => (-n).into(),
-                    (Operator::Not, value) => value.falsey().into(),
+    async fn binary(
+        &mut self,
+        expr: &Spanned<Expression>,
+        lhs: &Spanned<Expression>,
+        operator: Operator,
+        rhs: &Spanned<Expression>,
+    ) -> Eval<Value> {
+        let unsupported = || {
+            Err(Box::new(RuntimeError::UnsupportedOperation(
+                expr.clone(),
+                CallStack::default(),
+            )))
+        };
 
-                    (_, value) => {
-                        return Err(RuntimeError::UnsupportedUnaryOperation(
-                            *operator,
-                            *(rhs.clone()),
-                            value.clone(),
-                        ))
-                    }
-                }
-            }
+        if operator == Operator::Assign {
+            let value = self.eval(rhs).await?;
 
-            Expression::Call {
-                function,
-                arguments,
-            } => {
-                if matches!(function.0, Expression::Ident(ref ident) if **ident == *"print" ) {
-                    for arg in arguments {
-                        println!("{}", self.eval(arg)?);
-                    }
+            match &lhs.0 {
+                Expression::Ident(ref ident) => {
+                    self.memory.store(ident, value, expr)?;
                     return Ok(Value::Nil);
                 }
+                Expression::ArrayIndex { lhs, index } => {
+                    if let (Value::Array(arr), Value::Number(i)) =
+                        (self.eval(lhs).await?, self.eval(index).await?)
+                    {
+                        let mut arr = arr.borrow_mut();
+                        let element =
+                            element(i, arr.len()).map(|i| &mut arr[i]).ok_or_else(|| {
+                                RuntimeError::ArrayOutOfBounds {
+                                    array: *lhs.clone(),
+                                    index: *index.clone(),
+                                    stack: CallStack::default(),
+                                }
+                            })?;
 
-                match self.eval(function)? {
-                    Value::Function(ref func) => self.run_func(func, arguments)?,
-
-                    _ => todo!(),
+                        *element = value;
+                        return Ok(Value::Nil);
+                    }
                 }
+
+                _ => {}
             }
 
-            Expression::Dictionary(vec) => {
-                let mut map = HashMap::new();
+            return unsupported();
+        }
 
-                for (key, val) in vec {
-                    map.insert(key.clone(), self.eval(val)?);
-                }
+        Ok(match (self.eval(lhs).await?, self.eval(rhs).await?) {
+            (Value::Number(a), Value::Number(b)) => Value::Number(match operator {
+                Operator::Sub => a - b,
+                Operator::Add => a + b,
+                Operator::Mul => a * b,
+                Operator::Div => a / b,
+                Operator::Mod => a % b,
+                Operator::Equals => return Ok(Value::Bool(a == b)),
+                Operator::NotEqual => return Ok(Value::Bool(a != b)),
+                Operator::Greater => return Ok(Value::Bool(a > b)),
+                Operator::GreaterOrEqual => return Ok(Value::Bool(a >= b)),
+                Operator::Less => return Ok(Value::Bool(a < b)),
+                Operator::LessOrEqual => return Ok(Value::Bool(a <= b)),
+                _ => return unsupported(),
+            }),
+            (Value::Bool(a), Value::Bool(b)) => Value::Bool(match operator {
+                Operator::Or => a || b,
+                Operator::And => a && b,
+                Operator::Nor => !a && !b,
+                Operator::Xor => a ^ b,
+                Operator::Equals => a == b,
+                Operator::NotEqual => a != b,
+                _ => return unsupported(),
+            }),
+            (Value::String(a), Value::String(b)) => match operator {
+                Operator::Add => Value::String(a + &b),
+                Operator::Equals => Value::Bool(a == b),
+                Operator::NotEqual => Value::Bool(a != b),
+                _ => return unsupported(),
+            },
+            _ => Value::Nil,
+        })
+    }
 
-                Value::Dictionary(Rc::new(map))
+    async fn unary(&mut self, operator: Operator, rhs: &Spanned<Expression>) -> Eval<Value> {
+        debug_assert!(
+            operator.is_unary(),
+            "Operator for unary expression must be unary"
+        );
+
+        let value = self.eval(rhs).await?;
+
+        match (operator, value) {
+            (Operator::Sub, Value::Number(n)) => Ok((-n).into()),
+            (Operator::Not, value) => Ok(value.falsey().into()),
+
+            (_, value) => Err(Box::new(RuntimeError::UnsupportedUnaryOperation(
+                operator,
+                rhs.clone(),
+                value.clone(),
+                CallStack::default(),
+            ))),
+        }
+    }
+
+    async fn call_expr(
+        &mut self,
+        expr: &Spanned<Expression>,
+        function: &Spanned<Expression>,
+        arguments: &[Spanned<Expression>],
+    ) -> Eval<Value> {
+        let callee = self.eval(function).await?;
+        self.charge(self.costs().call, &expr.1).await?;
+
+        match callee {
+            Value::Function(ref func) => {
+                let name = callee_name(&function.0);
+                self.run_func(func, name, arguments, &expr.1).await
             }
+            Value::NativeFunction(ref native) => self.run_native(native, arguments, &expr.1).await,
 
-            Expression::Error => Value::Nil,
-        })
+            value => Err(Box::new(RuntimeError::NotCallable {
+                data_type: value.get_type(),
+                span: function.1.clone(),
+                stack: CallStack::default(),
+            })),
+        }
+    }
+
+    /// Calls a function with arguments that were already evaluated
+    async fn call(&mut self, function: &Value, values: Vec<Value>, call: &Span) -> Eval<Value> {
+        self.charge(self.costs().call, call).await?;
+
+        match function {
+            Value::Function(func) => {
+                let expected = func.inner().arguments().len();
+                arity(values.len() == expected, expected, values.len(), call)?;
+
+                // natives call back functions they were given, not names
+                let frame = Frame {
+                    name: None,
+                    call: Some(call.clone()),
+                    callee: func.inner().body().1.clone(),
+                };
+                self.invoke(func, values, frame).await
+            }
+            Value::NativeFunction(native) => {
+                arity(
+                    native.accepts(values.len()),
+                    native.params().len(),
+                    values.len(),
+                    call,
+                )?;
+                self.invoke_native(native, values, std::iter::repeat(call), call)
+                    .await
+            }
+            value => Err(Box::new(RuntimeError::NotCallable {
+                data_type: value.get_type(),
+                span: call.clone(),
+                stack: CallStack::default(),
+            })),
+        }
     }
 
-    fn run_func(
+    async fn run_func(
         &mut self,
         func: &Rc<Function>,
+        name: Option<Identifier>,
         arguments: &[Spanned<Expression>],
-    ) -> Result<Value> {
+        call: &Span,
+    ) -> Eval<Value> {
+        let expected = func.inner().arguments().len();
+        arity(arguments.len() == expected, expected, arguments.len(), call)?;
+
+        // Arguments are evaluated in the scope of the caller
+        let values = self.eval_all(arguments).await?;
+
+        let frame = Frame {
+            name,
+            call: Some(call.clone()),
+            callee: func.inner().body().1.clone(),
+        };
+        self.invoke(func, values, frame).await
+    }
+
+    async fn eval_all(&mut self, arguments: &[Spanned<Expression>]) -> Eval<Vec<Value>> {
+        let mut values = Vec::with_capacity(arguments.len());
+        for arg in arguments {
+            values.push(self.eval(arg).await?);
+        }
+        Ok(values)
+    }
+
+    async fn invoke(
+        &mut self,
+        func: &Rc<Function>,
+        values: Vec<Value>,
+        frame: Frame,
+    ) -> Eval<Value> {
+        // calls nest on the native stack, which would overflow long before memory runs out
+        if self.stack.len() >= self.max_depth {
+            let span = frame.call.clone().unwrap_or_else(|| frame.callee.clone());
+            let mut stack = self.stack.clone();
+            stack.push(frame);
+            return Err(Box::new(RuntimeError::StackOverflow {
+                depth: self.max_depth,
+                span,
+                stack,
+            }));
+        }
+
+        self.stack.push(frame);
+
         // Push memory scope
         let old = std::mem::replace(&mut self.memory, func.scope().clone());
 
-        let func = func.inner();
+        let returns = self.enter(func.inner(), values).await;
+
+        // Pop memory scope
+        self.memory = old;
+
+        // the innermost call an error leaves records the calls that led to it
+        let returns = returns.map_err(|mut error| {
+            error.trace(&self.stack);
+            error
+        });
+        self.stack.pop();
+        returns
+    }
 
+    /// Runs the body of a function, in the scope it was defined in
+    async fn enter(&mut self, func: &ast::Function, values: Vec<Value>) -> Eval<Value> {
         self.memory.push_env();
 
-        for (param, arg) in func.arguments().iter().zip(arguments) {
+        for (param, value) in func.arguments().iter().zip(values) {
             let data_type = if let Some(ty) = param.0.data_type() {
                 self.resolve_type(ty)?
             } else {
                 Default::default()
             };
 
-            let value = self.eval(arg)?;
-
+            let name = param.0.name().clone();
             self.memory.define(
-                param.0.name().clone(),
+                name.clone(),
                 Variable {
                     data_type,
                     mutability: param.0.mutablity(),
                     value,
                 },
-                arg,
+                &(Expression::Ident(name), param.1.clone()),
             )?;
         }
 
-        let returns = self.eval(func.body())?;
+        let returns = self.eval(func.body()).await?;
 
         let _ = self.memory.pop_env();
 
-        // Pop memory scope
-        self.memory = old;
-
         Ok(returns)
     }
+
+    async fn run_native(
+        &mut self,
+        native: &NativeFunction,
+        arguments: &[Spanned<Expression>],
+        call: &Span,
+    ) -> Eval<Value> {
+        arity(
+            native.accepts(arguments.len()),
+            native.params().len(),
+            arguments.len(),
+            call,
+        )?;
+
+        let values = self.eval_all(arguments).await?;
+
+        self.invoke_native(native, values, arguments.iter().map(|arg| &arg.1), call)
+            .await
+    }
+
+    /// Runs a native function, `spans` are where each argument came from
+    async fn invoke_native<'s>(
+        &mut self,
+        native: &NativeFunction,
+        values: Vec<Value>,
+        spans: impl Iterator<Item = &'s Span>,
+        call: &Span,
+    ) -> Eval<Value> {
+        for ((i, value), span) in values.iter().enumerate().zip(spans) {
+            let expected = native.param(i);
+            if !value.is_type(expected) {
+                return Err(Box::new(RuntimeError::ArgumentType {
+                    expected: expected.clone(),
+                    found: value.get_type(),
+                    span: span.clone(),
+                    stack: CallStack::default(),
+                }));
+            }
+        }
+
+        self.natives += 1;
+        let result = native.call(&mut Context::new(self, call), &values);
+        self.natives -= 1;
+
+        let value = result.map_err(|error| match error {
+            NativeError::Message(message) => Box::new(RuntimeError::Native {
+                name: native.name().clone(),
+                message,
+                span: call.clone(),
+                stack: CallStack::default(),
+            }),
+            NativeError::Runtime(error) => error,
+        })?;
+
+        let ticks = std::mem::take(&mut self.waiting);
+        if ticks > 0 {
+            self.pause(ticks, call).await?;
+        }
+
+        Ok(value)
+    }
+}
+
+/// Name a function is called by, `None` if the call does not name it
+fn callee_name(function: &Expression) -> Option<Identifier> {
+    match function {
+        Expression::Ident(name) => Some(name.clone()),
+        Expression::PropertyAccess { property, .. } => Some(property.clone()),
+        _ => None,
+    }
+}
+
+/// Fails a call that does not pass as many arguments as the function accepts
+fn arity(accepts: bool, expected: usize, found: usize, call: &Span) -> Result<()> {
+    if accepts {
+        return Ok(());
+    }
+
+    Err(RuntimeError::ArgumentCount {
+        expected,
+        found,
+        span: call.clone(),
+        stack: CallStack::default(),
+    })
+}
+
+/// Position of the element an index refers to in an array of a length, `None` if it is out of
+/// bounds. Fractions are rounded down, by indexing and by the natives taking indexes alike.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn element(i: f64, len: usize) -> Option<usize> {
+    let i = i.floor();
+    (i >= 0. && i < len as f64).then_some(i as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::{SourceId, SourceMap};
+
+    use super::{
+        error::RuntimeError,
+        fuel::{Costs, Fuel},
+        io::WireError,
+        native::NativeFunction,
+        value::{Type, Value},
+        Chip, Tick,
+    };
+
+    fn run(source: &str) -> super::Result<Value> {
+        Chip::new(crate::parse(SourceId::empty(), source).unwrap()).run()
+    }
+
+    fn run_with(source: &str, native: NativeFunction) -> super::Result<Value> {
+        Chip::new(crate::parse(SourceId::empty(), source).unwrap())
+            .with_native(native)
+            .run()
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(
+            run("func f(a, b) => a + b func main() => f(1, 2)").unwrap(),
+            Value::Number(3.)
+        );
+        assert!(matches!(
+            run("func f(a, b) => a func main() => f(1)"),
+            Err(RuntimeError::ArgumentCount {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            run("func main(a) => a"),
+            Err(RuntimeError::ArgumentCount {
+                expected: 1,
+                found: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn argument_scope() {
+        assert_eq!(
+            run("func f(a) => a func main() { let local = 1 f(local) }").unwrap(),
+            Value::Number(1.),
+            "arguments see the locals of the caller"
+        );
+        assert_eq!(
+            run("func f(a, b) => b func main() { let a = 1 f(2, a) }").unwrap(),
+            Value::Number(1.),
+            "arguments do not see the parameters of the callee"
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            run("func main() => \"a\" + \"b\" == \"ab\"").unwrap(),
+            Value::Bool(true)
+        );
+        assert!(matches!(
+            run("func main() => \"a\" * \"b\""),
+            Err(RuntimeError::UnsupportedOperation(..))
+        ));
+        assert!(matches!(
+            run("func main() => true + false"),
+            Err(RuntimeError::UnsupportedOperation(..))
+        ));
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(
+            run("func main() { let a = [1, 2] a[1.5] = 3 a[1] }").unwrap(),
+            Value::Number(3.)
+        );
+        for source in [
+            "func main() { let a = [1] a[1] = 2 }",
+            "func main() { let a = [1] a[-1] = 2 }",
+            "func main() { let a = [1] a[-1] }",
+        ] {
+            assert!(
+                matches!(run(source), Err(RuntimeError::ArrayOutOfBounds { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn natives() {
+        let half =
+            || {
+                NativeFunction::new("half", vec![Type::Number], Type::Number, |_, values| {
+                    match values[0] {
+                        Value::Number(n) if n.is_finite() => Ok(Value::Number(n / 2.)),
+                        _ => Err("not a finite number".into()),
+                    }
+                })
+            };
+
+        assert_eq!(
+            run_with("func main() => half(3)", half()).unwrap(),
+            Value::Number(1.5)
+        );
+        assert_eq!(
+            run_with(
+                "func twice(f, x) => f(f(x)) func main() => twice(half, 8)",
+                half()
+            )
+            .unwrap(),
+            Value::Number(2.),
+            "natives are values"
+        );
+        assert_eq!(
+            run("func print(x) => x func main() => print(4)").unwrap(),
+            Value::Number(4.),
+            "natives can be shadowed"
+        );
+        assert_eq!(
+            run("func main() { print(1, 2) print }")
+                .unwrap()
+                .to_string(),
+            "[native function print]"
+        );
+
+        assert!(matches!(
+            run_with("func main() => half(1, 2)", half()),
+            Err(RuntimeError::ArgumentCount {
+                expected: 1,
+                found: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            run_with("func main() => half(\"a\")", half()),
+            Err(RuntimeError::ArgumentType {
+                expected: Type::Number,
+                found: Type::String,
+                ..
+            })
+        ));
+        assert!(matches!(
+            run_with("func main() => half(1 / 0)", half()),
+            Err(RuntimeError::Native { message, .. }) if message == "not a finite number"
+        ));
+    }
+
+    #[test]
+    fn fuel() {
+        let program = |source| crate::parse(SourceId::empty(), source).unwrap();
+
+        let mut chip =
+            Chip::new(program("func main() { while true { } }")).with_fuel(Fuel::new(100));
+        assert!(matches!(chip.run(), Err(RuntimeError::OutOfFuel { .. })));
+        assert_eq!(chip.fuel().remaining(), Some(0));
+
+        // the declaration, the function it is initialized with and its body
+        let mut chip = Chip::new(program("func main() => 1")).with_fuel(Fuel::new(100));
+        assert_eq!(chip.run().unwrap(), Value::Number(1.));
+        assert_eq!(chip.fuel().used(), 3);
+
+        let mut chip = Chip::new(program("func main() => map([1, 2], func(x) => x)"))
+            .with_fuel(Fuel::unlimited());
+        chip.run().unwrap();
+        assert!(chip.fuel().used() > 10, "callbacks of natives are charged");
+    }
+
+    #[test]
+    fn ticks() {
+        let program = |source| crate::parse(SourceId::empty(), source).unwrap();
+
+        let mut chip = Chip::new(program(
+            "let count = 0 func main() { while count < 2 { count = count + 1 yield() } wait(2) count }",
+        ));
+        for _ in 0..4 {
+            assert_eq!(chip.tick().unwrap(), Tick::Paused);
+        }
+        assert_eq!(chip.tick().unwrap(), Tick::Finished(Value::Number(2.)));
+        assert_eq!(
+            chip.tick().unwrap(),
+            Tick::Paused,
+            "finished programs start over"
+        );
+
+        assert_eq!(
+            run("func main() { yield() wait(3) 1 }").unwrap(),
+            Value::Number(1.),
+            "runs to the end do not pause"
+        );
+        assert_eq!(
+            run("func main() { wait(1e15) 1 }").unwrap(),
+            Value::Number(1.),
+            "runs to the end skip long waits at once"
+        );
+
+        let mut chip = Chip::new(program("func main() { wait(1e15) 1 }"));
+        for _ in 0..1000 {
+            assert_eq!(chip.tick().unwrap(), Tick::Paused);
+        }
+        assert!(matches!(
+            Chip::new(program("func main() => map([1], func(x) => yield())")).tick(),
+            Err(RuntimeError::CannotPause { .. })
+        ));
+        assert!(matches!(
+            run("func main() => wait(0.5)"),
+            Err(RuntimeError::Native { message, .. }) if message == "cannot wait for 0.5 ticks"
+        ));
+
+        let mut chip = Chip::new(program(
+            "func main() { let i = 0 while i < 100 { i = i + 1 } i }",
+        ))
+        .with_fuel(Fuel::new(50));
+        let mut ticks = 1;
+        while chip.tick().unwrap() == Tick::Paused {
+            chip.fuel_mut().refill(50);
+            ticks += 1;
+        }
+        assert!(ticks > 5, "out of fuel pauses until the next tick");
+        assert_eq!(
+            chip.fuel().used(),
+            50 * (ticks - 1) + 50 - chip.fuel().remaining().unwrap()
+        );
+
+        let mut chip = Chip::new(program("func main() => 1 + 2"))
+            .with_fuel(Fuel::new(10).with_costs(Costs { node: 100, call: 1 }));
+        let ticks = (1..100).find(|_| {
+            chip.fuel_mut().refill(10);
+            chip.tick().unwrap() != Tick::Paused
+        });
+        assert!(
+            ticks.is_some(),
+            "work costing more than the budget takes a tick of its own rather than pausing forever"
+        );
+    }
+
+    #[test]
+    fn call_stack() {
+        let source = "func inner() => nothing func outer() => inner() func main() => outer()";
+        let error = run(source).unwrap_err();
+
+        let frames = error.stack().frames();
+        let names = frames.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(names, ["main", "outer", "inner"]);
+        assert_eq!(frames[0].call, None, "nothing calls main");
+
+        let call = source.rfind("outer()").unwrap();
+        assert_eq!(frames[1].call.as_ref().unwrap().range(), call..call + 7);
+        let body = source.find("nothing").unwrap();
+        assert_eq!(frames[2].callee.range(), body..body + 7);
+
+        let sources: SourceMap = [(SourceId::empty(), source)].into_iter().collect();
+        let mut out = vec![];
+        error.write(&sources, &mut out);
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("in inner"),
+            "the innermost function is labelled: {out}"
+        );
+
+        // natives call back functions they were given without a name
+        let error = run("func main() => map([1], func(x) => x.y)").unwrap_err();
+        let names = error
+            .stack()
+            .frames()
+            .iter()
+            .map(|frame| frame.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [Some("main".into()), None]);
+        assert_eq!(error.stack().to_string(), "main -> anonymous function");
+
+        assert!(run("func main() => nothing")
+            .unwrap_err()
+            .stack()
+            .frames()
+            .iter()
+            .all(|frame| frame.call.is_none()));
+        assert!(run("nothing").unwrap_err().stack().is_empty());
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let chip =
+            |source| Chip::new(crate::parse(SourceId::empty(), source).unwrap()).with_max_depth(8);
+
+        let error = chip("func f() => f() func main() => f()")
+            .run()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RuntimeError::StackOverflow { depth: 8, .. }
+        ));
+        assert_eq!(
+            error.stack().len(),
+            9,
+            "the call that overflowed is the last"
+        );
+        assert_eq!(error.stack().to_string(), "main -> f (8 times)");
+
+        assert!(matches!(
+            chip("func f(x) => map([x], f) func main() => f(1)").run(),
+            Err(RuntimeError::StackOverflow { .. })
+        ));
+        assert_eq!(
+            chip("func f(n) => if n == 0 { 0 } else { f(n - 1) } func main() => f(6)")
+                .run()
+                .unwrap(),
+            Value::Number(0.)
+        );
+
+        // threads get a small stack by default, the depth a chip allows has to fit in it
+        let (walk, unbounded, ticked) = std::thread::spawn(|| {
+            let source = "func sum(a, i) => if i == len(a) { 0 } else { a[i] + sum(a, i + 1) }
+                func main() { let a = [] let i = 0 while i < 100 { push(a, 1) i = i + 1 } sum(a, 0) }";
+            let walk = run(source).map(|value| value.to_string());
+            let unbounded = run("func f() => f() func main() => f()");
+            let ticked = Chip::new(crate::parse(SourceId::empty(), "func f() => f() func main() => f()").unwrap())
+                .with_max_depth(super::DEPTH_LIMIT)
+                .tick();
+            (
+                walk.map_err(|error| error.to_string()),
+                matches!(unbounded, Err(RuntimeError::StackOverflow { depth: super::MAX_DEPTH, .. })),
+                matches!(ticked, Err(RuntimeError::StackOverflow { .. })),
+            )
+        })
+        .join()
+        .unwrap();
+        assert_eq!(
+            walk,
+            Ok("100".to_string()),
+            "the default depth fits a recursive walk over an array"
+        );
+        assert!(
+            unbounded,
+            "unbounded recursion fails instead of overflowing the stack"
+        );
+        assert!(ticked, "so does the deepest limit when ticking");
+    }
+
+    #[test]
+    fn ports() {
+        let program = |source| crate::parse(SourceId::empty(), source).unwrap();
+
+        let mut sensor = Chip::new(program(
+            "@output(level, number) func main() => write(\"level\", 3)",
+        ));
+        let mut display = Chip::new(program(
+            "@input(level, number) func main() => read(\"level\") * 2",
+        ));
+        assert_eq!(
+            display.run().unwrap(),
+            Value::Number(0.),
+            "not connected yet"
+        );
+
+        display.connect("level", &sensor, "level").unwrap();
+        sensor.run().unwrap();
+        assert_eq!(display.run().unwrap(), Value::Number(6.));
+        assert_eq!(sensor.ports().outputs()[0].value(), Value::Number(3.));
+
+        let label = Chip::new(program("@input(level, str) func main() => nil"));
+        assert!(matches!(
+            label.connect("level", &sensor, "level"),
+            Err(WireError::MismatchedTypes { .. })
+        ));
+        assert!(matches!(
+            label.connect("level", &sensor, "missing"),
+            Err(WireError::UnknownPort(name)) if name.name() == "missing"
+        ));
+
+        assert!(matches!(
+            run("@output(on, bool) func main() => write(\"on\", [1])"),
+            Err(RuntimeError::Native { message, .. })
+                if message == "cannot write a value of type array to output on of type bool"
+        ));
+        assert!(matches!(
+            run("func main() => read(\"level\")"),
+            Err(RuntimeError::Native { message, .. }) if message == "there is no input named level"
+        ));
+        assert!(matches!(
+            run("@input(1) func main() => nil"),
+            Err(RuntimeError::InvalidPort { .. })
+        ));
+    }
 }
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, fmt::Debug, rc::Rc};
+
+use displaydoc::Display;
+use thiserror::Error;
+
+use crate::{
+    parser::{span::Span, symbol::Identifier},
+    semantic::Builtins,
+};
+
+use super::{
+    error::RuntimeError,
+    finish,
+    io::Ports,
+    prelude,
+    value::{Type, Value},
+    Machine,
+};
+
+#[derive(Debug, Display, Error)]
+pub enum NativeError {
+    /// {0}
+    Message(String),
+
+    /// {0}
+    Runtime(#[source] Box<RuntimeError>),
+}
+
+impl From<RuntimeError> for NativeError {
+    fn from(error: RuntimeError) -> Self {
+        Self::Runtime(Box::new(error))
+    }
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        Self::Message(message)
+    }
+}
+
+impl From<&str> for NativeError {
+    fn from(message: &str) -> Self {
+        Self::Message(message.to_string())
+    }
+}
+
+pub type NativeResult = Result<Value, NativeError>;
+
+/// Body of a native function, a message it fails with is reported along with the call
+pub type NativeBody = dyn Fn(&mut Context, &[Value]) -> NativeResult;
+
+/// Chip running a native function
+pub struct Context<'a> {
+    machine: &'a mut Machine,
+    call: &'a Span,
+}
+
+impl<'a> Context<'a> {
+    pub(super) fn new(machine: &'a mut Machine, call: &'a Span) -> Self {
+        Self { machine, call }
+    }
+
+    /// Calls a function of the program, or another native. The call cannot pause, a function
+    /// that tries to fails with [`RuntimeError::CannotPause`].
+    ///
+    /// # Errors
+    ///
+    /// If the value is not a function or the call fails
+    pub fn call(&mut self, function: &Value, arguments: Vec<Value>) -> NativeResult {
+        finish(self.machine.call(function, arguments, self.call)).map_err(NativeError::Runtime)
+    }
+
+    /// Inputs and outputs of the chip
+    #[must_use]
+    pub fn ports(&self) -> &Ports {
+        &self.machine.ports
+    }
+
+    /// Pauses the program for a number of ticks once the native returns, which does nothing
+    /// when the program is not run over ticks
+    pub fn wait(&mut self, ticks: u64) {
+        self.machine.wait(ticks);
+    }
+}
+
+/// Function implemented by the host instead of in the script
+pub struct NativeFunction {
+    name: Identifier,
+    params: Vec<Type>,
+    returns: Type,
+
+    /// Whether any number of arguments of the single parameter type is accepted
+    variadic: bool,
+
+    body: Box<NativeBody>,
+}
+
+impl NativeFunction {
+    /// Function taking one argument for each of `params`, arguments are checked against their
+    /// type before the body runs
+    pub fn new(
+        name: impl Into<Identifier>,
+        params: Vec<Type>,
+        returns: Type,
+        body: impl Fn(&mut Context, &[Value]) -> NativeResult + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            params,
+            returns,
+            variadic: false,
+            body: Box::new(body),
+        }
+    }
+
+    /// Function taking any number of arguments of the same type
+    pub fn variadic(
+        name: impl Into<Identifier>,
+        param: Type,
+        returns: Type,
+        body: impl Fn(&mut Context, &[Value]) -> NativeResult + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            params: vec![param],
+            returns,
+            variadic: true,
+            body: Box::new(body),
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &Identifier {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn params(&self) -> &[Type] {
+        &self.params
+    }
+
+    #[must_use]
+    pub fn returns(&self) -> &Type {
+        &self.returns
+    }
+
+    #[must_use]
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
+    /// Whether the function can be called with a number of arguments
+    #[must_use]
+    pub fn accepts(&self, arguments: usize) -> bool {
+        self.variadic || arguments == self.params.len()
+    }
+
+    /// Type the argument at an index has to be of
+    #[must_use]
+    pub fn param(&self, i: usize) -> &Type {
+        if self.variadic {
+            &self.params[0]
+        } else {
+            &self.params[i]
+        }
+    }
+
+    /// Type of the function, variadic functions cannot be described by one and are `any`
+    #[must_use]
+    pub fn signature(&self) -> Type {
+        if self.variadic {
+            return Type::Any;
+        }
+
+        Type::Function {
+            params: self.params.clone(),
+            returns: Box::new(self.returns.clone()),
+        }
+    }
+
+    /// Runs the body with arguments already checked against the parameters
+    ///
+    /// # Errors
+    ///
+    /// Whatever error the body fails with
+    pub fn call(&self, context: &mut Context, arguments: &[Value]) -> NativeResult {
+        (self.body)(context, arguments)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .field("returns", &self.returns)
+            .field("variadic", &self.variadic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for NativeFunction {
+    // two functions are never equal
+    fn eq(&self, _: &Self) -> bool {
+        false
+    }
+}
+
+/// Native functions a chip provides to its program as globals
+#[derive(Debug, Default, Clone)]
+pub struct Natives {
+    functions: HashMap<Identifier, Rc<NativeFunction>>,
+}
+
+impl Natives {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Functions every chip provides, see [`prelude`]
+    #[must_use]
+    pub fn std() -> Self {
+        let mut natives = Self::new();
+        prelude::register(&mut natives);
+        natives
+    }
+
+    /// Adds a function, replacing any registered before with the same name
+    pub fn register(&mut self, native: NativeFunction) {
+        self.functions
+            .insert(native.name().clone(), Rc::new(native));
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &Identifier) -> Option<&Rc<NativeFunction>> {
+        self.functions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<NativeFunction>> {
+        self.functions.values()
+    }
+
+    /// Signatures of the functions for analyzing programs that run with them
+    #[must_use]
+    pub fn builtins(&self) -> Builtins {
+        let mut builtins = Builtins::new();
+        for native in self.iter() {
+            builtins.insert(native.name().clone(), native.signature());
+        }
+        builtins
+    }
+}
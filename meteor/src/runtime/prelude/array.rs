@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+
+use super::{
+    super::{
+        native::{NativeFunction, Natives},
+        value::{Type, Value},
+    },
+    array, index, number, unsupported,
+};
+
+/// Type of a function taking a number of arguments of any type
+fn callback(params: usize) -> Type {
+    Type::Function {
+        params: vec![Type::Any; params],
+        returns: Box::new(Type::Any),
+    }
+}
+
+/// Orders numbers before strings, which are the only values that can be sorted
+fn order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.total_cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Number(_), _) => Ordering::Less,
+        (_, Value::Number(_)) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+pub(super) fn register(natives: &mut Natives) {
+    natives.register(NativeFunction::new(
+        "push",
+        vec![Type::array(), Type::Any],
+        Type::Nil,
+        |_, values| {
+            array(&values[0]).borrow_mut().push(values[1].clone());
+            Ok(Value::Nil)
+        },
+    ));
+
+    // `nil` if the array is empty
+    natives.register(NativeFunction::new(
+        "pop",
+        vec![Type::array()],
+        Type::Any,
+        |_, values| Ok(array(&values[0]).borrow_mut().pop().unwrap_or(Value::Nil)),
+    ));
+
+    // inserting at the length of the array appends to it
+    natives.register(NativeFunction::new(
+        "insert",
+        vec![Type::array(), Type::Number, Type::Any],
+        Type::Nil,
+        |_, values| {
+            let mut array = array(&values[0]).borrow_mut();
+            let i = index(number(&values[1]), array.len() + 1)?;
+            array.insert(i, values[2].clone());
+            Ok(Value::Nil)
+        },
+    ));
+
+    // callbacks run on a copy, so they are free to change the array they are called for
+    natives.register(NativeFunction::new(
+        "map",
+        vec![Type::array(), callback(1)],
+        Type::array(),
+        |context, values| {
+            let items = array(&values[0]).borrow().clone();
+            let mapped = items
+                .into_iter()
+                .map(|item| context.call(&values[1], vec![item]))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(mapped.into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "filter",
+        vec![Type::array(), callback(1)],
+        Type::array(),
+        |context, values| {
+            let items = array(&values[0]).borrow().clone();
+            let mut kept = vec![];
+            for item in items {
+                if context.call(&values[1], vec![item.clone()])?.truthy() {
+                    kept.push(item);
+                }
+            }
+            Ok(kept.into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "reduce",
+        vec![Type::array(), callback(2), Type::Any],
+        Type::Any,
+        |context, values| {
+            let items = array(&values[0]).borrow().clone();
+            items.into_iter().try_fold(values[2].clone(), |acc, item| {
+                context.call(&values[1], vec![acc, item])
+            })
+        },
+    ));
+
+    // sorts in place, numbers in ascending order followed by strings
+    natives.register(NativeFunction::new(
+        "sort",
+        vec![Type::array()],
+        Type::Nil,
+        |_, values| {
+            let mut items = array(&values[0]).borrow_mut();
+            if let Some(value) = items
+                .iter()
+                .find(|value| !matches!(value, Value::Number(_) | Value::String(_)))
+            {
+                return Err(unsupported("sort", value));
+            }
+
+            items.sort_by(order);
+            Ok(Value::Nil)
+        },
+    ));
+}
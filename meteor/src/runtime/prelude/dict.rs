@@ -0,0 +1,63 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::parser::symbol::Identifier;
+
+use super::{
+    super::{
+        native::{NativeFunction, Natives},
+        value::{Type, Value},
+    },
+    string,
+};
+
+fn dict(value: &Value) -> &Rc<HashMap<Identifier, Value>> {
+    match value {
+        Value::Dictionary(dict) => dict,
+        value => unreachable!("{value} is not a dictionary"),
+    }
+}
+
+/// Entries of a dictionary ordered by key, so that programs behave the same on every run
+fn entries(value: &Value) -> Vec<(&Identifier, &Value)> {
+    let mut entries = dict(value).iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+    entries
+}
+
+pub(super) fn register(natives: &mut Natives) {
+    natives.register(NativeFunction::new(
+        "keys",
+        vec![Type::dict()],
+        Type::Array(Box::new(Type::String)),
+        |_, values| {
+            let keys = entries(&values[0])
+                .into_iter()
+                .map(|(key, _)| Value::from(key.name()))
+                .collect::<Vec<_>>();
+            Ok(keys.into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "values",
+        vec![Type::dict()],
+        Type::array(),
+        |_, values| {
+            let values = entries(&values[0])
+                .into_iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>();
+            Ok(values.into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "has",
+        vec![Type::dict(), Type::String],
+        Type::Bool,
+        |_, values| {
+            let key = Identifier::from(string(&values[1]));
+            Ok(dict(&values[0]).contains_key(&key).into())
+        },
+    ));
+}
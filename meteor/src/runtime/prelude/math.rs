@@ -0,0 +1,55 @@
+use super::{
+    super::{
+        native::{NativeFunction, Natives},
+        value::{Type, Value},
+    },
+    number,
+};
+
+fn unary(name: &str, f: fn(f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, vec![Type::Number], Type::Number, move |_, values| {
+        Ok(Value::Number(f(number(&values[0]))))
+    })
+}
+
+fn binary(name: &str, f: fn(f64, f64) -> f64) -> NativeFunction {
+    NativeFunction::new(
+        name,
+        vec![Type::Number, Type::Number],
+        Type::Number,
+        move |_, values| Ok(Value::Number(f(number(&values[0]), number(&values[1])))),
+    )
+}
+
+pub(super) fn register(natives: &mut Natives) {
+    natives.register(unary("sqrt", f64::sqrt));
+    natives.register(unary("sin", f64::sin));
+    natives.register(unary("floor", f64::floor));
+    natives.register(binary("atan2", f64::atan2));
+    natives.register(binary("min", f64::min));
+    natives.register(binary("max", f64::max));
+
+    natives.register(NativeFunction::new(
+        "clamp",
+        vec![Type::Number; 3],
+        Type::Number,
+        |_, values| {
+            let (x, min, max) = (number(&values[0]), number(&values[1]), number(&values[2]));
+            if min <= max {
+                Ok(Value::Number(x.clamp(min, max)))
+            } else {
+                Err(format!("minimum {min} is greater than maximum {max}").into())
+            }
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "lerp",
+        vec![Type::Number; 3],
+        Type::Number,
+        |_, values| {
+            let (a, b, t) = (number(&values[0]), number(&values[1]), number(&values[2]));
+            Ok(Value::Number(a + (b - a) * t))
+        },
+    ));
+}
@@ -0,0 +1,264 @@
+//! Native functions every chip provides.
+//!
+//! Arguments are checked against the parameter types before a function runs, so the accessors
+//! below only fail for functions taking `any`.
+//!
+//! `yield` and `wait` are natives like the others rather than keywords. They pause the program
+//! until a later tick, so a loop calling them can run forever without using up the fuel of a
+//! single tick.
+
+mod array;
+mod dict;
+mod math;
+mod ports;
+mod string;
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::parser::symbol::Identifier;
+
+use super::{
+    native::{NativeError, NativeFunction, Natives},
+    value::{Type, Value},
+};
+
+/// Natives that pause the program until a later tick
+pub const YIELDING: [&str; 2] = ["yield", "wait"];
+
+pub(super) fn register(natives: &mut Natives) {
+    natives.register(NativeFunction::variadic(
+        "print",
+        Type::Any,
+        Type::Nil,
+        |_, values| {
+            for value in values {
+                println!("{value}");
+            }
+            Ok(Value::Nil)
+        },
+    ));
+
+    // works on strings, arrays and dictionaries alike
+    natives.register(NativeFunction::new(
+        "len",
+        vec![Type::Any],
+        Type::Number,
+        |_, values| {
+            let len = match &values[0] {
+                Value::String(string) => string.chars().count(),
+                Value::Array(array) => array.borrow().len(),
+                Value::Dictionary(dict) => dict.len(),
+                value => return Err(unsupported("take the length of", value)),
+            };
+            Ok(count(len))
+        },
+    ));
+
+    // removes an element from an array, but dictionaries are immutable and are copied instead
+    natives.register(NativeFunction::new(
+        "remove",
+        vec![Type::Any, Type::Any],
+        Type::Any,
+        |_, values| match (&values[0], &values[1]) {
+            (Value::Array(array), Value::Number(i)) => {
+                let mut array = array.borrow_mut();
+                let i = index(*i, array.len())?;
+                Ok(array.remove(i))
+            }
+            (Value::Dictionary(dict), Value::String(key)) => {
+                let mut dict = dict.as_ref().clone();
+                dict.remove(&Identifier::from(key.as_str()));
+                Ok(Value::Dictionary(Rc::new(dict)))
+            }
+            (collection, key) => Err(format!(
+                "cannot remove a key of type {} from a value of type {}",
+                key.get_type(),
+                collection.get_type()
+            )
+            .into()),
+        },
+    ));
+
+    // pauses the program until the next tick
+    natives.register(NativeFunction::new(
+        "yield",
+        vec![],
+        Type::Nil,
+        |context, _| {
+            context.wait(1);
+            Ok(Value::Nil)
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "wait",
+        vec![Type::Number],
+        Type::Nil,
+        |context, values| {
+            context.wait(ticks(number(&values[0]))?);
+            Ok(Value::Nil)
+        },
+    ));
+
+    math::register(natives);
+    string::register(natives);
+    array::register(natives);
+    dict::register(natives);
+    ports::register(natives);
+}
+
+fn unsupported(action: &str, value: &Value) -> NativeError {
+    format!("cannot {action} a value of type {}", value.get_type()).into()
+}
+
+/// Number of a length or position, which are never large enough to lose precision
+#[allow(clippy::cast_precision_loss)]
+fn count(n: usize) -> Value {
+    Value::Number(n as f64)
+}
+
+/// Position of an element in an array of a length, rounded down like indexing does
+fn index(i: f64, len: usize) -> Result<usize, NativeError> {
+    super::element(i, len)
+        .ok_or_else(|| format!("index {i} is out of bounds for an array of length {len}").into())
+}
+
+/// Number of ticks to wait for
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn ticks(n: f64) -> Result<u64, NativeError> {
+    if n.fract() == 0. && n >= 0. {
+        Ok(n as u64)
+    } else {
+        Err(format!("cannot wait for {n} ticks").into())
+    }
+}
+
+fn number(value: &Value) -> f64 {
+    match value {
+        Value::Number(n) => *n,
+        value => unreachable!("{value} is not a number"),
+    }
+}
+
+fn string(value: &Value) -> &str {
+    match value {
+        Value::String(string) => string,
+        value => unreachable!("{value} is not a string"),
+    }
+}
+
+fn array(value: &Value) -> &Rc<RefCell<Vec<Value>>> {
+    match value {
+        Value::Array(array) => array,
+        value => unreachable!("{value} is not an array"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use super::super::{error::RuntimeError, Chip, Result};
+
+    /// Value of the last expression of a function body as it would be printed
+    fn eval(body: &str) -> Result<String> {
+        let source = format!("func main() {{ {body} }}");
+        let value = Chip::new(crate::parse(SourceId::empty(), &source).unwrap()).run()?;
+        Ok(value.to_string())
+    }
+
+    #[test]
+    fn math() {
+        assert_eq!(eval("sqrt(16) + floor(2.7)").unwrap(), "6");
+        assert_eq!(eval("atan2(0, 1) + sin(0)").unwrap(), "0");
+        assert_eq!(eval("min(1, 2) + max(1, 2)").unwrap(), "3");
+        assert_eq!(eval("clamp(7, 0, 5) + lerp(0, 10, 0.5)").unwrap(), "10");
+        assert!(matches!(
+            eval("clamp(1, 5, 0)"),
+            Err(RuntimeError::Native { message, .. }) if message == "minimum 5 is greater than maximum 0"
+        ));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(eval("len(\"héllo\")").unwrap(), "5");
+        assert_eq!(
+            eval("join(split(\"a,b,c\", \",\"), \"-\")").unwrap(),
+            "a-b-c"
+        );
+        assert_eq!(eval("len(split(\"abc\", \"\"))").unwrap(), "3");
+        assert_eq!(
+            eval("upper(replace(\"a-b\", \"-\", \"+\"))").unwrap(),
+            "A+B"
+        );
+        assert_eq!(eval("find(\"héllo\", \"l\")").unwrap(), "2");
+        assert_eq!(eval("find(\"abc\", \"x\")").unwrap(), "nil");
+        assert_eq!(eval("parse_number(\" 1.5 \") + 1").unwrap(), "2.5");
+        assert_eq!(eval("parse_number(\"one\")").unwrap(), "nil");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(
+            eval("let a = [3, 1] push(a, 2) insert(a, 0, 4) sort(a) a").unwrap(),
+            "[1,2,3,4,]"
+        );
+        assert_eq!(
+            eval("let a = [1, 2, 3] let last = pop(a) remove(a, 0) + last + len(a)").unwrap(),
+            "5"
+        );
+        assert_eq!(
+            eval("let a = [1, 2, 3] remove(a, 1.5) + a[1.5]").unwrap(),
+            "5",
+            "natives round indexes down like indexing does"
+        );
+        assert_eq!(
+            eval("map(filter([1, 2, 3, 4], func(x) => x % 2 == 0), func(x) => x * 10)").unwrap(),
+            "[20,40,]"
+        );
+        assert_eq!(
+            eval("reduce([1, 2, 3], func(sum, x) => sum + x, 0)").unwrap(),
+            "6"
+        );
+        assert_eq!(
+            eval("let a = [\"b\", 2, \"a\", 1] sort(a) a").unwrap(),
+            "[1,2,a,b,]"
+        );
+
+        assert!(matches!(
+            eval("remove([1], 1)"),
+            Err(RuntimeError::Native { message, .. }) if message == "index 1 is out of bounds for an array of length 1"
+        ));
+        assert!(matches!(
+            eval("sort([1, [2]])"),
+            Err(RuntimeError::Native { message, .. }) if message == "cannot sort a value of type array"
+        ));
+        assert!(matches!(
+            eval("map([1], func(a, b) => a)"),
+            Err(RuntimeError::ArgumentType { .. })
+        ));
+        assert!(
+            matches!(
+                eval("map([1], func(x) => missing)"),
+                Err(RuntimeError::UnknownVariable { .. })
+            ),
+            "errors in callbacks are reported where they happen"
+        );
+    }
+
+    #[test]
+    fn dictionaries() {
+        let dict = "{ b = 2, a = 1 }";
+        assert_eq!(eval(&format!("keys({dict})")).unwrap(), "[a,b,]");
+        assert_eq!(eval(&format!("values({dict})")).unwrap(), "[1,2,]");
+        assert_eq!(
+            eval(&format!("has({dict}, \"a\") and not has({dict}, \"c\")")).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            eval(&format!("let d = {dict} len(remove(d, \"a\")) + len(d)")).unwrap(),
+            "3",
+            "dictionaries are copied"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use crate::parser::symbol::Identifier;
+
+use super::{
+    super::{
+        native::{NativeFunction, Natives},
+        value::{Type, Value},
+    },
+    string,
+};
+
+pub(super) fn register(natives: &mut Natives) {
+    // inputs that are not connected read the default of their type
+    natives.register(NativeFunction::new(
+        "read",
+        vec![Type::String],
+        Type::Any,
+        |context, values| {
+            let name = Identifier::from(string(&values[0]));
+            match context.ports().input(&name) {
+                Some(sink) => Ok(sink.value()),
+                None => Err(format!("there is no input named {name}").into()),
+            }
+        },
+    ));
+
+    // values are converted to the type of the output where possible
+    natives.register(NativeFunction::new(
+        "write",
+        vec![Type::String, Type::Any],
+        Type::Nil,
+        |context, values| {
+            let name = Identifier::from(string(&values[0]));
+            let Some(socket) = context.ports().output(&name) else {
+                return Err(format!("there is no output named {name}").into());
+            };
+
+            socket.send(values[1].clone()).map_err(|_| {
+                format!(
+                    "cannot write a value of type {} to output {name} of type {}",
+                    values[1].get_type(),
+                    socket.data_type()
+                )
+            })?;
+            Ok(Value::Nil)
+        },
+    ));
+}
@@ -0,0 +1,83 @@
+use super::{
+    super::{
+        native::{NativeFunction, Natives},
+        value::{Type, Value},
+    },
+    array, count, string,
+};
+
+pub(super) fn register(natives: &mut Natives) {
+    // an empty separator splits every character
+    natives.register(NativeFunction::new(
+        "split",
+        vec![Type::String, Type::String],
+        Type::Array(Box::new(Type::String)),
+        |_, values| {
+            let (text, separator) = (string(&values[0]), string(&values[1]));
+            let parts = if separator.is_empty() {
+                text.chars().map(|c| Value::String(c.to_string())).collect()
+            } else {
+                text.split(separator).map(Value::from).collect::<Vec<_>>()
+            };
+            Ok(parts.into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "join",
+        vec![Type::array(), Type::String],
+        Type::String,
+        |_, values| {
+            let parts = array(&values[0])
+                .borrow()
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>();
+            Ok(parts.join(string(&values[1])).into())
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "upper",
+        vec![Type::String],
+        Type::String,
+        |_, values| Ok(string(&values[0]).to_uppercase().into()),
+    ));
+
+    // position in characters of the first occurrence, `nil` if there is none
+    natives.register(NativeFunction::new(
+        "find",
+        vec![Type::String, Type::String],
+        Type::Any,
+        |_, values| {
+            let text = string(&values[0]);
+            Ok(match text.find(string(&values[1])) {
+                Some(i) => count(text[..i].chars().count()),
+                None => Value::Nil,
+            })
+        },
+    ));
+
+    natives.register(NativeFunction::new(
+        "replace",
+        vec![Type::String; 3],
+        Type::String,
+        |_, values| {
+            let (text, from, to) = (string(&values[0]), string(&values[1]), string(&values[2]));
+            Ok(text.replace(from, to).into())
+        },
+    ));
+
+    // `nil` if the string is not a number
+    natives.register(NativeFunction::new(
+        "parse_number",
+        vec![Type::String],
+        Type::Any,
+        |_, values| {
+            Ok(string(&values[0])
+                .trim()
+                .parse::<f64>()
+                .map_or(Value::Nil, Value::Number))
+        },
+    ));
+}
@@ -0,0 +1,311 @@
+//! Recordings of the values sockets carry over time.
+//!
+//! A [`Recorder`] subscribes to the watch channel of every socket it records and is sampled
+//! once per tick, so a socket written more than once in a tick shows only its last value, the
+//! same one the inputs connected to it read. Recordings are exported as JSON or as a Value
+//! Change Dump for waveform viewers.
+
+use std::io::{self, Write};
+
+use serde::{Serialize, Serializer};
+use tokio::sync::watch;
+
+use super::{
+    io::Socket,
+    value::{Type, Value},
+};
+
+/// Every value a signal took, along with the tick it changed at
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Signal {
+    pub name: String,
+
+    #[serde(rename = "type", serialize_with = "display")]
+    pub data_type: Type,
+
+    pub changes: Vec<Change>,
+}
+
+/// Value of a signal from a tick on, arrays and dictionaries are copied when recorded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub time: u64,
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Trace {
+    /// Tick of the last sample
+    pub end: u64,
+
+    pub signals: Vec<Signal>,
+}
+
+/// Records the values of sockets
+#[derive(Debug, Default)]
+pub struct Recorder {
+    trace: Trace,
+
+    /// Receivers of the signals of the trace, in the same order
+    receivers: Vec<watch::Receiver<Value>>,
+}
+
+impl Recorder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts recording a socket, with its current value as of the last sample
+    pub fn record(&mut self, name: impl Into<String>, socket: &Socket) {
+        let mut receiver = socket.subscribe();
+        let value = snapshot(&receiver.borrow_and_update());
+
+        self.trace.signals.push(Signal {
+            name: name.into(),
+            data_type: socket.data_type().clone(),
+            changes: vec![Change {
+                time: self.trace.end,
+                value,
+            }],
+        });
+        self.receivers.push(receiver);
+    }
+
+    /// Logs the signals that changed since the last sample, only the last value sent to each
+    /// since then is kept
+    pub fn sample(&mut self, time: u64) {
+        for (signal, receiver) in self.trace.signals.iter_mut().zip(&mut self.receivers) {
+            if !receiver.has_changed().unwrap_or(false) {
+                continue;
+            }
+
+            // sending the same value again is not a change
+            let value = snapshot(&receiver.borrow_and_update());
+            if signal.changes.last().map(|change| &change.value) != Some(&value) {
+                signal.changes.push(Change { time, value });
+            }
+        }
+
+        self.trace.end = time;
+    }
+
+    #[must_use]
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+}
+
+impl Trace {
+    /// Value Change Dump of the trace, a tick lasts one time unit. Signals named `scope.name`
+    /// are grouped in a scope, which is how circuits name the outputs of their chips. Signals
+    /// that are neither numbers nor booleans are dumped as strings, which needs a viewer
+    /// supporting the GTKWave extension for them.
+    ///
+    /// # Errors
+    ///
+    /// If writing fails
+    pub fn write_vcd(&self, mut vcd: impl Write) -> io::Result<()> {
+        writeln!(vcd, "$version meteor $end")?;
+        writeln!(vcd, "$timescale 1 s $end")?;
+        writeln!(vcd, "$scope module trace $end")?;
+
+        let mut scope = None;
+        for (i, signal) in self.signals.iter().enumerate() {
+            let (prefix, name) = match signal.name.split_once('.') {
+                Some((prefix, name)) => (Some(prefix), name),
+                None => (None, signal.name.as_str()),
+            };
+
+            if prefix != scope {
+                if scope.is_some() {
+                    writeln!(vcd, "$upscope $end")?;
+                }
+                if let Some(prefix) = prefix {
+                    writeln!(vcd, "$scope module {prefix} $end")?;
+                }
+                scope = prefix;
+            }
+
+            // `string` variables and the `s` changes of their values are not part of the VCD
+            // standard, they are an extension GTKWave reads and some other viewers skip
+            let (kind, size) = match signal.data_type {
+                Type::Number => ("real", 64),
+                Type::Bool => ("wire", 1),
+                _ => ("string", 1),
+            };
+            writeln!(vcd, "$var {kind} {size} {} {name} $end", code(i))?;
+        }
+        if scope.is_some() {
+            writeln!(vcd, "$upscope $end")?;
+        }
+        writeln!(vcd, "$upscope $end")?;
+        writeln!(vcd, "$enddefinitions $end")?;
+
+        let mut changes = self
+            .signals
+            .iter()
+            .enumerate()
+            .flat_map(|(i, signal)| signal.changes.iter().map(move |change| (change, i, signal)))
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|(change, i, _)| (change.time, *i));
+
+        let mut time = None;
+        for (change, i, signal) in changes {
+            if time != Some(change.time) {
+                writeln!(vcd, "#{}", change.time)?;
+                time = Some(change.time);
+            }
+
+            let code = code(i);
+            match (&signal.data_type, &change.value) {
+                (Type::Number, serde_json::Value::Number(n)) => writeln!(vcd, "r{n} {code}"),
+                (Type::Bool, serde_json::Value::Bool(b)) => {
+                    writeln!(vcd, "{}{code}", u8::from(*b))
+                }
+                // numbers that are not finite are recorded as null
+                (Type::Number, _) => writeln!(vcd, "rnan {code}"),
+                (Type::Bool, _) => writeln!(vcd, "x{code}"),
+                (_, serde_json::Value::String(text)) => writeln!(vcd, "s{} {code}", escape(text)),
+                (_, value) => writeln!(vcd, "s{} {code}", escape(&value.to_string())),
+            }?;
+        }
+
+        // keeps the last values on screen until the end of the trace
+        if time.is_some_and(|time| time < self.end) {
+            writeln!(vcd, "#{}", self.end)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy of a value that later changes to arrays cannot reach
+fn snapshot(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => serde_json::Number::from_f64(*n)
+            .map_or(serde_json::Value::Null, serde_json::Value::Number),
+        Value::String(text) => text.as_str().into(),
+        Value::Array(values) => values.borrow().iter().map(snapshot).collect(),
+        Value::Dictionary(dict) => dict
+            .iter()
+            .map(|(key, value)| (key.name().to_string(), snapshot(value)))
+            .collect(),
+        Value::Function(..) | Value::NativeFunction(..) => value.to_string().into(),
+    }
+}
+
+/// Short identifier of the signal at an index, made of the printable characters VCD allows
+fn code(mut i: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - b'!' + 1) as usize;
+
+    let mut code = String::new();
+    loop {
+        code.push(char::from(FIRST + u8::try_from(i % COUNT).unwrap()));
+        i /= COUNT;
+        if i == 0 {
+            return code;
+        }
+        i -= 1;
+    }
+}
+
+/// Strings in a dump cannot contain whitespace
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
+fn display<T: std::fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::{
+        super::{io::Socket, value::Type},
+        code, Recorder,
+    };
+
+    #[test]
+    fn recording() {
+        let level = Socket::new("level", Type::Number);
+        let alarm = Socket::new("alarm", Type::Bool);
+
+        let mut recorder = Recorder::new();
+        recorder.record("sensor.level", &level);
+        recorder.record("sensor.alarm", &alarm);
+
+        level.send(1.into()).unwrap();
+        level.send(2.5.into()).unwrap();
+        recorder.sample(1);
+        alarm.send(false.into()).unwrap();
+        recorder.sample(2);
+        alarm.send(true.into()).unwrap();
+        recorder.sample(3);
+        recorder.sample(5);
+
+        let trace = recorder.trace();
+        let times = |i: usize| {
+            trace.signals[i]
+                .changes
+                .iter()
+                .map(|change| change.time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(0), [0, 1], "only the last value of a tick is kept");
+        assert_eq!(times(1), [0, 3], "sending the same value is not a change");
+        assert_eq!(trace.end, 5);
+
+        let mut vcd = Vec::new();
+        trace.write_vcd(&mut vcd).unwrap();
+        assert_eq!(
+            String::from_utf8(vcd).unwrap(),
+            indoc! {"
+                $version meteor $end
+                $timescale 1 s $end
+                $scope module trace $end
+                $scope module sensor $end
+                $var real 64 ! level $end
+                $var wire 1 \" alarm $end
+                $upscope $end
+                $upscope $end
+                $enddefinitions $end
+                #0
+                r0.0 !
+                0\"
+                #1
+                r2.5 !
+                #3
+                1\"
+                #5
+            "}
+        );
+
+        assert_eq!(
+            serde_json::to_value(trace).unwrap()["signals"][0],
+            serde_json::json!({
+                "name": "sensor.level",
+                "type": "number",
+                "changes": [{ "time": 0, "value": 0.0 }, { "time": 1, "value": 2.5 }],
+            })
+        );
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(code(0), "!");
+        assert_eq!(code(93), "~");
+        assert_eq!(code(94), "!!");
+        assert_eq!(code(94 + 94 * 94), "!!!");
+    }
+}
@@ -8,10 +8,14 @@ use std::{
 
 use crate::parser::{
     ast::{self},
+    span::Spanned,
     symbol::Identifier,
 };
 
-use super::memory::{MemEnviornment, Memory};
+use super::{
+    memory::{MemEnviornment, Memory},
+    native::NativeFunction,
+};
 
 #[derive(Debug, Default, Hash, Clone, PartialEq, Eq)]
 pub enum Type {
@@ -27,6 +31,10 @@ pub enum Type {
     },
     User(Identifier, Vec<Type>),
     Array(Box<Type>),
+    Function {
+        params: Vec<Type>,
+        returns: Box<Type>,
+    },
 }
 
 impl Type {
@@ -41,14 +49,57 @@ impl Type {
         }
     }
 
+    /// Resolves a type annotation, `None` if it names an unknown type
+    pub fn resolve(ty: &ast::Type) -> Option<Self> {
+        match ty {
+            ast::Type::Named(identifier) => Some(match identifier.name() {
+                "int" | "float" | "number" => Type::Number,
+                "bool" => Type::Bool,
+                "str" => Type::String,
+                "nil" => Type::Nil,
+                "any" => Type::Any,
+                "dict" => Type::dict(),
+                "array" => Type::array(),
+
+                // TODO: support custom types
+                _ => return None,
+            }),
+            ast::Type::Generic(..) => None,
+            ast::Type::Function { params, returns } => Some(Type::Function {
+                params: params.iter().map(Self::resolve).collect::<Option<_>>()?,
+                returns: Box::new(match returns {
+                    Some(returns) => Self::resolve(returns)?,
+                    None => Type::Any,
+                }),
+            }),
+        }
+    }
+
+    /// Type of a function from its annotations, unannotated or unknown types are `any`
+    #[must_use]
+    pub fn signature(function: &ast::Function) -> Self {
+        let resolve = |ty: Option<&Spanned<ast::Type>>| {
+            ty.and_then(|(ty, _)| Self::resolve(ty)).unwrap_or_default()
+        };
+
+        Type::Function {
+            params: function
+                .arguments()
+                .iter()
+                .map(|(param, _)| resolve(param.data_type()))
+                .collect(),
+            returns: Box::new(resolve(function.returns())),
+        }
+    }
+
     pub fn default(&self) -> Value {
         match self {
-            Type::Any | Type::Nil => Value::Nil,
+            Type::Any | Type::Nil | Type::Function { .. } => Value::Nil,
             Type::String => "".into(),
             Type::Bool => false.into(),
             Type::Number => 0.into(),
-            Type::Dictionary { .. } => todo!("Dictionaries are not supported yet"),
-            Type::Array(..) => todo!("Arrays are not supported yet"),
+            Type::Dictionary { .. } => Value::Dictionary(Rc::default()),
+            Type::Array(..) => Value::Array(Rc::default()),
             Type::User(..) => todo!("Custom user types are not supported"),
         }
     }
@@ -89,6 +140,7 @@ pub enum Value {
     Number(f64),
     Array(Rc<RefCell<Vec<Value>>>),
     Function(Rc<Function>),
+    NativeFunction(Rc<NativeFunction>),
     Dictionary(Rc<HashMap<Identifier, Value>>),
     Nil,
 }
@@ -102,7 +154,15 @@ impl Value {
             | (Value::String(_), Type::String)
             | (Value::Bool(_), Type::Bool)
             | (Value::Number(_), Type::Number)
-            | (Value::Nil, Type::Nil) => true,
+            | (Value::Nil, Type::Nil)
+            | (Value::Array(_), Type::Array(_))
+            | (Value::Dictionary(_), Type::Dictionary { .. }) => true,
+            (Value::Function(func), Type::Function { params, .. }) => {
+                func.inner().arguments().len() == params.len()
+            }
+            (Value::NativeFunction(native), Type::Function { params, .. }) => {
+                native.accepts(params.len())
+            }
             _ => false,
         }
     }
@@ -113,7 +173,10 @@ impl Value {
             Value::Bool(b) => *b,
 
             Value::Number(b) => *b != 0.,
-            Value::Dictionary(..) | Value::Array(_) | Value::Function(_) => true,
+            Value::Dictionary(..)
+            | Value::Array(_)
+            | Value::Function(_)
+            | Value::NativeFunction(_) => true,
             Value::Nil => false,
         }
     }
@@ -146,7 +209,8 @@ impl Value {
             Value::Bool(_) => Type::Bool,
             Value::Number(_) => Type::Number,
             Value::Array(..) => Type::Array(Default::default()),
-            Value::Function(..) => todo!("Functions have no types"),
+            Value::Function(func) => Type::signature(func.inner()),
+            Value::NativeFunction(native) => native.signature(),
             Value::Dictionary(..) => Type::Dictionary {
                 key: Default::default(),
                 value: Default::default(),
@@ -194,6 +258,12 @@ from_value!(u32, Number);
 from_value!(f32, Number);
 from_value!(f64, Number);
 
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self::Array(Rc::new(RefCell::new(values)))
+    }
+}
+
 impl From<()> for Value {
     fn from(_: ()) -> Self {
         Self::Nil
@@ -207,6 +277,46 @@ impl PartialEq for Function {
     }
 }
 
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Any => f.write_str("any"),
+            Type::String => f.write_str("str"),
+            Type::Bool => f.write_str("bool"),
+            Type::Number => f.write_str("number"),
+            Type::Nil => f.write_str("nil"),
+            Type::Dictionary { value, .. } if **value == Type::Any => f.write_str("dict"),
+            Type::Dictionary { key, value } => write!(f, "dict<{key}, {value}>"),
+            Type::Array(item) if **item == Type::Any => f.write_str("array"),
+            Type::Array(item) => write!(f, "array<{item}>"),
+            Type::Function { params, returns } => {
+                f.write_str("func(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    param.fmt(f)?;
+                }
+                write!(f, ") -> {returns}")
+            }
+            Type::User(name, params) => {
+                f.write_str(name)?;
+                if !params.is_empty() {
+                    f.write_char('<')?;
+                    for (i, param) in params.iter().enumerate() {
+                        if i != 0 {
+                            f.write_str(", ")?;
+                        }
+                        param.fmt(f)?;
+                    }
+                    f.write_char('>')?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -230,6 +340,7 @@ impl Display for Value {
                 f.write_char('}')
             }
             Value::Function(..) => f.write_str("[function]"),
+            Value::NativeFunction(native) => write!(f, "[native function {}]", native.name()),
             Value::Nil => f.write_str("nil"),
         }
     }
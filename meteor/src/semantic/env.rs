@@ -2,14 +2,80 @@ use std::collections::{HashMap, HashSet};
 
 use logos::Source;
 
-use crate::parser::{
-    span::{Span, Spanned},
-    symbol::Identifier,
+use crate::{
+    parser::{
+        ast::{Expression, Program},
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::{native::Natives, value::Type},
 };
 
+/// Signatures of the functions provided by the runtime a program is analyzed for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Builtins {
+    functions: HashMap<Identifier, Type>,
+}
+
+impl Builtins {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Functions every chip provides
+    #[must_use]
+    pub fn std() -> Self {
+        Natives::std().builtins()
+    }
+
+    pub fn insert(&mut self, name: Identifier, signature: Type) {
+        self.functions.insert(name, signature);
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &Identifier) -> Option<&Type> {
+        self.functions.get(name)
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &Identifier) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Identifier, &Type)> {
+        self.functions.iter()
+    }
+
+    /// Builtins a program can use, its globals hide the ones of the same name everywhere
+    #[must_use]
+    pub fn visible(&self, program: &Program) -> Self {
+        self.without(
+            program
+                .expressions()
+                .iter()
+                .filter_map(|expr| match &expr.0 {
+                    Expression::Let { meta, .. } => Some(meta.0.name()),
+                    _ => None,
+                }),
+        )
+    }
+
+    /// Builtins without the ones globals of the given names hide
+    #[must_use]
+    pub fn without<'a>(&self, names: impl IntoIterator<Item = &'a Identifier>) -> Self {
+        let mut visible = self.clone();
+        for name in names {
+            visible.functions.remove(name);
+        }
+        visible
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct SymbolTable {
     symbols: HashMap<Identifier, Span>,
+    builtins: HashSet<Identifier>,
 }
 
 impl SymbolTable {
@@ -18,23 +84,25 @@ impl SymbolTable {
         Default::default()
     }
 
+    /// Scope of the names provided by the runtime, enclosing the globals of a program
     #[must_use]
-    pub fn std_include() -> Self {
-        Default::default()
+    pub fn std_include(builtins: &Builtins) -> Self {
+        Self {
+            symbols: HashMap::new(),
+            builtins: builtins.iter().map(|(name, _)| name.clone()).collect(),
+        }
     }
 
     pub fn push(&mut self, name: Spanned<Identifier>) {
         self.symbols.insert(name.0, name.1);
     }
 
+    /// Where a name is declared, builtins are not declared anywhere
+    pub fn span(&self, ident: &Identifier) -> Option<&Span> {
+        self.symbols.get(ident)
+    }
+
     pub fn contains(&self, ident: &Identifier) -> bool {
-        if self.symbols.contains_key(ident) {
-            true
-        } else {
-            match ident.name() {
-                "print" => true,
-                _ => false,
-            }
-        }
+        self.symbols.contains_key(ident) || self.builtins.contains(ident)
     }
 }
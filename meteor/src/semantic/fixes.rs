@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+
+use chumsky::span::Span as _;
+
+use crate::parser::{
+    ast::{Expression, Function, Program},
+    span::{Span, Spanned},
+    src::SourceId,
+};
+
+use super::{lints::ENTRYPOINT, Diagnostic, DiagnosticKind, Edit, Fix};
+
+/// Iterations a loop that never stops is bounded to
+pub const MAX_ITERATIONS: usize = 1000;
+
+/// Variable counting the iterations of a bounded loop, never reported as unused
+const COUNTER: &str = "_iterations";
+
+fn insert(span: &Span, offset: usize, text: impl Into<String>) -> Edit {
+    Edit {
+        span: Span::new(span.src(), offset..offset),
+        text: text.into(),
+    }
+}
+
+fn replace(span: &Span, text: impl Into<String>) -> Edit {
+    Edit {
+        span: span.clone(),
+        text: text.into(),
+    }
+}
+
+/// Fixes found in a program, along with the span and lint of the diagnostic they resolve
+struct Suggestions<'a> {
+    fixes: Vec<(&'a Span, &'static str, Fix)>,
+}
+
+impl<'a> Suggestions<'a> {
+    fn push(&mut self, span: &'a Span, kind: &DiagnosticKind, message: String, edits: Vec<Edit>) {
+        self.fixes.push((span, kind.lint(), Fix { message, edits }));
+    }
+
+    /// Wraps the statements at the top level of a file without an entrypoint in one
+    fn top_level(&mut self, program: &'a Program) {
+        let exprs = program.expressions();
+
+        let has_entrypoint = exprs.iter().any(|expr| match &expr.0 {
+            Expression::Let { meta, .. } => meta.0.name().name() == ENTRYPOINT,
+            _ => false,
+        });
+
+        let invalid = exprs
+            .iter()
+            .enumerate()
+            .filter(|(_, expr)| !matches!(expr.0, Expression::Func(..) | Expression::Let { .. }))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let (Some(&first), Some(&last)) = (invalid.first(), invalid.last()) else {
+            return;
+        };
+
+        // declarations in between would move out of the top level
+        if has_entrypoint || last - first + 1 != invalid.len() {
+            return;
+        }
+
+        let (first, last) = (&exprs[first].1, &exprs[last].1);
+        let edits = vec![
+            insert(
+                first,
+                first.range().start,
+                format!("func {ENTRYPOINT}() {{\n"),
+            ),
+            insert(last, last.range().end, "\n}"),
+        ];
+
+        for i in invalid {
+            self.push(
+                &exprs[i].1,
+                &DiagnosticKind::InvalidTopLevel,
+                format!("move into a `{ENTRYPOINT}` function"),
+                edits.clone(),
+            );
+        }
+    }
+
+    fn visit(&mut self, expr: &'a Spanned<Expression>) {
+        match &expr.0 {
+            Expression::Block(exprs) => {
+                for expr in exprs {
+                    self.statement(expr);
+                }
+            }
+
+            Expression::Func(function) => self.arguments(function),
+
+            _ => {}
+        }
+
+        for child in expr.0.children() {
+            self.visit(child);
+        }
+    }
+
+    fn statement(&mut self, expr: &'a Spanned<Expression>) {
+        match &expr.0 {
+            Expression::Block(exprs) if exprs.is_empty() => self.push(
+                &expr.1,
+                &DiagnosticKind::EmptyBlock,
+                "remove the block".to_string(),
+                vec![replace(&expr.1, "")],
+            ),
+
+            // an empty block in the source is read as an empty dictionary
+            Expression::Dictionary(pairs) if pairs.is_empty() => self.push(
+                &expr.1,
+                &DiagnosticKind::IgnoredOperation,
+                "remove the block".to_string(),
+                vec![replace(&expr.1, "")],
+            ),
+
+            // the counter is declared right before the loop, so it has to be a statement
+            Expression::While { condition, then } if matches!(then.0, Expression::Block(_)) => {
+                self.push(
+                    &expr.1,
+                    &DiagnosticKind::InfiniteLoop,
+                    format!("stop after {MAX_ITERATIONS} iterations"),
+                    vec![
+                        insert(&expr.1, expr.1.range().start, format!("let {COUNTER} = 0 ")),
+                        replace(&condition.1, format!("{COUNTER} < {MAX_ITERATIONS}")),
+                        insert(
+                            &then.1,
+                            then.1.range().start + 1,
+                            format!(" {COUNTER} = {COUNTER} + 1"),
+                        ),
+                    ],
+                );
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Renames arguments that a later one repeats the name of to one the function does not use,
+    /// the last argument of a name is the one the body sees, so it keeps it
+    fn arguments(&mut self, function: &'a Function) {
+        let mut used = HashSet::new();
+        names(function.body(), &mut used);
+        used.extend(
+            function
+                .arguments()
+                .iter()
+                .map(|arg| arg.0.name().name().to_string()),
+        );
+
+        let mut previous = HashMap::new();
+        for arg in function.arguments() {
+            let name = arg.0.name().name();
+            let Some(earlier) = previous.insert(name, &arg.1) else {
+                continue;
+            };
+
+            let mut n = 2;
+            while used.contains(&format!("{name}_{n}")) {
+                n += 1;
+            }
+            let renamed = format!("{name}_{n}");
+
+            let start = earlier.range().start;
+            self.push(
+                &arg.1,
+                &DiagnosticKind::DuplicateArgumentName(arg.0.name().clone()),
+                format!("rename the earlier `{name}` to `{renamed}`"),
+                vec![Edit {
+                    span: Span::new(earlier.src(), start..start + name.len()),
+                    text: renamed.clone(),
+                }],
+            );
+            used.insert(renamed);
+        }
+    }
+}
+
+/// Every name declared or referenced in an expression
+fn names(expr: &Spanned<Expression>, names: &mut HashSet<String>) {
+    match &expr.0 {
+        Expression::Ident(ident) => {
+            names.insert(ident.name().to_string());
+        }
+        Expression::Let { meta, .. } => {
+            names.insert(meta.0.name().name().to_string());
+        }
+        Expression::Func(function) => names.extend(
+            function
+                .arguments()
+                .iter()
+                .map(|arg| arg.0.name().name().to_string()),
+        ),
+        _ => {}
+    }
+
+    for child in expr.0.children() {
+        self::names(child, names);
+    }
+}
+
+/// Attaches a fix to every diagnostic there is a machine-applicable one for
+pub(super) fn suggest(program: &Program, diagnoses: &mut [Diagnostic]) {
+    let mut suggestions = Suggestions { fixes: vec![] };
+
+    suggestions.top_level(program);
+    for expr in program.expressions() {
+        suggestions.statement(expr);
+        suggestions.visit(expr);
+    }
+
+    for diagnostic in diagnoses.iter_mut().filter(|d| d.fix.is_none()) {
+        let lint = diagnostic.kind.lint();
+        diagnostic.fix = suggestions
+            .fixes
+            .iter()
+            .find(|(span, kind, _)| **span == diagnostic.span && *kind == lint)
+            .map(|(.., fix)| fix.clone());
+    }
+}
+
+/// Applies the fixes for a file to its source.
+///
+/// Fixes share edits when they resolve several diagnostics at once, repeated fixes are only
+/// applied once. Fixes that overlap one applied before them are skipped, checking again after
+/// applying the rest resolves them. Returns the fixed source along with the fixes applied to it.
+#[must_use]
+pub fn apply<'a>(
+    source: &str,
+    id: SourceId,
+    fixes: impl IntoIterator<Item = &'a Fix>,
+) -> (String, Vec<&'a Fix>) {
+    let mut applied: Vec<&Fix> = vec![];
+    let mut edits: Vec<&Edit> = vec![];
+
+    for fix in fixes {
+        if applied.contains(&fix) || fix.edits.iter().any(|edit| edit.span.src() != id) {
+            continue;
+        }
+
+        let overlaps = fix.edits.iter().any(|edit| {
+            edits.iter().any(|other| {
+                let (a, b) = (edit.span.range(), other.span.range());
+                a == b || (a.start < b.end && b.start < a.end)
+            })
+        });
+        if overlaps {
+            continue;
+        }
+
+        applied.push(fix);
+        edits.extend(&fix.edits);
+    }
+
+    edits.sort_by_key(|edit| (edit.span.range().start, edit.span.range().end));
+
+    let mut output = String::with_capacity(source.len());
+    let mut end = 0;
+    for edit in edits {
+        let range = edit.span.range();
+        output.push_str(&source[end..range.start]);
+        output.push_str(&edit.text);
+        end = range.end;
+    }
+    output.push_str(&source[end..]);
+
+    (output, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use chumsky::span::Span as _;
+
+    use crate::parser::span::Span;
+
+    use super::super::{analyze, Edit, Fix};
+
+    /// Source after applying every fix of its diagnostics
+    fn fix(source: &str) -> String {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let diagnoses = analyze(&program);
+
+        super::apply(
+            source,
+            SourceId::empty(),
+            diagnoses
+                .diagnostics
+                .iter()
+                .filter_map(|diagnostic| diagnostic.fix.as_ref()),
+        )
+        .0
+    }
+
+    #[test]
+    fn empty_blocks() {
+        assert_eq!(
+            fix("func main() { print(1) {} print(2) }"),
+            "func main() { print(1)  print(2) }"
+        );
+    }
+
+    #[test]
+    fn duplicate_arguments() {
+        assert_eq!(
+            fix("func f(a, a, a_2, a) => a + a_2"),
+            "func f(a_3, a_4, a_2, a) => a + a_2",
+            "the body keeps seeing the last argument of a name"
+        );
+    }
+
+    #[test]
+    fn top_level() {
+        assert_eq!(
+            fix("let a = 1\nprint(a)\nprint(a)"),
+            "let a = 1\nfunc main() {\nprint(a)\nprint(a)\n}"
+        );
+        assert_eq!(
+            fix("print(1) let a = 1 print(a)"),
+            "print(1) let a = 1 print(a)",
+            "declarations in between stay global"
+        );
+    }
+
+    #[test]
+    fn infinite_loops() {
+        let fixed = fix("func main() { while true { print(1) } }");
+        assert_eq!(
+            fixed,
+            "func main() { let _iterations = 0 while _iterations < 1000 { _iterations = _iterations + 1 print(1) } }"
+        );
+        assert_eq!(fix(&fixed), fixed);
+    }
+
+    #[test]
+    fn overlapping() {
+        let id = SourceId::empty();
+        let fix = |range, text: &str| Fix {
+            message: String::new(),
+            edits: vec![Edit {
+                span: Span::new(id, range),
+                text: text.to_string(),
+            }],
+        };
+
+        let fixes = [
+            fix(0..3, "x"),
+            fix(2..5, "y"),
+            fix(0..3, "x"),
+            fix(6..6, "z "),
+        ];
+        let (output, applied) = super::apply("let a = 1", id, &fixes);
+        assert_eq!(output, "x a z = 1");
+        assert_eq!(
+            applied,
+            [&fixes[0], &fixes[3]],
+            "skipped fixes are not applied"
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use crate::{
+    parser::{
+        ast::{Expression, Function},
+        span::{Span, Spanned},
+    },
+    runtime::{prelude::YIELDING, value::Type},
+};
+
+use super::{
+    fold::Constants,
+    resolve::{DefinitionKind, NameIndex},
+    Context, Diagnostic, DiagnosticKind, Severity,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+#[derive(Debug)]
+struct Node<'a> {
+    /// `None` for the end of the function
+    expr: Option<&'a Spanned<Expression>>,
+    successors: Vec<NodeId>,
+}
+
+/// Control flow graph of a function body.
+///
+/// Every expression is a node, in the order the runtime evaluates them, with an edge to each
+/// expression that can be evaluated next. Branches ruled out by constant conditions get no edge.
+#[derive(Debug)]
+pub struct ControlFlow<'a> {
+    nodes: Vec<Node<'a>>,
+    entry: NodeId,
+
+    /// Node each expression of a block starts at
+    blocks: Vec<Vec<(&'a Span, NodeId)>>,
+
+    /// Branches a constant condition skips, with the node of the condition guarding them
+    skipped: Vec<(&'a Span, NodeId, NodeId)>,
+}
+
+impl<'a> ControlFlow<'a> {
+    /// Control flow of a function body, nested functions are not part of it. Loops that yield
+    /// can be left, since the chip running them is expected to stop them from the outside.
+    #[must_use]
+    pub fn new(function: &'a Function, constants: &Constants, index: &NameIndex) -> Self {
+        let mut graph = Self {
+            nodes: vec![Node {
+                expr: None,
+                successors: vec![],
+            }],
+            entry: NodeId(0),
+            blocks: vec![],
+            skipped: vec![],
+        };
+
+        graph.entry = graph.build(function.body(), NodeId(0), constants, index);
+        graph
+    }
+
+    /// Whether the end of the function can be reached, so it returns at all
+    #[must_use]
+    pub fn returns(&self) -> bool {
+        self.reachable()[0]
+    }
+
+    /// Code that is never evaluated, only the first expression of each unreachable stretch
+    #[must_use]
+    pub fn unreachable(&self) -> Vec<&'a Span> {
+        let reachable = self.reachable();
+
+        let mut spans = self
+            .blocks
+            .iter()
+            .filter_map(|block| {
+                // when the first expression is unreachable so is the whole block
+                let i = block.iter().position(|(_, node)| !reachable[node.0])?;
+                (i > 0).then_some(block[i].0)
+            })
+            .chain(
+                self.skipped
+                    .iter()
+                    .filter(|(_, node, guard)| reachable[guard.0] && !reachable[node.0])
+                    .map(|(span, ..)| *span),
+            )
+            .collect::<Vec<_>>();
+
+        spans.sort_by_key(|span| span.range().start);
+        spans
+    }
+
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = vec![self.entry];
+
+        while let Some(node) = stack.pop() {
+            if !reachable[node.0] {
+                reachable[node.0] = true;
+                stack.extend(&self.nodes[node.0].successors);
+            }
+        }
+
+        reachable
+    }
+
+    fn node(&mut self, expr: &'a Spanned<Expression>, successors: Vec<NodeId>) -> NodeId {
+        self.nodes.push(Node {
+            expr: Some(expr),
+            successors,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Adds the nodes of an expression followed by `next`, returns where it starts
+    fn build(
+        &mut self,
+        expr: &'a Spanned<Expression>,
+        next: NodeId,
+        constants: &Constants,
+        index: &NameIndex,
+    ) -> NodeId {
+        match &expr.0 {
+            Expression::Block(exprs) => {
+                let mut entries = vec![];
+                let mut next = next;
+
+                for expr in exprs.iter().rev() {
+                    next = self.build(expr, next, constants, index);
+                    entries.push((&expr.1, next));
+                }
+
+                entries.reverse();
+                self.blocks.push(entries);
+                next
+            }
+
+            Expression::If {
+                condition,
+                then,
+                or_else,
+            } => {
+                let then_entry = self.build(then, next, constants, index);
+                let else_entry = self.build(or_else, next, constants, index);
+
+                let taken = constants.evaluate(condition).map(|value| value.truthy());
+                let successors = match taken {
+                    Some(true) => vec![then_entry],
+                    Some(false) => vec![else_entry],
+                    None => vec![then_entry, else_entry],
+                };
+
+                let branch = self.node(expr, successors);
+                let entry = self.build(condition, branch, constants, index);
+
+                match taken {
+                    // a missing else is not written out
+                    Some(true) if or_else.0 != Expression::Nil => {
+                        self.skipped.push((&or_else.1, else_entry, entry));
+                    }
+                    Some(false) => self.skipped.push((&then.1, then_entry, entry)),
+                    _ => {}
+                }
+
+                entry
+            }
+
+            Expression::While { condition, then } => {
+                let header = self.node(expr, vec![]);
+                let entry = self.build(condition, header, constants, index);
+                let body = self.build(then, entry, constants, index);
+
+                let taken = constants
+                    .evaluate(condition)
+                    .map(|value| value.truthy())
+                    .filter(|taken| !taken || !yields(then, index));
+                self.nodes[header.0].successors = match taken {
+                    Some(true) => vec![body],
+                    Some(false) => vec![next],
+                    None => vec![body, next],
+                };
+
+                if taken == Some(false) {
+                    self.skipped.push((&then.1, body, entry));
+                }
+
+                entry
+            }
+
+            // functions only run when called
+            Expression::Func(..) => self.node(expr, vec![next]),
+
+            _ => {
+                let node = self.node(expr, vec![next]);
+                expr.0
+                    .children()
+                    .into_iter()
+                    .rev()
+                    .fold(node, |next, child| {
+                        self.build(child, next, constants, index)
+                    })
+            }
+        }
+    }
+}
+
+/// Reports unreachable code in every function of a top level expression and loops that can
+/// never stop
+pub(super) fn check(
+    item: &Spanned<Expression>,
+    context: &Context,
+    constants: &Constants,
+    index: &NameIndex,
+) -> Vec<Diagnostic> {
+    let mut diagnoses = vec![];
+
+    visit(item, &mut |expr| match &expr.0 {
+        Expression::Func(function) => {
+            for span in ControlFlow::new(function, constants, index).unreachable() {
+                diagnoses.push(Diagnostic::new(
+                    DiagnosticKind::UnreachableCode,
+                    Severity::Warning,
+                    span.clone(),
+                ));
+            }
+        }
+
+        Expression::While { condition, then }
+            if constants.evaluate(condition).is_none()
+                && is_unchanged(index, context, condition, then) =>
+        {
+            diagnoses.push(Diagnostic::new(
+                DiagnosticKind::UnchangedLoopCondition,
+                Severity::Warning,
+                condition.1.clone(),
+            ));
+        }
+
+        _ => {}
+    });
+
+    diagnoses
+}
+
+/// Whether the body of a loop calls `yield` or `wait`, which let the rest of the chip run each
+/// time around. Calls in functions the loop only declares do not count.
+pub(super) fn yields(body: &Spanned<Expression>, index: &NameIndex) -> bool {
+    match &body.0 {
+        Expression::Func(..) => false,
+        Expression::Call { function, .. }
+            if matches!(&function.0, Expression::Ident(name) if YIELDING.contains(&name.name()))
+                && index.reference_at(&function.1).is_some_and(|reference| {
+                    reference
+                        .definition
+                        .is_some_and(|id| index.definition(id).kind == DefinitionKind::Builtin)
+                }) =>
+        {
+            true
+        }
+        expr => expr
+            .children()
+            .into_iter()
+            .any(|child| yields(child, index)),
+    }
+}
+
+fn visit<'a>(expr: &'a Spanned<Expression>, f: &mut impl FnMut(&'a Spanned<Expression>)) {
+    f(expr);
+    for child in expr.0.children() {
+        visit(child, f);
+    }
+}
+
+/// Whether nothing in the body of a loop can change the outcome of its condition
+fn is_unchanged(
+    index: &NameIndex,
+    context: &Context,
+    condition: &Spanned<Expression>,
+    body: &Spanned<Expression>,
+) -> bool {
+    // anything but plain variables could be changed through another reference
+    fn is_simple(expr: &Spanned<Expression>) -> bool {
+        match &expr.0 {
+            Expression::Ident(_)
+            | Expression::Nil
+            | Expression::String(_)
+            | Expression::Bool(_)
+            | Expression::Number(_) => true,
+            Expression::UnaryOp { rhs, .. } => is_simple(rhs),
+            Expression::BinaryOp { lhs, rhs, .. } => is_simple(lhs) && is_simple(rhs),
+            _ => false,
+        }
+    }
+
+    if !is_simple(condition) {
+        return false;
+    }
+
+    // natives cannot reach the variables of the program unless they are given a function
+    let is_native = |function: &Spanned<Expression>| {
+        let Expression::Ident(name) = &function.0 else {
+            return false;
+        };
+
+        let calls_back = matches!(
+            context.builtins.get(name),
+            Some(Type::Function { params, .. })
+                if params.iter().any(|param| matches!(param, Type::Function { .. }))
+        );
+
+        !calls_back
+            && index.references().iter().any(|reference| {
+                reference.span == function.1
+                    && reference
+                        .definition
+                        .is_some_and(|id| index.definition(id).kind == DefinitionKind::Builtin)
+            })
+    };
+
+    let mut calls = false;
+    visit(body, &mut |expr| {
+        if let Expression::Call { function, .. } = &expr.0 {
+            calls |= !is_native(function);
+        }
+    });
+
+    let variables = index
+        .references()
+        .iter()
+        .filter(|reference| condition.1.contains(&reference.span));
+
+    let mut any = false;
+    for reference in variables {
+        let Some(id) = reference.definition else {
+            return false;
+        };
+
+        if index.definition(id).kind == DefinitionKind::Builtin {
+            continue;
+        }
+
+        let mut writes = index.references_to(id).filter(|reference| reference.write);
+
+        // a called function could assign to the variable from somewhere else, globals can be
+        // assigned to by the rest of the file
+        let changed = if calls {
+            writes.next().is_some()
+                || index
+                    .definition(id)
+                    .span
+                    .as_ref()
+                    .and_then(|span| context.outline.global_at(span))
+                    .is_some_and(|global| global.assigned)
+        } else {
+            writes.any(|write| body.1.contains(&write.span))
+        };
+
+        if changed {
+            return false;
+        }
+        any = true;
+    }
+
+    any
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{ast::Expression, src::SourceId};
+
+    use super::{
+        super::{
+            fold::Constants, outline::Outline, resolve::NameIndex, Builtins, Context,
+            DiagnosticKind,
+        },
+        ControlFlow,
+    };
+
+    fn unreachable(source: &str) -> Vec<String> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let Some(Expression::Let { init, .. }) = program.expressions().last().map(|expr| &expr.0)
+        else {
+            panic!("expected a declaration");
+        };
+        let Expression::Func(function) = &init.0 else {
+            panic!("expected a function");
+        };
+
+        let index = NameIndex::new(&program);
+        let constants = Constants::new(&program, &index);
+        ControlFlow::new(function, &constants, &index)
+            .unreachable()
+            .into_iter()
+            .map(|span| source[span.range()].to_string())
+            .collect()
+    }
+
+    fn loops(source: &str) -> usize {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let index = NameIndex::new(&program);
+        let outline = Outline::new(&program, &index);
+        let context = Context::new(&outline, &Builtins::std());
+        let constants = Constants::new(&program, &index);
+
+        program
+            .expressions()
+            .iter()
+            .flat_map(|item| super::check(item, &context, &constants, &index))
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::UnchangedLoopCondition)
+            .count()
+    }
+
+    #[test]
+    fn unreachable_code() {
+        assert_eq!(
+            unreachable("func main() { while true { print(1) } print(2) print(3) }"),
+            vec!["print(2)"]
+        );
+        assert_eq!(
+            unreachable("let debug = false func main() { if debug { print(1) } else { 2 } if not debug { 3 } }"),
+            vec!["{ print(1) }"]
+        );
+        assert_eq!(
+            unreachable(
+                "func main(a) { while a { a = a - 1 } let b = func() { while true { } 1 } }"
+            ),
+            Vec::<String>::new(),
+            "nested functions have their own graph"
+        );
+        assert_eq!(
+            unreachable("func main() { while true { yield() } print(1) }"),
+            Vec::<String>::new(),
+            "loops that yield can be stopped"
+        );
+        assert_eq!(
+            unreachable("func main() { while true { let f = func() { wait(1) } } print(1) }"),
+            vec!["print(1)"]
+        );
+    }
+
+    #[test]
+    fn returns() {
+        let program = crate::parse(
+            SourceId::empty(),
+            "func f(a) { while true { a } } func g(a) { while a { } }",
+        )
+        .unwrap();
+        let index = NameIndex::new(&program);
+        let constants = Constants::new(&program, &index);
+
+        let returns = program
+            .expressions()
+            .iter()
+            .map(|expr| match &expr.0 {
+                Expression::Let { init, .. } => match &init.0 {
+                    Expression::Func(function) => {
+                        ControlFlow::new(function, &constants, &index).returns()
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(returns, vec![false, true]);
+    }
+
+    #[test]
+    fn unchanged_conditions() {
+        assert_eq!(
+            loops("func main(a) { let i = 0 while i < a { print(i) } }"),
+            1
+        );
+        assert_eq!(
+            loops("func main(a) { let i = 0 while i < a { i = i + 1 } }"),
+            0
+        );
+        assert_eq!(
+            loops("let i = 0 func step() { i = i + 1 } func main() { while i < 3 { step() } }"),
+            0,
+            "calls can change globals"
+        );
+        assert_eq!(
+            loops("let i = 0 func step(x) { i = i + x } func main(xs) { while i < 3 { map(xs, step) } }"),
+            0,
+            "natives can call the functions they are given"
+        );
+        assert_eq!(loops("func main(a) { while a[0] { } }"), 0);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use crate::{
+    parser::{
+        ast::{Expression, Function, Program},
+        operator::Operator,
+        span::{Span, Spanned},
+        src::SourceId,
+    },
+    runtime::value::Value,
+};
+
+use super::{
+    outline::Outline,
+    resolve::{DefinitionId, DefinitionKind, NameIndex},
+};
+
+/// Where a name starts, identifying a definition or reference across files
+type Position = (SourceId, usize);
+
+fn position(span: &Span) -> Position {
+    (span.src(), span.range().start)
+}
+
+/// Values of the expressions that can be computed without running the program.
+///
+/// Variables are constant when they are never assigned to after being initialized with a
+/// constant expression.
+pub struct Constants {
+    /// Definition each read of a variable refers to
+    references: HashMap<Position, DefinitionId>,
+    values: HashMap<DefinitionId, Value>,
+}
+
+impl Constants {
+    #[must_use]
+    pub fn new(program: &Program, index: &NameIndex) -> Self {
+        let mut constants = Self::with_references(index);
+        let definitions = definitions(index, |kind| {
+            matches!(kind, DefinitionKind::Global | DefinitionKind::Local)
+        });
+
+        for expr in program.expressions() {
+            constants.collect(expr, &definitions);
+        }
+
+        constants
+    }
+
+    /// Constants of a single top level expression, the values of the globals of its file come
+    /// from the outline
+    #[must_use]
+    pub fn for_item(item: &Spanned<Expression>, index: &NameIndex, outline: &Outline) -> Self {
+        let mut constants = Self::with_references(index);
+
+        for global in outline.globals() {
+            let value = global
+                .constant
+                .as_ref()
+                .and_then(|literal| constants.evaluate(&(literal.clone(), global.span.clone())));
+
+            if let (Some(id), Some(value)) = (index.definition_at(&global.span), value) {
+                constants.values.insert(id, value);
+            }
+        }
+
+        let definitions = definitions(index, |kind| kind == DefinitionKind::Local);
+        constants.collect(item, &definitions);
+
+        constants
+    }
+
+    fn with_references(index: &NameIndex) -> Self {
+        let references = index
+            .references()
+            .iter()
+            .filter(|reference| !reference.write)
+            .filter_map(|reference| Some((position(&reference.span), reference.definition?)))
+            .collect();
+
+        Self {
+            references,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Literal of the value a variable always holds, numbers that cannot be written in source
+    /// are left out
+    #[must_use]
+    pub fn literal(&self, id: DefinitionId) -> Option<Expression> {
+        self.values.get(&id).cloned().and_then(literal_of)
+    }
+
+    /// Value of an expression, if it is the same every time it is evaluated
+    #[must_use]
+    pub fn evaluate(&self, expr: &Spanned<Expression>) -> Option<Value> {
+        match &expr.0 {
+            Expression::Nil => Some(Value::Nil),
+            Expression::Bool(b) => Some(Value::Bool(*b)),
+            Expression::Number(n) => Some(Value::Number(*n)),
+            Expression::String(str) => Some(Value::String(str.clone())),
+
+            Expression::Ident(_) => self
+                .references
+                .get(&position(&expr.1))
+                .and_then(|id| self.values.get(id))
+                .cloned(),
+
+            Expression::UnaryOp { operator, rhs } => match (operator, self.evaluate(rhs)?) {
+                (Operator::Sub, Value::Number(n)) => Some(Value::Number(-n)),
+                (Operator::Not, value) => Some(Value::Bool(value.falsey())),
+                _ => None,
+            },
+
+            Expression::BinaryOp { lhs, operator, rhs } if *operator != Operator::Assign => {
+                binary(*operator, self.evaluate(lhs)?, self.evaluate(rhs)?)
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Replaces every constant expression with its value
+    #[must_use]
+    pub fn fold(&self, expr: &Spanned<Expression>) -> Spanned<Expression> {
+        let literal = matches!(
+            expr.0,
+            Expression::Nil | Expression::Bool(_) | Expression::Number(_) | Expression::String(_)
+        );
+
+        if !literal {
+            if let Some(folded) = self.evaluate(expr).and_then(literal_of) {
+                return (folded, expr.1.clone());
+            }
+        }
+
+        let fold = |expr: &Spanned<Expression>| Box::new(self.fold(expr));
+
+        let folded = match &expr.0 {
+            Expression::Array(items) => {
+                Expression::Array(items.iter().map(|item| self.fold(item)).collect())
+            }
+
+            Expression::Dictionary(pairs) => Expression::Dictionary(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.fold(value)))
+                    .collect(),
+            ),
+
+            Expression::Func(function) => Expression::Func(Box::new(
+                Function::new(function.arguments().to_vec(), self.fold(function.body()))
+                    .with_returns(function.returns().cloned()),
+            )),
+
+            Expression::Let { meta, init } => Expression::Let {
+                meta: meta.clone(),
+                init: fold(init),
+            },
+
+            Expression::Block(exprs) => {
+                Expression::Block(exprs.iter().map(|expr| self.fold(expr)).collect())
+            }
+
+            Expression::If {
+                condition,
+                then,
+                or_else,
+            } => Expression::If {
+                condition: fold(condition),
+                then: fold(then),
+                or_else: fold(or_else),
+            },
+
+            Expression::While { condition, then } => Expression::While {
+                condition: fold(condition),
+                then: fold(then),
+            },
+
+            Expression::PropertyAccess { lhs, property } => Expression::PropertyAccess {
+                lhs: fold(lhs),
+                property: property.clone(),
+            },
+
+            Expression::ArrayIndex { lhs, index } => Expression::ArrayIndex {
+                lhs: fold(lhs),
+                index: fold(index),
+            },
+
+            // the target of an assignment is not a value
+            Expression::BinaryOp {
+                lhs,
+                operator: Operator::Assign,
+                rhs,
+            } if matches!(lhs.0, Expression::Ident(_)) => Expression::BinaryOp {
+                lhs: lhs.clone(),
+                operator: Operator::Assign,
+                rhs: fold(rhs),
+            },
+
+            Expression::BinaryOp { lhs, operator, rhs } => Expression::BinaryOp {
+                lhs: fold(lhs),
+                operator: *operator,
+                rhs: fold(rhs),
+            },
+
+            Expression::UnaryOp { operator, rhs } => Expression::UnaryOp {
+                operator: *operator,
+                rhs: fold(rhs),
+            },
+
+            Expression::Call {
+                function,
+                arguments,
+            } => Expression::Call {
+                function: fold(function),
+                arguments: arguments.iter().map(|arg| self.fold(arg)).collect(),
+            },
+
+            expr @ (Expression::Error
+            | Expression::Nil
+            | Expression::Ident(_)
+            | Expression::String(_)
+            | Expression::Bool(_)
+            | Expression::Number(_)) => expr.clone(),
+        };
+
+        (folded, expr.1.clone())
+    }
+
+    /// Records the value of every constant variable, in the order they are initialized
+    fn collect(
+        &mut self,
+        expr: &Spanned<Expression>,
+        definitions: &HashMap<Position, DefinitionId>,
+    ) {
+        for child in expr.0.children() {
+            self.collect(child, definitions);
+        }
+
+        if let Expression::Let { meta, init } = &expr.0 {
+            let Some(id) = definitions.get(&position(&meta.1)) else {
+                return;
+            };
+
+            if let Some(value) = self.evaluate(init) {
+                self.values.insert(*id, value);
+            }
+        }
+    }
+}
+
+/// Variables of the given kinds that are never assigned to, by where they are declared
+fn definitions(
+    index: &NameIndex,
+    kinds: impl Fn(DefinitionKind) -> bool,
+) -> HashMap<Position, DefinitionId> {
+    index
+        .definitions()
+        .filter(|(id, definition)| {
+            kinds(definition.kind) && index.references_to(*id).all(|reference| !reference.write)
+        })
+        .filter_map(|(id, definition)| Some((position(definition.span.as_ref()?), id)))
+        .collect()
+}
+
+/// Rewrites a program with all of its constant expressions evaluated
+#[must_use]
+pub fn fold(program: &Program) -> Program {
+    let constants = Constants::new(program, &NameIndex::new(program));
+
+    Program::new(
+        program.directives().clone(),
+        program
+            .expressions()
+            .iter()
+            .map(|expr| constants.fold(expr))
+            .collect(),
+    )
+    .with_attributes(program.all_attributes().to_vec())
+}
+
+/// Applies an operator the same way the runtime does
+#[allow(clippy::float_cmp)]
+fn binary(operator: Operator, lhs: Value, rhs: Value) -> Option<Value> {
+    Some(match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => match operator {
+            Operator::Sub => Value::Number(a - b),
+            Operator::Add => Value::Number(a + b),
+            Operator::Mul => Value::Number(a * b),
+            Operator::Div => Value::Number(a / b),
+            Operator::Mod => Value::Number(a % b),
+            Operator::Equals => Value::Bool(a == b),
+            Operator::NotEqual => Value::Bool(a != b),
+            Operator::Greater => Value::Bool(a > b),
+            Operator::GreaterOrEqual => Value::Bool(a >= b),
+            Operator::Less => Value::Bool(a < b),
+            Operator::LessOrEqual => Value::Bool(a <= b),
+            _ => return None,
+        },
+
+        (Value::Bool(a), Value::Bool(b)) => Value::Bool(match operator {
+            Operator::Or => a || b,
+            Operator::And => a && b,
+            Operator::Nor => !a && !b,
+            Operator::Xor => a ^ b,
+            Operator::Equals => a == b,
+            Operator::NotEqual => a != b,
+            _ => return None,
+        }),
+
+        (Value::String(a), Value::String(b)) => match operator {
+            Operator::Add => Value::String(a + &b),
+            Operator::Equals => Value::Bool(a == b),
+            Operator::NotEqual => Value::Bool(a != b),
+            _ => return None,
+        },
+
+        _ => return None,
+    })
+}
+
+/// Literal expression evaluating to a value, numbers that cannot be written in source are kept
+fn literal_of(value: Value) -> Option<Expression> {
+    Some(match value {
+        Value::Nil => Expression::Nil,
+        Value::Bool(b) => Expression::Bool(b),
+        Value::Number(n) if n.is_finite() => Expression::Number(n),
+        Value::String(str) => Expression::String(str),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parser::{ast::Expression, src::SourceId},
+        runtime::value::Value,
+    };
+
+    use super::{super::resolve::NameIndex, Constants};
+
+    /// Value of the initializer of the last top level declaration
+    fn evaluate(source: &str) -> Option<Value> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let Some(Expression::Let { init, .. }) = program.expressions().last().map(|expr| &expr.0)
+        else {
+            panic!("expected a declaration");
+        };
+
+        Constants::new(&program, &NameIndex::new(&program)).evaluate(init)
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(evaluate("let a = 1 + 2 * 3"), Some(Value::Number(7.)));
+        assert_eq!(evaluate("let a = -(4 % 3) < 0"), Some(Value::Bool(true)));
+        assert_eq!(
+            evaluate("let a = true xor not nil"),
+            Some(Value::Bool(false))
+        );
+        assert_eq!(
+            evaluate("let a = \"con\" + \"cat\""),
+            Some(Value::String("concat".into()))
+        );
+        assert_eq!(evaluate("let a = 1 + true"), None);
+        assert_eq!(evaluate("let a = [1] let b = a[0]"), None);
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(evaluate("let a = 2 let b = a * a"), Some(Value::Number(4.)));
+        assert_eq!(evaluate("let a = 2 func f() { a = 3 } let b = a"), None);
+        assert_eq!(
+            evaluate("func f(n) => n + 1 let a = f(1)"),
+            None,
+            "calls are not evaluated"
+        );
+    }
+
+    #[test]
+    fn rewrite() {
+        let program = crate::parse(
+            SourceId::empty(),
+            "let size = 4 * 2 let mask = 0 func main() { mask = size - 1 print(size / 0, mask) }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            super::fold(&program).to_string(),
+            crate::parse(
+                SourceId::empty(),
+                "let size = 8 let mask = 0 func main() { mask = 7 print(8 / 0, mask) }"
+            )
+            .unwrap()
+            .to_string()
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{
+    ast::{Directive, Expression, Program},
+    span::Span,
+};
+
+use super::{Diagnostic, DiagnosticKind, Severity, LINTS};
+
+/// How a lint is reported, overriding its default severity
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// allow
+    Allow,
+
+    /// warn
+    Warn,
+
+    /// deny
+    Deny,
+}
+
+impl Level {
+    /// Level a directive like `@allow(name)` sets
+    #[must_use]
+    pub fn from_directive(directive: &Directive) -> Option<Self> {
+        match directive.name().name() {
+            "allow" => Some(Self::Allow),
+            "warn" => Some(Self::Warn),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+
+    /// Severity a diagnostic is reported with, `None` if it is not reported at all
+    #[must_use]
+    pub fn severity(self) -> Option<Severity> {
+        match self {
+            Self::Allow => None,
+            Self::Warn => Some(Severity::Warning),
+            Self::Deny => Some(Severity::Error),
+        }
+    }
+}
+
+/// Lint levels of a file, the innermost scope wins
+struct Levels<'a> {
+    project: &'a HashMap<String, Level>,
+    file: HashMap<&'a str, Level>,
+
+    /// Top level declarations along with the levels set right before them
+    declarations: Vec<(&'a Span, HashMap<&'a str, Level>)>,
+}
+
+impl Levels<'_> {
+    fn level(&self, diagnostic: &Diagnostic) -> Option<Level> {
+        let lint = diagnostic.kind.lint();
+
+        self.declarations
+            .iter()
+            .filter(|(span, _)| span.contains(&diagnostic.span))
+            .find_map(|(_, levels)| levels.get(lint))
+            .or_else(|| self.file.get(lint))
+            .or_else(|| self.project.get(lint))
+            .copied()
+    }
+}
+
+/// Reads the lint directives of a program, diagnosing the ones naming unknown lints
+fn directives<'a>(
+    directives: impl Iterator<Item = &'a (Directive, Span)>,
+    diagnoses: &mut Vec<Diagnostic>,
+) -> HashMap<&'a str, Level> {
+    let mut levels = HashMap::new();
+
+    for (directive, _) in directives {
+        let Some(level) = Level::from_directive(directive) else {
+            continue;
+        };
+
+        for (param, span) in directive.params() {
+            let kind = match param {
+                Expression::Ident(name) if LINTS.contains(&name.name()) => {
+                    levels.insert(name.name(), level);
+                    continue;
+                }
+                Expression::Ident(name) => DiagnosticKind::UnknownLint(name.clone()),
+                _ => DiagnosticKind::InvalidLintDirective,
+            };
+
+            diagnoses.push(Diagnostic::new(kind, Severity::Warning, span.clone()));
+        }
+    }
+
+    levels
+}
+
+/// Changes the severity of diagnostics according to the project config and the `@allow`,
+/// `@warn` and `@deny` directives of the program, dropping the allowed ones.
+///
+/// Directives at the top of a file apply to all of it, the ones in between top level
+/// declarations only to the declaration after them.
+pub(super) fn apply(
+    program: &Program,
+    project: &HashMap<String, Level>,
+    mut diagnoses: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    let file = directives(program.directives().iter(), &mut diagnoses);
+
+    let declarations = program
+        .expressions()
+        .iter()
+        .enumerate()
+        .map(|(i, (_, span))| (span, directives(program.attributes(i), &mut diagnoses)))
+        .filter(|(_, levels)| !levels.is_empty())
+        .collect();
+
+    // directives after the last declaration still get checked
+    directives(
+        program.attributes(program.expressions().len()),
+        &mut diagnoses,
+    );
+
+    let levels = Levels {
+        project,
+        file,
+        declarations,
+    };
+
+    diagnoses
+        .into_iter()
+        .filter_map(|mut diagnostic| {
+            if let Some(level) = levels.level(&diagnostic) {
+                diagnostic.severity = level.severity()?;
+            }
+            Some(diagnostic)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::parser::src::SourceId;
+
+    use super::{
+        super::{Diagnostic, DiagnosticKind, Severity},
+        Level,
+    };
+
+    fn apply(source: &str, project: &[(&str, Level)]) -> Vec<(String, Severity)> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let project = project
+            .iter()
+            .map(|(name, level)| ((*name).to_string(), *level))
+            .collect::<HashMap<_, _>>();
+
+        // one diagnostic for every top level declaration
+        let diagnoses = program
+            .expressions()
+            .iter()
+            .map(|(_, span)| {
+                Diagnostic::new(DiagnosticKind::EmptyBlock, Severity::Warning, span.clone())
+            })
+            .collect();
+
+        super::apply(&program, &project, diagnoses)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.kind.lint().to_string(), diagnostic.severity))
+            .collect()
+    }
+
+    #[test]
+    fn scopes() {
+        let empty = || ("empty_block".to_string(), Severity::Warning);
+        let denied = || ("empty_block".to_string(), Severity::Error);
+
+        assert_eq!(
+            apply("func a() {} func b() {}", &[]),
+            vec![empty(), empty()]
+        );
+        assert_eq!(
+            apply("func a() {} func b() {}", &[("empty_block", Level::Deny)]),
+            vec![denied(), denied()]
+        );
+        assert_eq!(
+            apply(
+                "@allow(empty_block) func a() {} @warn(empty_block) func b() {}",
+                &[("empty_block", Level::Deny)]
+            ),
+            vec![empty()],
+            "directives override the project"
+        );
+        assert_eq!(
+            apply("func a() {} @deny(empty_block) func b() {}", &[]),
+            vec![empty(), denied()],
+            "declaration directives only apply to the next declaration"
+        );
+    }
+
+    #[test]
+    fn unknown_lints() {
+        assert_eq!(
+            apply("@allow(empty_blocks, 1) func a() {}", &[]),
+            vec![
+                ("empty_block".to_string(), Severity::Warning),
+                ("unknown_lint".to_string(), Severity::Warning),
+                ("invalid_lint_directive".to_string(), Severity::Warning),
+            ]
+        );
+        assert_eq!(
+            apply("@allow(unknown_lint) @allow(empty_blocks) func a() {}", &[]),
+            vec![("empty_block".to_string(), Severity::Warning)]
+        );
+    }
+}
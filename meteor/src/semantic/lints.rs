@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use crate::parser::{
+    ast::{Expression, Program},
+    span::{Span, Spanned},
+    symbol::Identifier,
+};
+
+use super::{
+    resolve::{DefinitionId, DefinitionKind, NameIndex},
+    Diagnostic, DiagnosticKind, Severity,
+};
+
+/// Function the runtime calls after evaluating the top level
+pub(super) const ENTRYPOINT: &str = "main";
+
+/// Declaration in the source, found by the start of its name
+struct Declaration<'a> {
+    span: &'a Span,
+
+    /// Body of the function the variable is initialized with, uses inside of it are recursion
+    function: Option<&'a Span>,
+}
+
+/// Reports variables of a top level expression that are never read, shadowed or only ever
+/// written to, unused functions are reported for the whole file by [`unused_functions`].
+///
+/// Names starting with `_` are never reported.
+pub(super) fn lint(item: &Spanned<Expression>, index: &NameIndex) -> Vec<Diagnostic> {
+    let mut declarations = HashMap::new();
+    collect(item, &mut declarations);
+
+    let mut diagnoses = vec![];
+
+    for (id, definition) in index.definitions() {
+        let Some(declaration) = definition
+            .span
+            .as_ref()
+            .and_then(|span| declarations.get(&span.range().start))
+        else {
+            continue;
+        };
+
+        if definition.name.name().starts_with('_') {
+            continue;
+        }
+
+        if let Some(shadowed) = definition.shadows {
+            let mut diagnostic = Diagnostic::new(
+                DiagnosticKind::ShadowedVariable(definition.name.clone()),
+                Severity::Hint,
+                declaration.span.clone(),
+            );
+            if let Some(span) = &index.definition(shadowed).span {
+                diagnostic = diagnostic.with_related(span.clone(), "shadowed declaration");
+            }
+            diagnoses.push(diagnostic);
+        }
+
+        let (reads, writes) = usage(index, id, declaration.function);
+
+        let (kind, severity) = match definition.kind {
+            _ if reads > 0 => continue,
+
+            DefinitionKind::Parameter => (
+                DiagnosticKind::UnusedParameter(definition.name.clone()),
+                Severity::Hint,
+            ),
+
+            DefinitionKind::Local if writes > 0 => (
+                DiagnosticKind::WriteOnlyVariable(definition.name.clone()),
+                Severity::Warning,
+            ),
+
+            DefinitionKind::Local => (
+                DiagnosticKind::UnusedVariable(definition.name.clone()),
+                Severity::Warning,
+            ),
+
+            _ => continue,
+        };
+
+        diagnoses.push(unused(kind, severity, declaration.span, &definition.name));
+    }
+
+    diagnoses
+}
+
+/// Reports global functions that are never called
+pub(super) fn unused_functions(program: &Program, index: &NameIndex) -> Vec<Diagnostic> {
+    // without an entrypoint the file is a module and all of its globals are exports
+    let library = !program.expressions().iter().any(|expr| match &expr.0 {
+        Expression::Let { meta, .. } => meta.0.name().name() == ENTRYPOINT,
+        _ => false,
+    });
+    if library {
+        return vec![];
+    }
+
+    program
+        .expressions()
+        .iter()
+        .filter_map(|expr| {
+            let Expression::Let { meta, init } = &expr.0 else {
+                return None;
+            };
+            let Expression::Func(..) = init.0 else {
+                return None;
+            };
+
+            let name = meta.0.name();
+            if name.name().starts_with('_') || name.name() == ENTRYPOINT {
+                return None;
+            }
+
+            let id = index.definition_at(&meta.1)?;
+            (usage(index, id, Some(&init.1)) == (0, 0)).then(|| {
+                let kind = DiagnosticKind::UnusedFunction(name.clone());
+                unused(kind, Severity::Warning, &meta.1, name)
+            })
+        })
+        .collect()
+}
+
+/// How often a variable is read and written, uses inside of the body of the function it is
+/// initialized with are recursion and do not count
+fn usage(index: &NameIndex, id: DefinitionId, function: Option<&Span>) -> (usize, usize) {
+    index
+        .references_to(id)
+        .filter(|reference| function.map_or(true, |body| !body.contains(&reference.span)))
+        .fold((0, 0), |(reads, writes), reference| {
+            if reference.write {
+                (reads, writes + 1)
+            } else {
+                (reads + 1, writes)
+            }
+        })
+}
+
+fn unused(kind: DiagnosticKind, severity: Severity, span: &Span, name: &Identifier) -> Diagnostic {
+    Diagnostic::new(kind, severity, span.clone()).with_note(format!(
+        "prefix the name with an underscore if this is intended: `_{name}`"
+    ))
+}
+
+fn collect<'a>(expr: &'a Spanned<Expression>, declarations: &mut HashMap<usize, Declaration<'a>>) {
+    match &expr.0 {
+        Expression::Let { meta, init } => {
+            let function = match &init.0 {
+                Expression::Func(..) => Some(&init.1),
+                _ => None,
+            };
+
+            declarations.insert(
+                meta.1.range().start,
+                Declaration {
+                    span: &meta.1,
+                    function,
+                },
+            );
+        }
+
+        Expression::Func(function) => {
+            for (_, span) in function.arguments() {
+                declarations.insert(
+                    span.range().start,
+                    Declaration {
+                        span,
+                        function: None,
+                    },
+                );
+            }
+        }
+
+        _ => {}
+    }
+
+    for child in expr.0.children() {
+        collect(child, declarations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use super::super::{resolve::NameIndex, DiagnosticKind};
+
+    fn lint(source: &str) -> Vec<String> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let index = NameIndex::new(&program);
+
+        program
+            .expressions()
+            .iter()
+            .flat_map(|item| super::lint(item, &index))
+            .chain(super::unused_functions(&program, &index))
+            .map(|diagnostic| match diagnostic.kind {
+                DiagnosticKind::UnusedVariable(name) => format!("unused {name}"),
+                DiagnosticKind::UnusedParameter(name) => format!("parameter {name}"),
+                DiagnosticKind::WriteOnlyVariable(name) => format!("write-only {name}"),
+                DiagnosticKind::ShadowedVariable(name) => format!("shadowed {name}"),
+                DiagnosticKind::UnusedFunction(name) => format!("function {name}"),
+                kind => panic!("unexpected {kind}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            lint("func main(a, b, _c) { let x = 1 let y = 2 y = 3 let z = 4 let _w = 5 print(b, z) }"),
+            vec!["parameter a", "unused x", "write-only y"]
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            lint("let a = 1 func main(a) { if a { let a = 2 print(a) } let _a = a }"),
+            vec!["shadowed a", "shadowed a"]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            lint(
+                "func loop(n) => loop(n) func used() => 1 func _spare() => 2 func main() => used()"
+            ),
+            vec!["function loop"]
+        );
+
+        // a module without an entrypoint exports its functions
+        assert!(lint("func helper(n) => n").is_empty());
+    }
+}
@@ -3,15 +3,32 @@ use std::collections::{HashMap, HashSet};
 
 use chumsky::container::{Container, Seq};
 use displaydoc::Display;
+pub use env::Builtins;
 use env::SymbolTable;
+use outline::Outline;
+use resolve::{DefinitionKind, NameIndex};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+pub use types::TypeEnv;
 
 mod env;
-
-use crate::parser::{
-    ast::{Expression, Program},
-    operator::Operator,
-    span::{Span, Spanned},
-    symbol::Identifier,
+pub mod fixes;
+pub mod flow;
+pub mod fold;
+pub mod levels;
+mod lints;
+pub mod outline;
+pub mod resolve;
+mod types;
+
+use crate::{
+    config::Config,
+    parser::{
+        ast::{self, Expression, Program},
+        operator::Operator,
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::value::{Type, Value},
 };
 
 #[derive(Display, Debug, Clone, PartialEq)]
@@ -19,12 +36,21 @@ pub enum DiagnosticKind {
     /// Unknown variable `{0}`
     UnknownVariable(Identifier),
 
+    /// `{0}` is used before it is declared
+    UsedBeforeDeclaration(Identifier),
+
     /// Invalid top level expression, can only be a variable or function declaration
     InvalidTopLevel,
 
-    /// Infinite loops are not supported and will crash your chip
+    /// Loop never stops and never yields, so it runs until the chip is out of fuel
     InfiniteLoop,
 
+    /// Nothing inside of the loop changes its condition, so it either never runs or never stops
+    UnchangedLoopCondition,
+
+    /// Unreachable code
+    UnreachableCode,
+
     /// Condition is always {0}
     ConditionIsConstant(bool),
 
@@ -45,9 +71,173 @@ pub enum DiagnosticKind {
 
     /// Cannot declare a new variable inside an expression
     InvalidInlineExpression,
+
+    /// Expected `{expected}` but found `{found}`
+    MismatchedType { expected: Type, found: Type },
+
+    /// Unknown type `{0}`
+    UnknownType(ast::Type),
+
+    /// Cannot apply `{operator}` to `{lhs}` and `{rhs}`
+    InvalidOperands {
+        operator: Operator,
+        lhs: Type,
+        rhs: Type,
+    },
+
+    /// Cannot apply `{operator}` to `{rhs}`
+    InvalidOperand { operator: Operator, rhs: Type },
+
+    /// Values of type `{0}` cannot be called
+    NotCallable(Type),
+
+    /// Expected {expected} arguments but found {found}
+    WrongArgumentCount { expected: usize, found: usize },
+
+    /// Values of type `{0}` cannot be indexed
+    NotIndexable(Type),
+
+    /// Values of type `{0}` have no properties
+    NoProperties(Type),
+
+    /// Variable `{0}` is never used
+    UnusedVariable(Identifier),
+
+    /// Parameter `{0}` is never used
+    UnusedParameter(Identifier),
+
+    /// Variable `{0}` is assigned to but never read
+    WriteOnlyVariable(Identifier),
+
+    /// `{0}` shadows a variable from an outer scope
+    ShadowedVariable(Identifier),
+
+    /// Function `{0}` is never called
+    UnusedFunction(Identifier),
+
+    /// Unknown lint `{0}`
+    UnknownLint(Identifier),
+
+    /// Expected the name of a lint
+    InvalidLintDirective,
+
+    /// Module `{module}` has no export named `{name}`
+    UnknownExport {
+        module: Identifier,
+        name: Identifier,
+    },
+}
+
+/// Names of all lints, see [`DiagnosticKind::lint`]
+pub const LINTS: &[&str] = &[
+    "unknown_variable",
+    "used_before_declaration",
+    "invalid_top_level",
+    "infinite_loop",
+    "unchanged_loop_condition",
+    "unreachable_code",
+    "constant_condition",
+    "negative_array_index",
+    "fractional_array_index",
+    "ignored_operation",
+    "empty_block",
+    "duplicate_argument_name",
+    "invalid_inline_expression",
+    "mismatched_type",
+    "unknown_type",
+    "invalid_operands",
+    "invalid_operand",
+    "not_callable",
+    "wrong_argument_count",
+    "not_indexable",
+    "no_properties",
+    "unused_variable",
+    "unused_parameter",
+    "write_only_variable",
+    "shadowed_variable",
+    "unused_function",
+    "unknown_lint",
+    "invalid_lint_directive",
+    "unknown_export",
+];
+
+impl DiagnosticKind {
+    /// Stable name of the lint, used to change its level with `@allow(name)`, `@warn(name)` and
+    /// `@deny(name)` or in the project config
+    #[must_use]
+    pub fn lint(&self) -> &'static str {
+        match self {
+            Self::UnknownVariable(..) => "unknown_variable",
+            Self::UsedBeforeDeclaration(..) => "used_before_declaration",
+            Self::InvalidTopLevel => "invalid_top_level",
+            Self::InfiniteLoop => "infinite_loop",
+            Self::UnchangedLoopCondition => "unchanged_loop_condition",
+            Self::UnreachableCode => "unreachable_code",
+            Self::ConditionIsConstant(..) => "constant_condition",
+            Self::NegativeArrayIndex => "negative_array_index",
+            Self::FractionalArrayIndex => "fractional_array_index",
+            Self::IgnoredOperation => "ignored_operation",
+            Self::EmptyBlock => "empty_block",
+            Self::DuplicateArgumentName(..) => "duplicate_argument_name",
+            Self::InvalidInlineExpression => "invalid_inline_expression",
+            Self::MismatchedType { .. } => "mismatched_type",
+            Self::UnknownType(..) => "unknown_type",
+            Self::InvalidOperands { .. } => "invalid_operands",
+            Self::InvalidOperand { .. } => "invalid_operand",
+            Self::NotCallable(..) => "not_callable",
+            Self::WrongArgumentCount { .. } => "wrong_argument_count",
+            Self::NotIndexable(..) => "not_indexable",
+            Self::NoProperties(..) => "no_properties",
+            Self::UnusedVariable(..) => "unused_variable",
+            Self::UnusedParameter(..) => "unused_parameter",
+            Self::WriteOnlyVariable(..) => "write_only_variable",
+            Self::ShadowedVariable(..) => "shadowed_variable",
+            Self::UnusedFunction(..) => "unused_function",
+            Self::UnknownLint(..) => "unknown_lint",
+            Self::InvalidLintDirective => "invalid_lint_directive",
+            Self::UnknownExport { .. } => "unknown_export",
+        }
+    }
+
+    /// Stable code of the diagnostic, codes are never reused once a kind is removed
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownVariable(..) => "M0001",
+            Self::UsedBeforeDeclaration(..) => "M0002",
+            Self::InvalidTopLevel => "M0003",
+            Self::InfiniteLoop => "M0004",
+            Self::UnchangedLoopCondition => "M0005",
+            Self::UnreachableCode => "M0006",
+            Self::ConditionIsConstant(..) => "M0007",
+            Self::NegativeArrayIndex => "M0008",
+            Self::FractionalArrayIndex => "M0009",
+            Self::IgnoredOperation => "M0010",
+            Self::EmptyBlock => "M0011",
+            Self::DuplicateArgumentName(..) => "M0012",
+            Self::InvalidInlineExpression => "M0013",
+            Self::MismatchedType { .. } => "M0014",
+            Self::UnknownType(..) => "M0015",
+            Self::InvalidOperands { .. } => "M0016",
+            Self::InvalidOperand { .. } => "M0017",
+            Self::NotCallable(..) => "M0018",
+            Self::WrongArgumentCount { .. } => "M0019",
+            Self::NotIndexable(..) => "M0020",
+            Self::NoProperties(..) => "M0021",
+            Self::UnusedVariable(..) => "M0022",
+            Self::UnusedParameter(..) => "M0023",
+            Self::WriteOnlyVariable(..) => "M0024",
+            Self::ShadowedVariable(..) => "M0025",
+            Self::UnusedFunction(..) => "M0026",
+            Self::UnknownLint(..) => "M0027",
+            Self::InvalidLintDirective => "M0028",
+            Self::UnknownExport { .. } => "M0029",
+        }
+    }
 }
 
-#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Severity {
     /// Hint
     Hint,
@@ -60,34 +250,188 @@ pub enum Severity {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Diagnostic<'a> {
+pub struct Diagnostic {
     pub kind: DiagnosticKind,
     pub severity: Severity,
-    pub span: &'a Span,
+    pub span: Span,
+
+    /// Other places in the source that explain the diagnostic
+    pub related: Vec<Label>,
+
+    pub notes: Vec<String>,
+
+    /// Suggested change to the source that resolves the diagnostic
+    pub fix: Option<Fix>,
+}
+
+/// Span of the source along with what it has to do with a diagnostic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
 }
 
-impl Diagnostic<'_> {
+/// Replacement of the source text in a span, an empty span inserts the text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+/// Edits that together resolve a diagnostic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn new(kind: DiagnosticKind, severity: Severity, span: Span) -> Self {
+        Self {
+            kind,
+            severity,
+            span,
+            related: vec![],
+            notes: vec![],
+            fix: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_related(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
     pub fn reason(&self) -> String {
         format!("{}", self.kind)
     }
+
+    /// Same diagnostic with every span in it replaced
+    #[must_use]
+    pub fn map_spans(mut self, f: impl Fn(&Span) -> Span) -> Self {
+        self.span = f(&self.span);
+        for label in &mut self.related {
+            label.span = f(&label.span);
+        }
+        for edit in self.fix.iter_mut().flat_map(|fix| &mut fix.edits) {
+            edit.span = f(&edit.span);
+        }
+        self
+    }
+}
+
+impl Serialize for Diagnostic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut diagnostic = serializer.serialize_struct("Diagnostic", 8)?;
+        diagnostic.serialize_field("code", self.kind.code())?;
+        diagnostic.serialize_field("lint", self.kind.lint())?;
+        diagnostic.serialize_field("severity", &self.severity)?;
+        diagnostic.serialize_field("message", &self.reason())?;
+        diagnostic.serialize_field("span", &self.span)?;
+        diagnostic.serialize_field("related", &self.related)?;
+        diagnostic.serialize_field("notes", &self.notes)?;
+        diagnostic.serialize_field("fix", &self.fix)?;
+        diagnostic.end()
+    }
+}
+
+pub struct Diagnoses {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// What a top level expression is analyzed against besides itself
+#[derive(Debug, Clone)]
+pub struct Context<'a> {
+    pub outline: &'a Outline,
+
+    /// Builtins the file can use, see [`Outline::visible`]
+    pub builtins: Builtins,
+
+    /// Exports of the imported modules by namespace, the ones that could not be found are left out
+    pub exports: HashMap<Identifier, Vec<Identifier>>,
 }
 
-pub struct Diagnoses<'a> {
-    pub diagnostics: Vec<Diagnostic<'a>>,
+/// Diagnostics of a single top level expression
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemDiagnostics {
+    /// Reported before the type errors of the file
+    pub errors: Vec<Diagnostic>,
+
+    /// Unused and shadowed variables, reported after the type errors of the file
+    pub lints: Vec<Diagnostic>,
+}
+
+impl<'a> Context<'a> {
+    #[must_use]
+    pub fn new(outline: &'a Outline, builtins: &Builtins) -> Self {
+        Self {
+            outline,
+            builtins: outline.visible(builtins),
+            exports: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_exports(mut self, namespace: Identifier, exports: Vec<Identifier>) -> Self {
+        self.exports.insert(namespace, exports);
+        self
+    }
+}
+
+impl ItemDiagnostics {
+    /// Same diagnostics with every span in them replaced
+    #[must_use]
+    pub fn map_spans(self, f: impl Fn(&Span) -> Span) -> Self {
+        let map = |diagnostics: Vec<Diagnostic>| {
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.map_spans(&f))
+                .collect()
+        };
+
+        Self {
+            errors: map(self.errors),
+            lints: map(self.lints),
+        }
+    }
 }
 
 struct Analyzer<'a> {
-    program: &'a Program,
-    diagnoses: Vec<Diagnostic<'a>>,
+    context: &'a Context<'a>,
+    diagnoses: Vec<Diagnostic>,
     symbols: Vec<SymbolTable>,
+
+    /// Every top level declaration, functions only run once all of them are initialized
+    globals: SymbolTable,
+
+    /// How many function bodies the expression being analyzed is nested in
+    functions: usize,
+
+    index: &'a NameIndex,
+    constants: fold::Constants,
 }
 
 impl<'a> Analyzer<'a> {
-    fn new(program: &'a Program) -> Self {
+    fn new(context: &'a Context<'a>, index: &'a NameIndex, constants: fold::Constants) -> Self {
         Self {
+            context,
             diagnoses: vec![],
             symbols: vec![],
-            program,
+            globals: SymbolTable::new(),
+            functions: 0,
+            index,
+            constants,
         }
     }
 
@@ -110,34 +454,45 @@ impl<'a> Analyzer<'a> {
         self.symbols.pop().expect("Mismatch pop/push");
     }
 
-    fn analyze_prog(mut self) -> Diagnoses<'a> {
-        self.validate_top_level();
+    /// Analyzes the top level expression at a position of the file
+    fn analyze_item(mut self, item: &'a Spanned<Expression>, position: usize) -> Vec<Diagnostic> {
+        self.validate_top_level(item);
+
+        self.symbols
+            .push(SymbolTable::std_include(&self.context.builtins));
 
-        self.symbols.push(SymbolTable::std_include());
+        // imported modules are bound to their namespace
+        for (namespace, span) in self.context.outline.imports() {
+            self.add_symbol((namespace.clone(), span.clone()));
+        }
 
-        for expr in self.program.expressions() {
-            self.analyze(expr);
+        for global in self.context.outline.globals() {
+            let symbol = (global.name.clone(), global.span.clone());
+            self.globals.push(symbol.clone());
+            if global.item < position {
+                self.add_symbol(symbol);
+            }
         }
 
+        self.analyze(item);
         self.pop_scope();
 
-        Diagnoses {
-            diagnostics: self.diagnoses,
-        }
+        let flow = flow::check(item, self.context, &self.constants, self.index);
+        self.diagnoses.extend(flow);
+
+        self.diagnoses
     }
 
-    fn validate_top_level(&mut self) {
-        for prog in self.program.expressions() {
-            match prog.0 {
-                Expression::Func(..) | Expression::Let { .. } => {}
+    fn validate_top_level(&mut self, item: &Spanned<Expression>) {
+        match item.0 {
+            Expression::Func(..) | Expression::Let { .. } => {}
 
-                _ => {
-                    self.diagnose(Diagnostic {
-                        kind: DiagnosticKind::InvalidTopLevel,
-                        severity: Severity::Warning,
-                        span: &prog.1,
-                    });
-                }
+            _ => {
+                self.diagnose(Diagnostic::new(
+                    DiagnosticKind::InvalidTopLevel,
+                    Severity::Warning,
+                    item.1.clone(),
+                ));
             }
         }
     }
@@ -161,29 +516,36 @@ impl<'a> Analyzer<'a> {
             Expression::While { .. } => {}
 
             Expression::Let { meta, init } => {
-                self.analyze_inline(init);
+                let symbol = (meta.0.name().clone(), meta.1.clone());
 
-                self.add_symbol((meta.0.name().clone(), meta.1.clone()));
+                // functions capture their scope, so they can see their own name
+                if let Expression::Func(..) = init.0 {
+                    self.add_symbol(symbol);
+                    self.analyze_inline(init);
+                } else {
+                    self.analyze_inline(init);
+                    self.add_symbol(symbol);
+                }
                 return;
             }
 
             Expression::Block(exprs) => {
                 if exprs.is_empty() {
-                    self.diagnose(Diagnostic {
-                        kind: DiagnosticKind::EmptyBlock,
-                        severity: Severity::Warning,
-                        span: &expr.1,
-                    });
+                    self.diagnose(Diagnostic::new(
+                        DiagnosticKind::EmptyBlock,
+                        Severity::Warning,
+                        expr.1.clone(),
+                    ));
                 }
             }
 
             Expression::BinaryOp { operator, .. } if operator == &Operator::Assign => {}
 
-            _ => self.diagnose(Diagnostic {
-                kind: DiagnosticKind::IgnoredOperation,
-                severity: Severity::Warning,
-                span: &expr.1,
-            }),
+            _ => self.diagnose(Diagnostic::new(
+                DiagnosticKind::IgnoredOperation,
+                Severity::Warning,
+                expr.1.clone(),
+            )),
         }
         self.analyze_inline(expr);
     }
@@ -208,42 +570,54 @@ impl<'a> Analyzer<'a> {
             Expression::Dictionary(vec) => self.analyze_each_inline(vec.iter().map(|(_, v)| v)),
 
             Expression::Func(function) => {
-                let mut duplicates = HashSet::new();
+                let mut duplicates: HashMap<_, &Span> = HashMap::new();
 
                 self.push_scope();
+                self.functions += 1;
 
                 for arg in function.arguments() {
                     let name = arg.0.name().clone();
 
-                    if duplicates.contains(&name) {
-                        self.diagnose(Diagnostic {
-                            kind: DiagnosticKind::DuplicateArgumentName(name),
-                            severity: Severity::Warning,
-                            span: &arg.1,
-                        });
+                    if let Some(first) = duplicates.get(&name) {
+                        self.diagnose(
+                            Diagnostic::new(
+                                DiagnosticKind::DuplicateArgumentName(name),
+                                Severity::Warning,
+                                arg.1.clone(),
+                            )
+                            .with_related((*first).clone(), "first argument with this name"),
+                        );
                     } else {
-                        duplicates.insert(name);
+                        duplicates.insert(name, &arg.1);
                         self.add_symbol((arg.0.name().clone(), arg.1.clone()));
                     }
                 }
 
                 self.analyze_inline(function.body());
+                self.functions -= 1;
                 self.pop_scope();
             }
 
             Expression::While { condition, then } => {
-                self.check_condition(expr, condition);
+                // loops that yield are how a chip runs forever, so their condition is meant to
+                // be constant
+                let yields = flow::yields(then, self.index);
+                if !yields {
+                    self.check_condition(expr, condition);
+                }
+                self.analyze_inline(condition);
 
-                if let Expression::Bool(cond) = condition.0 {
-                    if cond {
-                        self.diagnose(Diagnostic {
-                            kind: DiagnosticKind::InfiniteLoop,
-                            severity: Severity::Warning,
-                            span: &expr.1,
-                        });
-                    }
-                } else {
-                    self.analyze_inline(condition);
+                if !yields
+                    && self
+                        .constants
+                        .evaluate(condition)
+                        .is_some_and(|value| value.truthy())
+                {
+                    self.diagnose(Diagnostic::new(
+                        DiagnosticKind::InfiniteLoop,
+                        Severity::Warning,
+                        expr.1.clone(),
+                    ));
                 }
 
                 self.analyze_inline(then);
@@ -251,11 +625,11 @@ impl<'a> Analyzer<'a> {
             }
 
             Expression::Let { .. } => {
-                self.diagnose(Diagnostic {
-                    kind: DiagnosticKind::InvalidInlineExpression,
-                    severity: Severity::Warning,
-                    span: &expr.1,
-                });
+                self.diagnose(Diagnostic::new(
+                    DiagnosticKind::InvalidInlineExpression,
+                    Severity::Warning,
+                    expr.1.clone(),
+                ));
             }
 
             Expression::If {
@@ -269,29 +643,30 @@ impl<'a> Analyzer<'a> {
                 self.analyze_inline(or_else);
             }
 
-            Expression::PropertyAccess { lhs, property: _ } => {
+            Expression::PropertyAccess { lhs, property } => {
                 self.analyze_inline(lhs);
+                self.check_export(expr, lhs, property);
             }
 
             Expression::ArrayIndex { lhs, index } => {
                 self.analyze_inline(lhs);
                 self.analyze_inline(index);
 
-                if let Expression::Number(n) = index.0 {
+                if let Some(Value::Number(n)) = self.constants.evaluate(index) {
                     if n < 0. {
-                        self.diagnose(Diagnostic {
-                            kind: DiagnosticKind::NegativeArrayIndex,
-                            severity: Severity::Warning,
-                            span: &index.1,
-                        });
+                        self.diagnose(Diagnostic::new(
+                            DiagnosticKind::NegativeArrayIndex,
+                            Severity::Warning,
+                            index.1.clone(),
+                        ));
                     }
 
                     if n.trunc() != n {
-                        self.diagnose(Diagnostic {
-                            kind: DiagnosticKind::FractionalArrayIndex,
-                            severity: Severity::Warning,
-                            span: &index.1,
-                        });
+                        self.diagnose(Diagnostic::new(
+                            DiagnosticKind::FractionalArrayIndex,
+                            Severity::Warning,
+                            index.1.clone(),
+                        ));
                     }
                 }
             }
@@ -318,12 +693,26 @@ impl<'a> Analyzer<'a> {
             }
 
             Expression::Ident(ident) => {
-                if !self.is_symbol_present(ident) {
-                    self.diagnose(Diagnostic {
-                        kind: DiagnosticKind::UnknownVariable(ident.clone()),
-                        severity: Severity::Error,
-                        span: &expr.1,
-                    });
+                if self.is_symbol_present(ident) {
+                    return;
+                }
+
+                if !self.globals.contains(ident) {
+                    self.diagnose(Diagnostic::new(
+                        DiagnosticKind::UnknownVariable(ident.clone()),
+                        Severity::Error,
+                        expr.1.clone(),
+                    ));
+                } else if self.functions == 0 {
+                    let mut diagnostic = Diagnostic::new(
+                        DiagnosticKind::UsedBeforeDeclaration(ident.clone()),
+                        Severity::Error,
+                        expr.1.clone(),
+                    );
+                    if let Some(span) = self.globals.span(ident) {
+                        diagnostic = diagnostic.with_related(span.clone(), "declared here");
+                    }
+                    self.diagnose(diagnostic);
                 }
             }
 
@@ -339,27 +728,553 @@ impl<'a> Analyzer<'a> {
         }
     }
 
-    fn diagnose(&mut self, diagnostic: Diagnostic<'a>) {
+    fn diagnose(&mut self, diagnostic: Diagnostic) {
         self.diagnoses.push(diagnostic);
     }
 
+    /// Flags members of an imported module that it does not export
+    fn check_export(
+        &mut self,
+        expr: &Spanned<Expression>,
+        lhs: &Spanned<Expression>,
+        property: &Identifier,
+    ) {
+        let Expression::Ident(namespace) = &lhs.0 else {
+            return;
+        };
+        let Some(exports) = self.context.exports.get(namespace) else {
+            return;
+        };
+        let Some(definition) = self
+            .index
+            .reference_at(&lhs.1)
+            .and_then(|reference| reference.definition)
+            .map(|id| self.index.definition(id))
+            .filter(|definition| definition.kind == DefinitionKind::Import)
+        else {
+            return;
+        };
+
+        if exports.contains(property) {
+            return;
+        }
+
+        let mut diagnostic = Diagnostic::new(
+            DiagnosticKind::UnknownExport {
+                module: namespace.clone(),
+                name: property.clone(),
+            },
+            Severity::Error,
+            expr.1.clone(),
+        );
+        if let Some(span) = &definition.span {
+            diagnostic = diagnostic.with_related(span.clone(), "imported here");
+        }
+        self.diagnose(diagnostic);
+    }
+
     fn check_condition(&mut self, expr: &'a Spanned<Expression>, cond: &'a Spanned<Expression>) {
-        let b = match cond.0 {
-            Expression::Bool(b) => b,
-            Expression::Number(n) => n != 0.,
-            Expression::String(ref str) => str.is_empty(),
-            Expression::Nil => false,
-            _ => return,
+        let Some(value) = self.constants.evaluate(cond) else {
+            return;
         };
 
-        self.diagnose(Diagnostic {
-            kind: DiagnosticKind::ConditionIsConstant(b),
-            severity: Severity::Hint,
-            span: &expr.1,
-        });
+        self.diagnose(Diagnostic::new(
+            DiagnosticKind::ConditionIsConstant(value.truthy()),
+            Severity::Hint,
+            expr.1.clone(),
+        ));
+    }
+}
+
+/// Flags the globals functions called from the top level read before they are initialized,
+/// following the calls they make to other global functions
+fn check_initialized(program: &Program, index: &NameIndex) -> Vec<Diagnostic> {
+    let mut globals = HashMap::new();
+    let mut bodies = HashMap::new();
+    for expr in program.expressions() {
+        if let Expression::Let { meta, init } = &expr.0 {
+            globals.insert(meta.0.name(), &meta.1);
+            if let Expression::Func(function) = &init.0 {
+                bodies.insert(meta.0.name(), function.as_ref());
+            }
+        }
+    }
+
+    // whether the identifier at a span refers to a global of the program
+    let is_global = |name: &Identifier, span: &Span| {
+        index
+            .reference_at(span)
+            .and_then(|reference| reference.definition)
+            .is_some_and(|id| {
+                let definition = index.definition(id);
+                definition.kind == DefinitionKind::Global && &definition.name == name
+            })
+    };
+
+    let mut initialized = HashSet::new();
+    let mut diagnoses = vec![];
+
+    for expr in program.expressions() {
+        let mut calls = vec![];
+        top_level_calls(expr, &mut calls);
+
+        for (call, name, span) in calls {
+            // calling a function before its declaration is already reported
+            if !is_global(name, span) || !initialized.contains(name) {
+                continue;
+            }
+
+            let mut pending = vec![name];
+            let mut called = HashSet::new();
+            let mut reported = HashSet::new();
+
+            while let Some(name) = pending.pop() {
+                let Some(body) = bodies.get(name).map(|function| function.body()) else {
+                    continue;
+                };
+                if !called.insert(name) {
+                    continue;
+                }
+
+                let mut reads = vec![];
+                reads_and_calls(body, &mut reads);
+
+                for (ident, span, is_call) in reads {
+                    if !is_global(ident, span) {
+                        continue;
+                    }
+
+                    if is_call {
+                        pending.push(ident);
+                    }
+
+                    if initialized.contains(ident) || !reported.insert(ident) {
+                        continue;
+                    }
+
+                    let mut diagnostic = Diagnostic::new(
+                        DiagnosticKind::UsedBeforeDeclaration(ident.clone()),
+                        Severity::Error,
+                        call.clone(),
+                    )
+                    .with_related(span.clone(), format!("`{name}` reads it here"));
+                    if let Some(span) = globals.get(ident) {
+                        diagnostic = diagnostic.with_related((*span).clone(), "declared here");
+                    }
+                    diagnoses.push(diagnostic);
+                }
+            }
+        }
+
+        if let Expression::Let { meta, .. } = &expr.0 {
+            initialized.insert(meta.0.name());
+        }
     }
+
+    diagnoses
 }
 
-pub fn analyze<'a>(program: &'a Program) -> Diagnoses<'a> {
-    Analyzer::new(program).analyze_prog()
+/// Calls of named functions a top level expression makes while it is evaluated, innermost first
+fn top_level_calls<'a>(
+    expr: &'a Spanned<Expression>,
+    calls: &mut Vec<(&'a Span, &'a Identifier, &'a Span)>,
+) {
+    if let Expression::Func(..) = expr.0 {
+        return;
+    }
+
+    for child in expr.0.children() {
+        top_level_calls(child, calls);
+    }
+
+    if let Expression::Call { function, .. } = &expr.0 {
+        if let Expression::Ident(name) = &function.0 {
+            calls.push((&expr.1, name, &function.1));
+        }
+    }
+}
+
+/// Identifiers a function body evaluates when it runs, with whether they are called directly,
+/// the bodies of nested functions only run once they are called
+fn reads_and_calls<'a>(
+    expr: &'a Spanned<Expression>,
+    reads: &mut Vec<(&'a Identifier, &'a Span, bool)>,
+) {
+    match &expr.0 {
+        Expression::Ident(ident) => reads.push((ident, &expr.1, false)),
+        Expression::Func(..) => {}
+        Expression::Call {
+            function,
+            arguments,
+        } => {
+            match &function.0 {
+                Expression::Ident(ident) => reads.push((ident, &function.1, true)),
+                _ => reads_and_calls(function, reads),
+            }
+            for argument in arguments {
+                reads_and_calls(argument, reads);
+            }
+        }
+        _ => {
+            for child in expr.0.children() {
+                reads_and_calls(child, reads);
+            }
+        }
+    }
+}
+
+pub fn analyze(program: &Program) -> Diagnoses {
+    analyze_with(program, &Config::default())
+}
+
+/// Analyzes a program with the lint levels of a project config
+pub fn analyze_with(program: &Program, config: &Config) -> Diagnoses {
+    analyze_with_builtins(program, config, &Builtins::std())
+}
+
+/// Analyzes a program for a chip providing other native functions than the standard ones
+#[must_use]
+pub fn analyze_with_builtins(program: &Program, config: &Config, builtins: &Builtins) -> Diagnoses {
+    let index = NameIndex::with_builtins(program, builtins);
+    let outline = Outline::new(program, &index);
+    let context = Context::new(&outline, builtins);
+    let mut env = TypeEnv::new(&outline);
+
+    let mut items = vec![];
+    let mut types = vec![];
+    for (position, item) in program.expressions().iter().enumerate() {
+        let item_index = NameIndex::for_item(item, position, &outline, builtins);
+        items.push(analyze_item(item, position, &item_index, &context));
+        types.extend(check_item_types(item, &context, &mut env));
+    }
+
+    analyze_file(program, &index, config, items, types)
+}
+
+/// Type errors of a program, before the lint levels are applied
+#[must_use]
+pub fn check_types(program: &Program, builtins: &Builtins) -> Vec<Diagnostic> {
+    types::TypeChecker::check(program, builtins)
+}
+
+/// Analyzes the top level expression at a position of its file, with the index of just that
+/// expression from [`NameIndex::for_item`]
+#[must_use]
+pub fn analyze_item(
+    item: &Spanned<Expression>,
+    position: usize,
+    index: &NameIndex,
+    context: &Context,
+) -> ItemDiagnostics {
+    let constants = fold::Constants::for_item(item, index, context.outline);
+
+    ItemDiagnostics {
+        errors: Analyzer::new(context, index, constants).analyze_item(item, position),
+        lints: lints::lint(item, index),
+    }
+}
+
+/// Type errors of a top level expression, the types of the globals it changes are updated for
+/// the expressions after it
+#[must_use]
+pub fn check_item_types(
+    item: &Spanned<Expression>,
+    context: &Context,
+    env: &mut TypeEnv,
+) -> Vec<Diagnostic> {
+    types::TypeChecker::check_item(item, &context.builtins, env)
+}
+
+/// Diagnostics of a file from the ones of each of its top level expressions, with the lint
+/// levels of a project config applied
+#[must_use]
+pub fn analyze_file(
+    program: &Program,
+    index: &NameIndex,
+    config: &Config,
+    items: Vec<ItemDiagnostics>,
+    types: Vec<Diagnostic>,
+) -> Diagnoses {
+    let mut diagnostics = vec![];
+    let mut lints = vec![];
+    for item in items {
+        diagnostics.extend(item.errors);
+        lints.extend(item.lints);
+    }
+
+    diagnostics.extend(check_initialized(program, index));
+    diagnostics.extend(types);
+    diagnostics.extend(lints);
+    diagnostics.extend(lints::unused_functions(program, index));
+    fixes::suggest(program, &mut diagnostics);
+
+    Diagnoses {
+        diagnostics: levels::apply(program, &config.lints, diagnostics),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use crate::{config::Config, runtime::value::Type};
+
+    use super::{
+        ast, outline::Outline, resolve::NameIndex, Builtins, Context, DiagnosticKind, Operator,
+        LINTS,
+    };
+
+    /// Diagnostics of the analyzer, without types and lints
+    fn analyze(source: &str) -> Vec<DiagnosticKind> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let builtins = Builtins::std();
+        let index = NameIndex::new(&program);
+        let outline = Outline::new(&program, &index);
+        let context = Context::new(&outline, &builtins);
+
+        program
+            .expressions()
+            .iter()
+            .enumerate()
+            .flat_map(|(position, item)| {
+                let index = NameIndex::for_item(item, position, &outline, &builtins);
+                super::analyze_item(item, position, &index, &context).errors
+            })
+            .chain(super::check_initialized(&program, &index))
+            .map(|diagnostic| diagnostic.kind)
+            .collect()
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            analyze("let limit = 4 func main() { if limit > 2 { } [1][limit - 5] [1][limit / 8] }"),
+            vec![
+                DiagnosticKind::ConditionIsConstant(true),
+                DiagnosticKind::NegativeArrayIndex,
+                DiagnosticKind::FractionalArrayIndex,
+            ]
+        );
+        assert_eq!(
+            analyze("func main() { let running = true while running { print(1) } }"),
+            vec![
+                DiagnosticKind::ConditionIsConstant(true),
+                DiagnosticKind::InfiniteLoop,
+            ]
+        );
+        assert_eq!(
+            analyze("func main() { let running = true while running { running = false } }"),
+            vec![]
+        );
+        assert_eq!(
+            analyze("func main() { while true { print(1) wait(2) } }"),
+            vec![],
+            "loops that yield are meant to run forever"
+        );
+        assert_eq!(
+            analyze("func wait(n) { } func main() { while true { wait(2) } }"),
+            vec![
+                DiagnosticKind::ConditionIsConstant(true),
+                DiagnosticKind::InfiniteLoop,
+            ],
+            "only the native yields"
+        );
+    }
+
+    #[test]
+    fn hoisting() {
+        // functions only run after the whole top level is initialized
+        assert_eq!(
+            analyze("func even(n) => odd(n) let limit = max func odd(n) => even(n) let max = 3"),
+            vec![DiagnosticKind::UsedBeforeDeclaration("max".into())]
+        );
+        assert_eq!(analyze("func main() => later let later = 1"), vec![]);
+        assert_eq!(
+            analyze("func f() => g() func g() => later let x = f() let later = 1"),
+            vec![DiagnosticKind::UsedBeforeDeclaration("later".into())],
+            "functions called by the top level run before it is initialized"
+        );
+        assert_eq!(
+            analyze("let early = 1 func f() => early let x = f() func g(later) => later let y = g(2) let later = 1"),
+            vec![]
+        );
+        assert_eq!(
+            analyze("func main() => missing"),
+            vec![DiagnosticKind::UnknownVariable("missing".into())]
+        );
+    }
+
+    #[test]
+    fn builtins() {
+        let source = "func main() { let log = print log(1) spin(2, \"a\") }";
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let kinds = |builtins: &Builtins| {
+            super::analyze_with_builtins(&program, &Config::default(), builtins)
+                .diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.kind)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kinds(&Builtins::std()),
+            vec![DiagnosticKind::UnknownVariable("spin".into())]
+        );
+
+        let mut builtins = Builtins::std();
+        builtins.insert(
+            "spin".into(),
+            Type::Function {
+                params: vec![Type::Number, Type::Number],
+                returns: Box::new(Type::Number),
+            },
+        );
+        assert_eq!(
+            kinds(&builtins),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::Number,
+                found: Type::String,
+            }]
+        );
+    }
+
+    #[test]
+    fn codes() {
+        let kinds = [
+            DiagnosticKind::UnknownVariable("a".into()),
+            DiagnosticKind::InvalidTopLevel,
+            DiagnosticKind::InvalidLintDirective,
+        ];
+        assert_eq!(
+            kinds.iter().map(DiagnosticKind::code).collect::<Vec<_>>(),
+            vec!["M0001", "M0003", "M0028"]
+        );
+
+        // codes follow the order of the lint names, which have to stay unique
+        let lints = LINTS.iter().copied().collect::<HashSet<_>>();
+        assert_eq!(lints.len(), LINTS.len());
+    }
+
+    #[test]
+    fn lints() {
+        // adding a kind fails to compile here until it is added to the list below
+        let listed = |kind: &DiagnosticKind| match kind {
+            DiagnosticKind::UnknownVariable(..)
+            | DiagnosticKind::UsedBeforeDeclaration(..)
+            | DiagnosticKind::InvalidTopLevel
+            | DiagnosticKind::InfiniteLoop
+            | DiagnosticKind::UnchangedLoopCondition
+            | DiagnosticKind::UnreachableCode
+            | DiagnosticKind::ConditionIsConstant(..)
+            | DiagnosticKind::NegativeArrayIndex
+            | DiagnosticKind::FractionalArrayIndex
+            | DiagnosticKind::IgnoredOperation
+            | DiagnosticKind::EmptyBlock
+            | DiagnosticKind::DuplicateArgumentName(..)
+            | DiagnosticKind::InvalidInlineExpression
+            | DiagnosticKind::MismatchedType { .. }
+            | DiagnosticKind::UnknownType(..)
+            | DiagnosticKind::InvalidOperands { .. }
+            | DiagnosticKind::InvalidOperand { .. }
+            | DiagnosticKind::NotCallable(..)
+            | DiagnosticKind::WrongArgumentCount { .. }
+            | DiagnosticKind::NotIndexable(..)
+            | DiagnosticKind::NoProperties(..)
+            | DiagnosticKind::UnusedVariable(..)
+            | DiagnosticKind::UnusedParameter(..)
+            | DiagnosticKind::WriteOnlyVariable(..)
+            | DiagnosticKind::ShadowedVariable(..)
+            | DiagnosticKind::UnusedFunction(..)
+            | DiagnosticKind::UnknownLint(..)
+            | DiagnosticKind::InvalidLintDirective
+            | DiagnosticKind::UnknownExport { .. } => true,
+        };
+
+        let name = || "a".into();
+        let kinds = [
+            DiagnosticKind::UnknownVariable(name()),
+            DiagnosticKind::UsedBeforeDeclaration(name()),
+            DiagnosticKind::InvalidTopLevel,
+            DiagnosticKind::InfiniteLoop,
+            DiagnosticKind::UnchangedLoopCondition,
+            DiagnosticKind::UnreachableCode,
+            DiagnosticKind::ConditionIsConstant(true),
+            DiagnosticKind::NegativeArrayIndex,
+            DiagnosticKind::FractionalArrayIndex,
+            DiagnosticKind::IgnoredOperation,
+            DiagnosticKind::EmptyBlock,
+            DiagnosticKind::DuplicateArgumentName(name()),
+            DiagnosticKind::InvalidInlineExpression,
+            DiagnosticKind::MismatchedType {
+                expected: Type::Number,
+                found: Type::Bool,
+            },
+            DiagnosticKind::UnknownType(ast::Type::Named(name())),
+            DiagnosticKind::InvalidOperands {
+                operator: Operator::Add,
+                lhs: Type::Bool,
+                rhs: Type::Bool,
+            },
+            DiagnosticKind::InvalidOperand {
+                operator: Operator::Sub,
+                rhs: Type::Bool,
+            },
+            DiagnosticKind::NotCallable(Type::Number),
+            DiagnosticKind::WrongArgumentCount {
+                expected: 1,
+                found: 2,
+            },
+            DiagnosticKind::NotIndexable(Type::Number),
+            DiagnosticKind::NoProperties(Type::Number),
+            DiagnosticKind::UnusedVariable(name()),
+            DiagnosticKind::UnusedParameter(name()),
+            DiagnosticKind::WriteOnlyVariable(name()),
+            DiagnosticKind::ShadowedVariable(name()),
+            DiagnosticKind::UnusedFunction(name()),
+            DiagnosticKind::UnknownLint(name()),
+            DiagnosticKind::InvalidLintDirective,
+            DiagnosticKind::UnknownExport {
+                module: name(),
+                name: name(),
+            },
+        ];
+        assert!(kinds.iter().all(listed));
+
+        // every kind has its own lint and code, and `LINTS` names them all in the same order
+        assert_eq!(
+            kinds.iter().map(DiagnosticKind::lint).collect::<Vec<_>>(),
+            LINTS
+        );
+        let codes = kinds
+            .iter()
+            .map(DiagnosticKind::code)
+            .collect::<HashSet<_>>();
+        assert_eq!(codes.len(), kinds.len());
+    }
+
+    #[test]
+    fn serialize() {
+        let source = "let limit = max let max = 3";
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        let diagnostics = super::analyze(&program).diagnostics;
+
+        let span = |range: std::ops::Range<usize>| json!({ "src": "[test]", "range": range });
+        assert_eq!(
+            serde_json::to_value(&diagnostics).unwrap(),
+            json!([{
+                "code": "M0002",
+                "lint": "used_before_declaration",
+                "severity": "error",
+                "message": "`max` is used before it is declared",
+                "span": span(12..15),
+                "related": [{ "span": span(20..23), "message": "declared here" }],
+                "notes": [],
+                "fix": null,
+            }])
+        );
+    }
 }
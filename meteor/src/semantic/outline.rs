@@ -0,0 +1,136 @@
+use chumsky::span::Span as _;
+
+use crate::{
+    module,
+    parser::{
+        ast::{Expression, Program},
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::value::Type,
+};
+
+use super::{env::Builtins, fold::Constants, resolve::NameIndex};
+
+/// Top level declaration of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: Identifier,
+
+    /// Span of the declaration, which starts with the name
+    pub span: Span,
+
+    /// Index of the top level expression declaring it
+    pub item: usize,
+
+    /// Whether it is assigned to anywhere in the file
+    pub assigned: bool,
+
+    /// Literal of the value it always holds, see [`Constants`]
+    pub constant: Option<Expression>,
+
+    /// Type of a function declared without an annotation, code before the declaration can
+    /// already call it
+    pub signature: Option<Type>,
+}
+
+/// What the top level of a file declares.
+///
+/// Each top level expression is analyzed on its own against the outline of its file, so when
+/// an edit leaves the outline the same only the expressions that changed are analyzed again.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outline {
+    /// Namespaces of the imported modules, with the directive importing each
+    imports: Vec<Spanned<Identifier>>,
+
+    globals: Vec<Global>,
+}
+
+impl Outline {
+    #[must_use]
+    pub fn new(program: &Program, index: &NameIndex) -> Self {
+        let imports = program
+            .directives()
+            .iter()
+            .filter(|(directive, _)| module::is_import(directive))
+            .filter_map(|(directive, span)| {
+                let namespace = module::import_path(directive).and_then(module::namespace)?;
+                Some((namespace, span.clone()))
+            })
+            .collect();
+
+        let constants = Constants::new(program, index);
+        let globals = program
+            .expressions()
+            .iter()
+            .enumerate()
+            .filter_map(|(item, expr)| {
+                let Expression::Let { meta, init } = &expr.0 else {
+                    return None;
+                };
+
+                let signature = match (&init.0, meta.0.data_type()) {
+                    (Expression::Func(function), None) => Some(Type::signature(function)),
+                    _ => None,
+                };
+
+                let definition = index.definition_at(&meta.1);
+
+                Some(Global {
+                    name: meta.0.name().clone(),
+                    span: meta.1.clone(),
+                    item,
+                    assigned: definition
+                        .is_some_and(|id| index.references_to(id).any(|reference| reference.write)),
+                    constant: definition.and_then(|id| constants.literal(id)),
+                    signature,
+                })
+            })
+            .collect();
+
+        Self { imports, globals }
+    }
+
+    /// Same outline with the span of each global replaced, along with the index of the
+    /// expression declaring it
+    #[must_use]
+    pub fn map_spans(mut self, f: impl Fn(usize, &Span) -> Span) -> Self {
+        for global in &mut self.globals {
+            global.span = f(global.item, &global.span);
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn imports(&self) -> &[Spanned<Identifier>] {
+        &self.imports
+    }
+
+    #[must_use]
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    /// Global declared at a span, which starts with its name
+    #[must_use]
+    pub fn global_at(&self, span: &Span) -> Option<&Global> {
+        self.globals.iter().find(|global| {
+            global.span.src() == span.src() && global.span.range().start == span.range().start
+        })
+    }
+
+    /// Builtins the file can use, its globals hide the ones of the same name everywhere
+    #[must_use]
+    pub fn visible(&self, builtins: &Builtins) -> Builtins {
+        builtins.without(self.globals.iter().map(|global| &global.name))
+    }
+}
+
+impl Global {
+    /// Span of just the name
+    #[must_use]
+    pub fn name_span(&self) -> Span {
+        let start = self.span.range().start;
+        Span::new(self.span.src(), start..start + self.name.len())
+    }
+}
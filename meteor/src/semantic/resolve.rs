@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use chumsky::span::Span as _;
+
+use crate::{
+    module,
+    parser::{
+        ast::{Expression, Program, VariableMeta},
+        operator::Operator,
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+};
+
+use super::{env::Builtins, outline::Outline};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefinitionId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefinitionKind {
+    /// `let` or `func` at the top level
+    Global,
+
+    /// `let` or `func` inside of a block
+    Local,
+
+    Parameter,
+
+    /// Namespace an imported module is bound to
+    Import,
+
+    /// Provided by the runtime
+    Builtin,
+}
+
+/// Declaration of a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: Identifier,
+    pub kind: DefinitionKind,
+
+    /// Span of the declared name, the whole directive for imports and `None` for builtins
+    pub span: Option<Span>,
+
+    /// Definition of the same name in an enclosing scope that this one hides
+    pub shadows: Option<DefinitionId>,
+}
+
+/// Use of a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: Identifier,
+    pub span: Span,
+
+    /// `None` if nothing of that name is in scope
+    pub definition: Option<DefinitionId>,
+
+    /// Whether the variable is assigned to rather than read
+    pub write: bool,
+}
+
+/// Links every use of a name in a program to its declaration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameIndex {
+    definitions: Vec<Definition>,
+
+    /// Ordered by position
+    references: Vec<Reference>,
+}
+
+impl NameIndex {
+    /// Index of a program running with the standard builtins
+    #[must_use]
+    pub fn new(program: &Program) -> Self {
+        Self::with_builtins(program, &Builtins::std())
+    }
+
+    #[must_use]
+    pub fn with_builtins(program: &Program, builtins: &Builtins) -> Self {
+        Resolver::resolve(program, builtins)
+    }
+
+    /// Index of the top level expression at a position in a file, every other global of the
+    /// file comes from its outline
+    #[must_use]
+    pub fn for_item(
+        item: &Spanned<Expression>,
+        position: usize,
+        outline: &Outline,
+        builtins: &Builtins,
+    ) -> Self {
+        Resolver::resolve_item(item, position, outline, builtins)
+    }
+
+    pub fn definitions(&self) -> impl Iterator<Item = (DefinitionId, &Definition)> {
+        self.definitions
+            .iter()
+            .enumerate()
+            .map(|(i, definition)| (DefinitionId(i), definition))
+    }
+
+    #[must_use]
+    pub fn definition(&self, id: DefinitionId) -> &Definition {
+        &self.definitions[id.0]
+    }
+
+    #[must_use]
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    pub fn references_to(&self, id: DefinitionId) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.definition == Some(id))
+    }
+
+    /// Definition of the variable whose declaration starts at a span
+    #[must_use]
+    pub fn definition_at(&self, span: &Span) -> Option<DefinitionId> {
+        self.definitions().find_map(|(id, definition)| {
+            let name = definition.span.as_ref()?;
+            (name.src() == span.src() && name.range().start == span.range().start).then_some(id)
+        })
+    }
+
+    /// Reference made by the identifier at a span
+    #[must_use]
+    pub fn reference_at(&self, span: &Span) -> Option<&Reference> {
+        let start = self
+            .references
+            .partition_point(|reference| reference.span.range().start < span.range().start);
+
+        self.references[start..]
+            .iter()
+            .take_while(|reference| reference.span.range().start == span.range().start)
+            .find(|reference| reference.span == *span)
+    }
+
+    /// References to names that are not declared
+    pub fn unresolved(&self) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(|reference| reference.definition.is_none())
+    }
+
+    /// Definition whose name or one of whose references touches a byte offset
+    #[must_use]
+    pub fn at(&self, offset: usize) -> Option<DefinitionId> {
+        let touches = |span: &Span| span.range().contains(&offset) || span.range().end == offset;
+
+        self.references
+            .iter()
+            .find(|reference| touches(&reference.span))
+            .and_then(|reference| reference.definition)
+            .or_else(|| {
+                self.definitions().find_map(|(id, definition)| {
+                    match (definition.kind, &definition.span) {
+                        (DefinitionKind::Import | DefinitionKind::Builtin, _) | (_, None) => None,
+                        (_, Some(span)) => touches(span).then_some(id),
+                    }
+                })
+            })
+    }
+}
+
+/// Walks a program keeping track of the names in scope, the same way the runtime does
+struct Resolver {
+    index: NameIndex,
+    scopes: Vec<HashMap<Identifier, DefinitionId>>,
+
+    /// Names provided by the runtime and the definitions of the ones referenced so far
+    available: Builtins,
+    builtins: HashMap<Identifier, DefinitionId>,
+
+    /// How many function bodies the walk is inside of
+    functions: usize,
+
+    /// Unresolved references inside of functions, which may use globals declared after them
+    forward: Vec<usize>,
+}
+
+impl Resolver {
+    fn new(available: Builtins) -> Self {
+        Self {
+            index: NameIndex::default(),
+            scopes: vec![HashMap::new()],
+            available,
+            builtins: HashMap::new(),
+            functions: 0,
+            forward: vec![],
+        }
+    }
+
+    fn resolve(program: &Program, builtins: &Builtins) -> NameIndex {
+        let mut resolver = Self::new(builtins.visible(program));
+
+        for (directive, span) in program.directives() {
+            if !module::is_import(directive) {
+                continue;
+            }
+
+            if let Some(namespace) = module::import_path(directive).and_then(module::namespace) {
+                resolver.define(namespace, DefinitionKind::Import, Some(span.clone()));
+            }
+        }
+
+        for expr in program.expressions() {
+            resolver.walk(expr);
+        }
+
+        resolver.finish()
+    }
+
+    fn resolve_item(
+        item: &Spanned<Expression>,
+        position: usize,
+        outline: &Outline,
+        builtins: &Builtins,
+    ) -> NameIndex {
+        let mut resolver = Self::new(outline.visible(builtins));
+
+        for (namespace, span) in outline.imports() {
+            resolver.define(
+                namespace.clone(),
+                DefinitionKind::Import,
+                Some(span.clone()),
+            );
+        }
+
+        let (before, after): (Vec<_>, Vec<_>) = outline
+            .globals()
+            .iter()
+            .filter(|global| global.item != position)
+            .partition(|global| global.item < position);
+
+        for global in before {
+            resolver.define(
+                global.name.clone(),
+                DefinitionKind::Global,
+                Some(global.name_span()),
+            );
+        }
+
+        resolver.walk(item);
+
+        // only functions see the globals declared after the item
+        for global in after {
+            resolver.define(
+                global.name.clone(),
+                DefinitionKind::Global,
+                Some(global.name_span()),
+            );
+        }
+
+        resolver.finish()
+    }
+
+    fn finish(mut self) -> NameIndex {
+        // functions only run once the whole top level is initialized
+        for i in self.forward {
+            let reference = &mut self.index.references[i];
+            reference.definition = self.scopes[0].get(&reference.name).copied();
+        }
+
+        let mut index = self.index;
+        index
+            .references
+            .sort_by_key(|reference| reference.span.range().start);
+
+        index
+    }
+
+    fn define(&mut self, name: Identifier, kind: DefinitionKind, span: Option<Span>) {
+        let id = DefinitionId(self.index.definitions.len());
+        let (scope, outer) = self.scopes.split_last().expect("Mismatch pop/push");
+        let shadows = outer
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .copied()
+            .filter(|_| !scope.contains_key(&name));
+
+        self.index.definitions.push(Definition {
+            name: name.clone(),
+            kind,
+            span,
+            shadows,
+        });
+
+        self.scopes
+            .last_mut()
+            .expect("Mismatch pop/push")
+            .insert(name, id);
+    }
+
+    fn define_variable(&mut self, meta: &Spanned<VariableMeta>, kind: DefinitionKind) {
+        let (meta, span) = meta;
+        let start = span.range().start;
+        let name = Span::new(span.src(), start..start + meta.name().len());
+
+        self.define(meta.name().clone(), kind, Some(name));
+    }
+
+    fn lookup(&mut self, name: &Identifier) -> Option<DefinitionId> {
+        if let Some(id) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Some(*id);
+        }
+
+        if !self.available.contains(name) {
+            return None;
+        }
+
+        let definitions = &mut self.index.definitions;
+        let id = *self.builtins.entry(name.clone()).or_insert_with(|| {
+            definitions.push(Definition {
+                name: name.clone(),
+                kind: DefinitionKind::Builtin,
+                span: None,
+                shadows: None,
+            });
+            DefinitionId(definitions.len() - 1)
+        });
+
+        Some(id)
+    }
+
+    fn reference(&mut self, name: &Identifier, span: &Span, write: bool) {
+        let definition = self.lookup(name);
+        if definition.is_none() && self.functions > 0 {
+            self.forward.push(self.index.references.len());
+        }
+
+        self.index.references.push(Reference {
+            name: name.clone(),
+            span: span.clone(),
+            definition,
+            write,
+        });
+    }
+
+    fn walk(&mut self, expr: &Spanned<Expression>) {
+        match &expr.0 {
+            Expression::Ident(ident) => self.reference(ident, &expr.1, false),
+
+            Expression::Let { meta, init } => {
+                let kind = if self.scopes.len() == 1 {
+                    DefinitionKind::Global
+                } else {
+                    DefinitionKind::Local
+                };
+
+                // functions capture their scope, so they can see their own name
+                if let Expression::Func(..) = init.0 {
+                    self.define_variable(meta, kind);
+                    self.walk(init);
+                } else {
+                    self.walk(init);
+                    self.define_variable(meta, kind);
+                }
+            }
+
+            Expression::Func(function) => {
+                self.scopes.push(HashMap::new());
+                self.functions += 1;
+                for param in function.arguments() {
+                    self.define_variable(param, DefinitionKind::Parameter);
+                }
+                self.walk(function.body());
+                self.functions -= 1;
+                self.scopes.pop();
+            }
+
+            Expression::Block(exprs) => {
+                self.scopes.push(HashMap::new());
+                for expr in exprs {
+                    self.walk(expr);
+                }
+                self.scopes.pop();
+            }
+
+            Expression::BinaryOp {
+                lhs,
+                operator: Operator::Assign,
+                rhs,
+            } => {
+                self.walk(rhs);
+                match &lhs.0 {
+                    Expression::Ident(ident) => self.reference(ident, &lhs.1, true),
+                    _ => self.walk(lhs),
+                }
+            }
+
+            Expression::Array(exprs) => {
+                for expr in exprs {
+                    self.walk(expr);
+                }
+            }
+
+            Expression::Dictionary(pairs) => {
+                for (_, value) in pairs {
+                    self.walk(value);
+                }
+            }
+
+            Expression::If {
+                condition,
+                then,
+                or_else,
+            } => {
+                self.walk(condition);
+                self.walk(then);
+                self.walk(or_else);
+            }
+
+            Expression::While { condition, then } => {
+                self.walk(condition);
+                self.walk(then);
+            }
+
+            Expression::PropertyAccess { lhs, .. } => self.walk(lhs),
+
+            Expression::ArrayIndex { lhs, index } => {
+                self.walk(lhs);
+                self.walk(index);
+            }
+
+            Expression::BinaryOp { lhs, rhs, .. } => {
+                self.walk(lhs);
+                self.walk(rhs);
+            }
+
+            Expression::UnaryOp { rhs, .. } => self.walk(rhs),
+
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.walk(function);
+                for arg in arguments {
+                    self.walk(arg);
+                }
+            }
+
+            Expression::Error
+            | Expression::Nil
+            | Expression::String(_)
+            | Expression::Bool(_)
+            | Expression::Number(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::src::SourceId;
+
+    use super::{DefinitionKind, NameIndex};
+
+    fn index(source: &str) -> NameIndex {
+        NameIndex::new(&crate::parse(SourceId::empty(), source).unwrap())
+    }
+
+    /// Name and source text of the definition each reference resolves to
+    fn links<'a>(index: &'a NameIndex, source: &'a str) -> Vec<(&'a str, Option<&'a str>)> {
+        index
+            .references()
+            .iter()
+            .map(|reference| {
+                let definition = reference.definition.and_then(|id| {
+                    let span = index.definition(id).span.as_ref()?;
+                    Some(&source[span.range()])
+                });
+                (reference.name.name(), definition)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scopes() {
+        let source = "let a = 1 func f(b) { let a = b a } let c = a";
+        let index = index(source);
+
+        let kinds = index
+            .definitions()
+            .map(|(_, definition)| (definition.name.name(), definition.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("a", DefinitionKind::Global),
+                ("f", DefinitionKind::Global),
+                ("b", DefinitionKind::Parameter),
+                ("a", DefinitionKind::Local),
+                ("c", DefinitionKind::Global),
+            ]
+        );
+
+        // every reference links to the declaration in scope
+        let positions = index
+            .references()
+            .iter()
+            .map(|reference| {
+                let id = reference.definition.unwrap();
+                index.definition(id).span.as_ref().unwrap().range().start
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![17, 26, 4]);
+    }
+
+    #[test]
+    fn declarations() {
+        let source = "func f(x: number) => f(x) let g = func() => g let y = y";
+        let index = index(source);
+
+        assert_eq!(
+            links(&index, source),
+            vec![
+                ("f", Some("f")),
+                ("x", Some("x")),
+                ("g", Some("g")),
+                // the initializer runs before the variable exists
+                ("y", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_and_builtins() {
+        let index = index("let a = 1 func main() { a = a + 1 print(a) print(b) }");
+
+        let a = index.references()[0].definition.unwrap();
+        let writes = index
+            .references_to(a)
+            .map(|reference| reference.write)
+            .collect::<Vec<_>>();
+        assert_eq!(writes, vec![true, false, false]);
+
+        let builtins = index
+            .references()
+            .iter()
+            .filter_map(|reference| reference.definition)
+            .filter(|id| index.definition(*id).kind == DefinitionKind::Builtin)
+            .collect::<Vec<_>>();
+        assert_eq!(builtins.len(), 2);
+        assert_eq!(builtins[0], builtins[1], "builtins are defined once");
+
+        let unresolved = index
+            .unresolved()
+            .map(|reference| reference.name.name())
+            .collect::<Vec<_>>();
+        assert_eq!(unresolved, vec!["b"]);
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "func even(n) => odd(n) let x = y let y = 1 func odd(n) => even(n)";
+        let index = index(source);
+
+        assert_eq!(
+            links(&index, source),
+            vec![
+                ("odd", Some("odd")),
+                ("n", Some("n")),
+                ("y", None),
+                ("even", Some("even")),
+                ("n", Some("n")),
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_by_offset() {
+        let source = "let abc = 1 let d = abc + abc";
+        let index = index(source);
+
+        let definition = index.at(5).unwrap();
+        assert_eq!(index.at(26), Some(definition));
+        assert_eq!(index.at(29), Some(definition), "the end of a name counts");
+        assert_eq!(index.references_to(definition).count(), 2);
+        assert_eq!(index.at(24), None);
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::HashMap;
+
+use crate::{
+    parser::{
+        ast::{self, Expression, Program},
+        operator::Operator,
+        span::{Span, Spanned},
+        symbol::Identifier,
+    },
+    runtime::value::Type,
+};
+
+use super::{outline::Outline, Builtins, Diagnostic, DiagnosticKind, Severity};
+
+/// What is known about the type of a variable
+#[derive(Debug, Clone, PartialEq)]
+struct Binding {
+    /// Type the variable was annotated with, every assignment has to match it
+    declared: Option<Type>,
+
+    /// Type of the value it holds, widened to `any` when an unannotated variable is reassigned
+    current: Type,
+}
+
+impl Binding {
+    fn get(&self) -> &Type {
+        self.declared.as_ref().unwrap_or(&self.current)
+    }
+}
+
+/// Types of the globals of a file as of some point of its top level
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeEnv {
+    globals: HashMap<Identifier, Binding>,
+}
+
+impl TypeEnv {
+    /// Globals before the top level runs, only the functions declared without an annotation are
+    /// known since code before them can already call them
+    #[must_use]
+    pub fn new(outline: &Outline) -> Self {
+        let globals = outline
+            .globals()
+            .iter()
+            .filter_map(|global| {
+                let binding = Binding {
+                    declared: None,
+                    current: global.signature.clone()?,
+                };
+                Some((global.name.clone(), binding))
+            })
+            .collect();
+
+        Self { globals }
+    }
+}
+
+/// Infers the type of every expression and reports the ones which cannot work at run time.
+///
+/// Typing is gradual, whatever cannot be inferred is `any` and is never reported.
+pub(super) struct TypeChecker {
+    scopes: Vec<HashMap<Identifier, Binding>>,
+    diagnoses: Vec<Diagnostic>,
+}
+
+impl TypeChecker {
+    fn new(builtins: &Builtins, env: TypeEnv) -> Self {
+        let builtins = builtins
+            .iter()
+            .map(|(name, signature)| {
+                let binding = Binding {
+                    declared: None,
+                    current: signature.clone(),
+                };
+                (name.clone(), binding)
+            })
+            .collect();
+
+        Self {
+            scopes: vec![builtins, env.globals],
+            diagnoses: vec![],
+        }
+    }
+
+    pub(super) fn check(program: &Program, builtins: &Builtins) -> Vec<Diagnostic> {
+        let mut checker = Self::new(&builtins.visible(program), TypeEnv::default());
+
+        // top level functions can call the ones declared after them
+        for expr in program.expressions() {
+            checker.declare(expr);
+        }
+
+        for expr in program.expressions() {
+            checker.infer(expr);
+        }
+
+        checker.diagnoses
+    }
+
+    /// Checks a single top level expression, updating the globals it changes. The builtins are
+    /// the ones visible to the file.
+    pub(super) fn check_item(
+        item: &Spanned<Expression>,
+        builtins: &Builtins,
+        env: &mut TypeEnv,
+    ) -> Vec<Diagnostic> {
+        let mut checker = Self::new(builtins, std::mem::take(env));
+        checker.infer(item);
+
+        env.globals = checker.scopes.pop().expect("Mismatch pop/push");
+        checker.diagnoses
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop().expect("Mismatch pop/push");
+    }
+
+    fn bind(&mut self, name: Identifier, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .expect("Mismatch pop/push")
+            .insert(name, binding);
+    }
+
+    /// Binds a variable initialized with an unannotated function to its signature
+    fn declare(&mut self, expr: &Spanned<Expression>) {
+        let Expression::Let { meta, init } = &expr.0 else {
+            return;
+        };
+
+        if let (Expression::Func(function), None) = (&init.0, meta.0.data_type()) {
+            let current = Type::signature(function);
+            self.bind(
+                meta.0.name().clone(),
+                Binding {
+                    declared: None,
+                    current,
+                },
+            );
+        }
+    }
+
+    fn lookup(&mut self, name: &Identifier) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn diagnose(&mut self, kind: DiagnosticKind, span: &Span) {
+        self.diagnoses
+            .push(Diagnostic::new(kind, Severity::Error, span.clone()));
+    }
+
+    fn resolve(&mut self, ty: &Spanned<ast::Type>) -> Type {
+        Type::resolve(&ty.0).unwrap_or_else(|| {
+            self.diagnose(DiagnosticKind::UnknownType(ty.0.clone()), &ty.1);
+            Type::Any
+        })
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, span: &Span) {
+        if !assignable(found, expected) {
+            self.diagnose(
+                DiagnosticKind::MismatchedType {
+                    expected: expected.clone(),
+                    found: found.clone(),
+                },
+                span,
+            );
+        }
+    }
+
+    fn infer_block(&mut self, exprs: &[Spanned<Expression>]) -> Type {
+        self.push_scope();
+        let ty = exprs.iter().fold(Type::Nil, |_, expr| self.infer(expr));
+        self.pop_scope();
+
+        ty
+    }
+
+    fn infer(&mut self, expr: &Spanned<Expression>) -> Type {
+        match &expr.0 {
+            Expression::Error => Type::Any,
+            Expression::Nil => Type::Nil,
+            Expression::String(_) => Type::String,
+            Expression::Bool(_) => Type::Bool,
+            Expression::Number(_) => Type::Number,
+
+            // unknown variables are reported by the analyzer
+            Expression::Ident(ident) => self
+                .lookup(ident)
+                .map_or(Type::Any, |binding| binding.get().clone()),
+
+            Expression::Array(items) => {
+                let item = items
+                    .iter()
+                    .map(|item| self.infer(item))
+                    .reduce(|a, b| join(&a, &b))
+                    .unwrap_or(Type::Any);
+
+                Type::Array(Box::new(item))
+            }
+
+            Expression::Dictionary(pairs) => {
+                let value = pairs
+                    .iter()
+                    .map(|(_, value)| self.infer(value))
+                    .reduce(|a, b| join(&a, &b))
+                    .unwrap_or(Type::Any);
+
+                Type::Dictionary {
+                    key: Box::new(Type::String),
+                    value: Box::new(value),
+                }
+            }
+
+            Expression::Func(function) => self.infer_function(function),
+
+            Expression::Let { meta, init } => {
+                // named functions can call themselves
+                self.declare(expr);
+
+                let found = self.infer(init);
+                let declared = meta.0.data_type().map(|ty| self.resolve(ty));
+
+                if let Some(declared) = &declared {
+                    self.expect(declared, &found, &init.1);
+                }
+
+                // `nil` is a placeholder for a value assigned later, so it does not fix the type
+                let current = match (&declared, found) {
+                    (Some(declared), _) => declared.clone(),
+                    (None, Type::Nil) => Type::Any,
+                    (None, found) => found,
+                };
+
+                self.bind(meta.0.name().clone(), Binding { declared, current });
+                Type::Nil
+            }
+
+            Expression::Block(exprs) => self.infer_block(exprs),
+
+            Expression::If {
+                condition,
+                then,
+                or_else,
+            } => {
+                self.infer(condition);
+                let then = self.infer(then);
+                let or_else = self.infer(or_else);
+
+                join(&then, &or_else)
+            }
+
+            Expression::While { condition, then } => {
+                self.infer(condition);
+                self.infer(then);
+                Type::Nil
+            }
+
+            Expression::PropertyAccess { lhs, .. } => {
+                // missing keys are nil, so the value type of a dictionary is not enough
+                match self.infer(lhs) {
+                    Type::Any | Type::Dictionary { .. } => {}
+                    ty => self.diagnose(DiagnosticKind::NoProperties(ty), &lhs.1),
+                }
+                Type::Any
+            }
+
+            Expression::ArrayIndex { lhs, index } => {
+                let array = self.infer(lhs);
+                let index_ty = self.infer(index);
+                self.expect(&Type::Number, &index_ty, &index.1);
+
+                match array {
+                    Type::Array(item) => *item,
+                    Type::Any => Type::Any,
+                    ty => {
+                        self.diagnose(DiagnosticKind::NotIndexable(ty), &lhs.1);
+                        Type::Any
+                    }
+                }
+            }
+
+            Expression::BinaryOp {
+                lhs,
+                operator: Operator::Assign,
+                rhs,
+            } => {
+                let value = self.infer(rhs);
+                self.assign(lhs, &value, &rhs.1);
+                Type::Nil
+            }
+
+            Expression::BinaryOp { lhs, operator, rhs } => {
+                let lhs_ty = self.infer(lhs);
+                let rhs_ty = self.infer(rhs);
+                self.binary(*operator, lhs_ty, rhs_ty, &expr.1)
+            }
+
+            Expression::UnaryOp { operator, rhs } => {
+                let rhs_ty = self.infer(rhs);
+
+                match operator {
+                    Operator::Not => Type::Bool,
+                    _ if accepts(&Type::Number, &rhs_ty) => Type::Number,
+                    _ => {
+                        self.diagnose(
+                            DiagnosticKind::InvalidOperand {
+                                operator: *operator,
+                                rhs: rhs_ty,
+                            },
+                            &expr.1,
+                        );
+                        Type::Any
+                    }
+                }
+            }
+
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                let callee = self.infer(function);
+                let found = arguments
+                    .iter()
+                    .map(|arg| self.infer(arg))
+                    .collect::<Vec<_>>();
+
+                match callee {
+                    Type::Any => Type::Any,
+                    Type::Function { params, .. } if params.len() != found.len() => {
+                        self.diagnose(
+                            DiagnosticKind::WrongArgumentCount {
+                                expected: params.len(),
+                                found: found.len(),
+                            },
+                            &expr.1,
+                        );
+                        Type::Any
+                    }
+                    Type::Function { params, returns } => {
+                        for ((param, found), arg) in params.iter().zip(&found).zip(arguments) {
+                            self.expect(param, found, &arg.1);
+                        }
+                        *returns
+                    }
+                    ty => {
+                        self.diagnose(DiagnosticKind::NotCallable(ty), &function.1);
+                        Type::Any
+                    }
+                }
+            }
+        }
+    }
+
+    fn infer_function(&mut self, function: &ast::Function) -> Type {
+        self.push_scope();
+
+        let mut params = vec![];
+        for (param, _) in function.arguments() {
+            let declared = param.data_type().map(|ty| self.resolve(ty));
+            let current = declared.clone().unwrap_or_default();
+            params.push(current.clone());
+            self.bind(param.name().clone(), Binding { declared, current });
+        }
+
+        let body = function.body();
+        let found = self.infer(body);
+        self.pop_scope();
+
+        let returns = match function.returns() {
+            Some(returns) => {
+                let returns = self.resolve(returns);
+
+                // point at the value a block evaluates to
+                let span = match &body.0 {
+                    Expression::Block(exprs) => exprs.last().map_or(&body.1, |last| &last.1),
+                    _ => &body.1,
+                };
+                self.expect(&returns, &found, span);
+
+                returns
+            }
+            None => found,
+        };
+
+        Type::Function {
+            params,
+            returns: Box::new(returns),
+        }
+    }
+
+    fn binary(&mut self, operator: Operator, lhs: Type, rhs: Type, span: &Span) -> Type {
+        let operands: &[Type] = if operator == Operator::Add {
+            &[Type::Number, Type::String]
+        } else if operator.is_arithmetic() || operator.is_comparison() {
+            &[Type::Number]
+        } else if operator.is_logical() {
+            &[Type::Bool]
+        } else {
+            &[Type::Number, Type::Bool, Type::String]
+        };
+
+        let valid = operands
+            .iter()
+            .any(|ty| accepts(ty, &lhs) && accepts(ty, &rhs));
+
+        if !valid {
+            self.diagnose(DiagnosticKind::InvalidOperands { operator, lhs, rhs }, span);
+            return Type::Any;
+        }
+
+        if operator == Operator::Add && (lhs == Type::String || rhs == Type::String) {
+            Type::String
+        } else if operator.is_arithmetic() {
+            Type::Number
+        } else {
+            Type::Bool
+        }
+    }
+
+    /// Checks an assignment against the annotation of its target, or widens the target
+    fn assign(&mut self, target: &Spanned<Expression>, value: &Type, span: &Span) {
+        match &target.0 {
+            Expression::Ident(ident) => {
+                let Some(binding) = self.lookup(ident) else {
+                    return;
+                };
+
+                match binding.declared.clone() {
+                    Some(declared) => self.expect(&declared, value, span),
+                    None => binding.current = join(&binding.current, value),
+                }
+            }
+
+            Expression::ArrayIndex { lhs, index } => {
+                let array = self.infer(lhs);
+                let index_ty = self.infer(index);
+                self.expect(&Type::Number, &index_ty, &index.1);
+
+                match (&lhs.0, array) {
+                    (Expression::Ident(ident), Type::Array(item)) if !assignable(value, &item) => {
+                        // arrays only get an item type by inference, so an item of another type
+                        // changes what the array holds
+                        if let Some(binding) = self.lookup(ident) {
+                            if binding.declared.is_none() {
+                                binding.current = Type::array();
+                            }
+                        }
+                    }
+                    (_, Type::Array(_) | Type::Any) => {}
+                    (_, ty) => self.diagnose(DiagnosticKind::NotIndexable(ty), &lhs.1),
+                }
+            }
+
+            _ => {
+                self.infer(target);
+            }
+        }
+    }
+}
+
+/// Whether an operand of type `found` can be used where `expected` is required
+fn accepts(expected: &Type, found: &Type) -> bool {
+    found == expected || *found == Type::Any
+}
+
+/// Whether a value of type `found` can be stored where `expected` is declared
+fn assignable(found: &Type, expected: &Type) -> bool {
+    match (found, expected) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Array(found), Type::Array(expected))
+        | (
+            Type::Dictionary { value: found, .. },
+            Type::Dictionary {
+                value: expected, ..
+            },
+        ) => assignable(found, expected),
+        (
+            Type::Function { params, returns },
+            Type::Function {
+                params: expected_params,
+                returns: expected_returns,
+            },
+        ) => {
+            params.len() == expected_params.len()
+                && params
+                    .iter()
+                    .zip(expected_params)
+                    .all(|(param, expected)| assignable(expected, param))
+                && assignable(returns, expected_returns)
+        }
+        _ => found == expected,
+    }
+}
+
+/// Type holding values of both types
+fn join(a: &Type, b: &Type) -> Type {
+    match (a, b) {
+        _ if a == b => a.clone(),
+        (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(join(a, b))),
+        _ => Type::Any,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        parser::{operator::Operator, src::SourceId},
+        runtime::value::Type,
+        semantic::{Builtins, DiagnosticKind},
+    };
+
+    use super::TypeChecker;
+
+    fn check(source: &str) -> Vec<DiagnosticKind> {
+        let program = crate::parse(SourceId::empty(), source).unwrap();
+        TypeChecker::check(&program, &Builtins::std())
+            .into_iter()
+            .map(|diagnostic| diagnostic.kind)
+            .collect()
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(check("let a: number = 1 let b: str = \"b\""), vec![]);
+        assert_eq!(
+            check("let a: number = \"1\""),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::Number,
+                found: Type::String,
+            }]
+        );
+        assert_eq!(
+            check("let a: number = 1 let main = func() { a = true }"),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::Number,
+                found: Type::Bool,
+            }]
+        );
+        assert!(matches!(
+            check("let a: thing = 1")[..],
+            [DiagnosticKind::UnknownType(_)]
+        ));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(check("let a = 1 + 2 * 3 > 4 and not nil"), vec![]);
+        assert_eq!(
+            check("let a: str = \"a\" + \"b\" let b: bool = a == \"ab\""),
+            vec![]
+        );
+        assert_eq!(
+            check("let a = 1 let b = a + true"),
+            vec![DiagnosticKind::InvalidOperands {
+                operator: Operator::Add,
+                lhs: Type::Number,
+                rhs: Type::Bool,
+            }]
+        );
+        assert_eq!(
+            check("let a = -\"a\""),
+            vec![DiagnosticKind::InvalidOperand {
+                operator: Operator::Sub,
+                rhs: Type::String,
+            }]
+        );
+        assert_eq!(
+            check("let a = [1, 2] let b = a[0] == 2"),
+            vec![],
+            "items of arrays are inferred"
+        );
+    }
+
+    #[test]
+    fn gradual() {
+        // parameters without annotations can be anything
+        assert_eq!(
+            check("let f = func(a, b) { a + b a or b a[0] a.b a() }"),
+            vec![]
+        );
+
+        // reassigning an unannotated variable forgets its type
+        assert_eq!(
+            check("let a = 1 let f = func() { a = \"a\" a + 1 }"),
+            vec![]
+        );
+        assert_eq!(check("let a = nil let f = func() { a + 1 }"), vec![]);
+
+        assert_eq!(
+            check("let f = func(a: str) { a + 1 }"),
+            vec![DiagnosticKind::InvalidOperands {
+                operator: Operator::Add,
+                lhs: Type::String,
+                rhs: Type::Number,
+            }]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            check("func f(a: number) -> number => a * 2 let b: number = f(f(1))"),
+            vec![]
+        );
+        assert_eq!(
+            check("func a() => b(1) func b() => 1"),
+            vec![DiagnosticKind::WrongArgumentCount {
+                expected: 0,
+                found: 1
+            }]
+        );
+        assert_eq!(
+            check("func f(a: number) -> str { a }"),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::String,
+                found: Type::Number,
+            }]
+        );
+        assert_eq!(
+            check("func f(a: number, b) => a let c = f(1) let d = f(\"1\", 2)"),
+            vec![
+                DiagnosticKind::WrongArgumentCount {
+                    expected: 2,
+                    found: 1,
+                },
+                DiagnosticKind::MismatchedType {
+                    expected: Type::Number,
+                    found: Type::String,
+                },
+            ]
+        );
+
+        // recursion sees the signature, return types are inferred without annotation
+        assert_eq!(
+            check("func f(a: number) -> bool => f(a - 1) let g = func() => 1 let h = g() + 1"),
+            vec![]
+        );
+        assert_eq!(
+            check("let f: func(number) -> bool = func(a: str) => a"),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::Function {
+                    params: vec![Type::Number],
+                    returns: Box::new(Type::Bool),
+                },
+                found: Type::Function {
+                    params: vec![Type::String],
+                    returns: Box::new(Type::String),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn access() {
+        assert_eq!(
+            check("let a = 1 let f = func() { a() a[0] a.b }"),
+            vec![
+                DiagnosticKind::NotCallable(Type::Number),
+                DiagnosticKind::NotIndexable(Type::Number),
+                DiagnosticKind::NoProperties(Type::Number),
+            ]
+        );
+        assert_eq!(
+            check("let a = [1] let f = func() { a[\"0\"] }"),
+            vec![DiagnosticKind::MismatchedType {
+                expected: Type::Number,
+                found: Type::String,
+            }]
+        );
+    }
+}